@@ -0,0 +1,324 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::captions::{self, now_millis, CaptionNaming, CaptionWriter, SaveOptions, SaveResult};
+use crate::error::{AppError, ErrorCode};
+use crate::history::CaptionSource;
+use crate::tags::{self, Tag, TagFormat};
+
+// Where a tag is inserted or moved to. An anchor tag that isn't in the
+// caption falls back to the end.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(tag = "at", rename_all = "snake_case")]
+pub enum TagPosition {
+    Start,
+    #[default]
+    End,
+    Before { tag: String },
+    After { tag: String },
+}
+
+// One edit applied to every caption's tag list. Tags are matched the way
+// dedupe compares them: ignoring case, spacing and underscores.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TagOperation {
+    // Rename a tag; `replace` may be several tags (`"a, b"`), and renaming onto
+    // a tag the caption already has merges the two
+    Replace { find: String, replace: String },
+    Remove { tags: Vec<String> },
+    // Insert tags the caption doesn't have yet
+    Add {
+        tags: Vec<String>,
+        #[serde(default)]
+        position: TagPosition,
+    },
+    Move { tag: String, position: TagPosition },
+}
+
+// Which captions a bulk edit covers: a whole dataset folder, or a selection
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BulkTarget {
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub recursive: bool,
+    pub paths: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkFileChange {
+    pub path: String,
+    pub caption_path: Option<String>,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkEditReport {
+    pub dry_run: bool,
+    // History batch the edit was saved under, so it can be rolled back as one
+    pub batch_id: Option<String>,
+    // Captions looked at (images without a caption file aren't counted)
+    pub scanned: usize,
+    pub changes: Vec<BulkFileChange>,
+    // Per-file save outcomes when the edit was applied
+    pub results: Vec<SaveResult>,
+    // Captions that couldn't be read
+    pub errors: Vec<AppError>,
+}
+
+impl BulkTarget {
+    fn images(&self) -> Result<Vec<PathBuf>, AppError> {
+        match (&self.directory, &self.paths) {
+            (_, Some(paths)) => Ok(paths.iter().map(PathBuf::from).collect()),
+            (Some(directory), None) => {
+                let dir = Path::new(directory);
+                if !dir.is_dir() {
+                    return Err(AppError::new(ErrorCode::NotFound, format!("Directory not found: {}", directory)).with_path(dir));
+                }
+                captions::images_in(dir, self.recursive).map_err(|e| AppError::io("Failed to read directory", dir, e))
+            }
+            (None, None) => Err(AppError::invalid("Pass either a directory or a list of image paths")),
+        }
+    }
+}
+
+// Work out what `operations` would do to every caption in `target`, and unless
+// `dry_run` is set write the changed captions as one history batch
+pub fn edit_tags(
+    writer: &CaptionWriter,
+    target: &BulkTarget,
+    operations: &[TagOperation],
+    format: &TagFormat,
+    options: &SaveOptions,
+    dry_run: bool,
+) -> Result<BulkEditReport, AppError> {
+    if operations.is_empty() {
+        return Err(AppError::invalid("No tag operations given"));
+    }
+
+    let images = target.images()?;
+    let mut report = BulkEditReport {
+        dry_run,
+        batch_id: None,
+        scanned: 0,
+        changes: Vec::new(),
+        results: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for loaded in captions::load_many(&writer.naming, &images) {
+        if let Some(error) = loaded.error {
+            report.errors.push(error);
+            continue;
+        }
+        let Some(before) = loaded.text else { continue };
+        report.scanned += 1;
+
+        let original = tags::normalize(tags::parse(&before), format);
+        let mut edited = original.clone();
+        for operation in operations {
+            apply(&mut edited, operation);
+        }
+        let edited = tags::normalize(edited, format);
+        if edited == original {
+            continue;
+        }
+
+        // Preview what will really be written, dictionaries included
+        let after = tags::serialize(&edited, format);
+        let after = writer.apply_dictionaries(&after).map_or(after, |report| report.caption);
+        report.changes.push(BulkFileChange {
+            path: loaded.path,
+            caption_path: loaded.caption_path,
+            before,
+            after,
+        });
+    }
+
+    if dry_run || report.changes.is_empty() {
+        return Ok(report);
+    }
+
+    let batch_id = format!("bulk-{}", now_millis());
+    let source = CaptionSource::BulkEdit { operations: operations.to_vec() };
+    report.results = report
+        .changes
+        .iter()
+        .map(|change| writer.save(Path::new(&change.path), &change.after, options, &source, Some(&batch_id)))
+        .collect();
+    report.batch_id = Some(batch_id);
+    Ok(report)
+}
+
+// Images in `target` whose captions have every one of `tags`
+pub fn find_tags(naming: &CaptionNaming, target: &BulkTarget, tags: &[String]) -> Result<Vec<String>, AppError> {
+    let keys: Vec<String> = tags.iter().map(|tag| tags::tag_key(tag)).collect();
+    let images = target.images()?;
+    Ok(captions::load_many(naming, &images)
+        .into_iter()
+        .filter(|loaded| {
+            let Some(text) = &loaded.text else { return false };
+            let present: Vec<String> = tags::parse(text).iter().map(|tag| tags::tag_key(&tag.name)).collect();
+            keys.iter().all(|key| present.contains(key))
+        })
+        .map(|loaded| loaded.path)
+        .collect())
+}
+
+// Apply one operation to a tag list in place
+pub fn apply(list: &mut Vec<Tag>, operation: &TagOperation) {
+    let position_of = |list: &[Tag], name: &str| {
+        let key = tags::tag_key(name);
+        list.iter().position(|tag| tags::tag_key(&tag.name) == key)
+    };
+
+    match operation {
+        TagOperation::Replace { find, replace } => {
+            let Some(index) = position_of(list, find) else {
+                return;
+            };
+            let weight = list[index].weight;
+            let replacements: Vec<Tag> = tags::parse(replace)
+                .into_iter()
+                .map(|tag| Tag { weight: tag.weight.or(weight), ..tag })
+                .collect();
+            list.splice(index..=index, replacements);
+        }
+        TagOperation::Remove { tags: remove } => {
+            let keys: Vec<String> = remove.iter().map(|tag| tags::tag_key(tag)).collect();
+            list.retain(|tag| !keys.contains(&tags::tag_key(&tag.name)));
+        }
+        TagOperation::Add { tags: add, position } => {
+            let new_tags: Vec<Tag> = add
+                .iter()
+                .flat_map(|tag| tags::parse(tag))
+                .filter(|new| position_of(list, &new.name).is_none())
+                .collect();
+            let index = insert_index(list, position);
+            list.splice(index..index, new_tags);
+        }
+        TagOperation::Move { tag, position } => {
+            let Some(from) = position_of(list, tag) else {
+                return;
+            };
+            let moved = list.remove(from);
+            let index = insert_index(list, position);
+            list.insert(index, moved);
+        }
+    }
+}
+
+fn insert_index(list: &[Tag], position: &TagPosition) -> usize {
+    let find = |anchor: &str| {
+        let key = tags::tag_key(anchor);
+        list.iter().position(|tag| tags::tag_key(&tag.name) == key)
+    };
+    match position {
+        TagPosition::Start => 0,
+        TagPosition::End => list.len(),
+        TagPosition::Before { tag } => find(tag).unwrap_or(list.len()),
+        TagPosition::After { tag } => find(tag).map(|index| index + 1).unwrap_or(list.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::{DictionaryKind, TagDictionaries};
+    use crate::history::CaptionHistory;
+    use crate::watcher::DatasetWatcher;
+
+    fn edited(caption: &str, operation: TagOperation) -> String {
+        let mut list = tags::parse(caption);
+        apply(&mut list, &operation);
+        tags::serialize(&list, &TagFormat::default())
+    }
+
+    fn add(tags: &[&str], position: TagPosition) -> TagOperation {
+        TagOperation::Add { tags: tags.iter().map(|tag| tag.to_string()).collect(), position }
+    }
+
+    #[test]
+    fn operations_edit_tag_lists() {
+        let replace = TagOperation::Replace { find: "Long_Hair".to_string(), replace: "very long hair, ponytail".to_string() };
+        assert_eq!(edited("1girl, (long hair:1.2), smile", replace.clone()), "1girl, (very long hair:1.2), (ponytail:1.2), smile");
+        assert_eq!(edited("1girl, smile", replace), "1girl, smile");
+
+        let remove = TagOperation::Remove { tags: vec!["SMILE".to_string(), "solo".to_string()] };
+        assert_eq!(edited("1girl, smile, solo, red eyes", remove), "1girl, red eyes");
+
+        assert_eq!(edited("1girl, smile", add(&["masterpiece"], TagPosition::Start)), "masterpiece, 1girl, smile");
+        assert_eq!(edited("1girl, smile", add(&["solo, smile"], TagPosition::End)), "1girl, smile, solo");
+        let before = TagPosition::Before { tag: "smile".to_string() };
+        assert_eq!(edited("1girl, smile", add(&["solo"], before)), "1girl, solo, smile");
+        let after = TagPosition::After { tag: "1girl".to_string() };
+        assert_eq!(edited("1girl, smile", add(&["solo"], after)), "1girl, solo, smile");
+        let missing = TagPosition::After { tag: "missing".to_string() };
+        assert_eq!(edited("1girl, smile", add(&["solo"], missing)), "1girl, smile, solo");
+
+        let to_start = TagOperation::Move { tag: "smile".to_string(), position: TagPosition::Start };
+        assert_eq!(edited("1girl, solo, smile", to_start), "smile, 1girl, solo");
+        let after_girl = TagOperation::Move { tag: "smile".to_string(), position: TagPosition::After { tag: "1girl".to_string() } };
+        assert_eq!(edited("solo, 1girl, red eyes, smile", after_girl), "solo, 1girl, smile, red eyes");
+    }
+
+    fn dataset(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tagmeister-bulk-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = image::RgbImage::new(2, 2);
+        for (name, caption) in [("a", "1girl, smile"), ("b", "1girl, smile, solo"), ("c", "landscape")] {
+            image.save(dir.join(format!("{}.png", name))).unwrap();
+            std::fs::write(dir.join(format!("{}.txt", name)), caption).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn dry_runs_preview_and_commits_write_one_batch() {
+        let dir = dataset("batch");
+        let watcher = DatasetWatcher::default();
+        let history = CaptionHistory::in_memory().unwrap();
+        let writer = CaptionWriter { watcher: &watcher, naming: CaptionNaming::default(), history: &history, dictionaries: None };
+        let target = BulkTarget { directory: Some(dir.to_string_lossy().to_string()), recursive: false, paths: None };
+        let operations = [TagOperation::Remove { tags: vec!["smile".to_string()] }];
+        let format = TagFormat::default();
+        let options = SaveOptions::default();
+
+        let preview = edit_tags(&writer, &target, &operations, &format, &options, true).unwrap();
+        assert_eq!(preview.scanned, 3);
+        let afters: Vec<&str> = preview.changes.iter().map(|change| change.after.as_str()).collect();
+        assert_eq!(afters, ["1girl", "1girl, solo"]);
+        assert!(preview.batch_id.is_none() && preview.results.is_empty());
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "1girl, smile");
+
+        let report = edit_tags(&writer, &target, &operations, &format, &options, false).unwrap();
+        let batch = history.batch(report.batch_id.as_deref().unwrap()).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "1girl");
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt")).unwrap(), "1girl, solo");
+        assert_eq!(std::fs::read_to_string(dir.join("c.txt")).unwrap(), "landscape");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removing_an_implied_tag_sticks() {
+        let dir = dataset("implied");
+        let implications = dir.join("implications.csv");
+        std::fs::write(&implications, "1girl,solo\n").unwrap();
+        let dictionaries = TagDictionaries::default();
+        dictionaries.load(&implications, DictionaryKind::Implications).unwrap();
+
+        let watcher = DatasetWatcher::default();
+        let history = CaptionHistory::in_memory().unwrap();
+        let writer = CaptionWriter { watcher: &watcher, naming: CaptionNaming::default(), history: &history, dictionaries: Some(&dictionaries) };
+        let target = BulkTarget { directory: None, recursive: false, paths: Some(vec![dir.join("b.png").to_string_lossy().to_string()]) };
+        let operations = [TagOperation::Remove { tags: vec!["solo".to_string()] }];
+
+        let report = edit_tags(&writer, &target, &operations, &TagFormat::default(), &SaveOptions::default(), false).unwrap();
+        assert_eq!(report.changes[0].after, "1girl, smile");
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt")).unwrap(), "1girl, smile");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
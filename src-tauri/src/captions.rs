@@ -0,0 +1,874 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+use crate::dictionary::{ApplyStage, DictionaryReport, TagDictionaries};
+use crate::error::{AppError, ErrorCode};
+use crate::formats::{self, Detection};
+use crate::history::{CaptionHistory, CaptionSource};
+use crate::watcher::DatasetWatcher;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionState {
+    // No caption file, or one that is empty/whitespace only
+    Uncaptioned,
+    Captioned,
+    // The image was modified after its caption was last written
+    Stale,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptionStatus {
+    pub path: String,
+    pub exists: bool,
+    pub size: u64,
+    // Last modification time in milliseconds since the Unix epoch
+    pub modified: Option<u64>,
+    pub word_count: usize,
+    // Number of comma-separated tags
+    pub tag_count: usize,
+    pub is_empty: bool,
+    pub state: CaptionState,
+}
+
+// Text statistics for a caption file, cached by the dataset index
+#[derive(Debug, Clone, Copy)]
+pub struct CaptionCounts {
+    pub word_count: usize,
+    pub tag_count: usize,
+    pub is_empty: bool,
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NewlineStyle {
+    // Write line endings exactly as given
+    #[default]
+    Keep,
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BomMode {
+    // Keep a BOM only if the caption file being replaced had one
+    #[default]
+    Preserve,
+    Add,
+    Strip,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    // Leave flushing to the OS; a crash can lose the write but never tears it
+    None,
+    // Flush the caption to disk before it replaces the old one
+    #[default]
+    File,
+    // Also flush the directory, so the rename itself survives a power cut
+    FileAndDirectory,
+}
+
+// Options for `save_caption`
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SaveOptions {
+    // Write the caption even when the image can't be found right now, e.g.
+    // while a sync client is replacing it
+    #[serde(default)]
+    pub create_missing: bool,
+    #[serde(default)]
+    pub newline: NewlineStyle,
+    #[serde(default)]
+    pub bom: BomMode,
+    // Strip whitespace from the end of every line and of the caption
+    #[serde(default)]
+    pub trim_trailing_whitespace: bool,
+    #[serde(default)]
+    pub sync: SyncMode,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveOutcome {
+    Written,
+    // The caption file already had exactly this text, so it was left alone
+    Unchanged,
+    // The image doesn't exist and `create_missing` was off
+    SkippedMissing,
+    Failed,
+}
+
+// Text encoding a caption file was read as
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TextEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    // Anything that isn't valid UTF-8; every byte sequence is valid Latin-1
+    Latin1,
+}
+
+// One caption returned by `load_captions`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoadedCaption {
+    // The image path the caption belongs to
+    pub path: String,
+    pub caption_path: Option<String>,
+    // False when the image simply has no caption file yet
+    pub exists: bool,
+    pub text: Option<String>,
+    pub encoding: Option<TextEncoding>,
+    pub error: Option<AppError>,
+}
+
+// What happened to one caption passed to `save_captions`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveResult {
+    // The image path the caption was given for
+    pub path: String,
+    pub caption_path: Option<String>,
+    pub outcome: SaveOutcome,
+    pub error: Option<AppError>,
+    // History entry recorded for the save, if anything was written
+    pub version: Option<i64>,
+    // What the tag dictionaries changed before the caption was written
+    #[serde(default)]
+    pub dictionary: Option<DictionaryReport>,
+}
+
+// How caption files are named after their images
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CaptionNaming {
+    // Caption file extension without the dot, e.g. "txt", "caption" or "tags"
+    pub extension: String,
+    // Name captions `<name>.<image ext>.<ext>` instead of `<name>.<ext>`, so
+    // `a.png` and `a.jpg` get separate captions
+    #[serde(default)]
+    pub keep_image_extension: bool,
+}
+
+impl Default for CaptionNaming {
+    fn default() -> Self {
+        CaptionNaming { extension: "txt".to_string(), keep_image_extension: false }
+    }
+}
+
+impl CaptionNaming {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let ext = self.extension.as_str();
+        if ext.is_empty() || ext.len() > 16 || !ext.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(AppError::invalid(format!(
+                "Invalid caption extension \"{}\": use letters, digits, '-' or '_' without the dot",
+                ext
+            )));
+        }
+        // An image extension would let captions overwrite images
+        if formats::spec_for_path(Path::new(&format!("caption.{}", ext))).is_some() {
+            return Err(AppError::invalid(format!("\"{}\" is an image extension and can't be used for captions", ext)));
+        }
+        Ok(())
+    }
+
+    // Get the caption file path for an image
+    pub fn caption_path_for(&self, path: &Path) -> Option<PathBuf> {
+        let parent = path.parent()?;
+        let base = if self.keep_image_extension { path.file_name()? } else { path.file_stem()? };
+        Some(parent.join(format!("{}.{}", base.to_string_lossy(), self.extension)))
+    }
+
+    pub fn is_caption_path(&self, path: &Path) -> bool {
+        let has_extension = path
+            .extension()
+            .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case(&self.extension));
+        if !has_extension {
+            return false;
+        }
+        // `notes.txt` isn't a caption when captions are named `a.png.txt`
+        !self.keep_image_extension || path.file_stem().is_some_and(|stem| formats::spec_for_path(Path::new(stem)).is_some())
+    }
+
+    // Every image in the caption's folder that maps to this caption file. More
+    // than one means the images collide (e.g. `a.png` and `a.jpg` both want
+    // `a.txt`). Extensions are matched in any case, the way the directory scan
+    // matches them, so `a.Png` is found too.
+    pub fn images_for_caption(&self, caption_path: &Path) -> Vec<PathBuf> {
+        let (Some(parent), Some(stem)) = (caption_path.parent(), caption_path.file_stem()) else {
+            return Vec::new();
+        };
+        if self.keep_image_extension {
+            let image = parent.join(stem);
+            if formats::spec_for_path(&image).is_some() && image.is_file() {
+                return vec![image];
+            }
+            return Vec::new();
+        }
+
+        let Ok(entries) = fs::read_dir(parent) else {
+            return Vec::new();
+        };
+        let mut images: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| parent.join(entry.file_name()))
+            .filter(|path| path.file_stem() == Some(stem) && formats::spec_for_path(path).is_some() && path.is_file())
+            .collect();
+        images.sort();
+        images
+    }
+
+    // Find the image a caption file belongs to
+    pub fn image_for_caption(&self, caption_path: &Path) -> Option<PathBuf> {
+        self.images_for_caption(caption_path).into_iter().next()
+    }
+}
+
+// Identifies a file regardless of how its path is spelled; None when the
+// path isn't a file
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path).ok()?;
+    metadata.is_file().then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path) -> Option<PathBuf> {
+    if !fs::metadata(path).ok()?.is_file() {
+        return None;
+    }
+    fs::canonicalize(path).ok()
+}
+
+// The naming policy in effect, shared by saving, scanning and the watcher
+#[derive(Default)]
+pub struct NamingPolicy {
+    current: Mutex<CaptionNaming>,
+}
+
+impl NamingPolicy {
+    pub fn get(&self) -> CaptionNaming {
+        self.current.lock().map(|naming| naming.clone()).unwrap_or_default()
+    }
+
+    pub fn set(&self, naming: CaptionNaming) -> Result<(), AppError> {
+        naming.validate()?;
+        let mut current = self.current.lock().map_err(|_| "Naming policy lock poisoned".to_string())?;
+        *current = naming;
+        Ok(())
+    }
+}
+
+// Milliseconds since the Unix epoch, as history, jobs and logs timestamp things
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+pub fn modified_millis(metadata: &fs::Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_millis() as u64)
+}
+
+pub fn count_words(caption: &str) -> usize {
+    caption.split_whitespace().count()
+}
+
+pub fn count_tags(caption: &str) -> usize {
+    caption.split(',').filter(|tag| !tag.trim().is_empty()).count()
+}
+
+pub fn read_counts(caption_path: &Path) -> CaptionCounts {
+    // Captions are small; a read failure is reported as an empty caption
+    let text = fs::read(caption_path)
+        .map(|bytes| decode_caption(&bytes).0)
+        .unwrap_or_default();
+    CaptionCounts {
+        word_count: count_words(&text),
+        tag_count: count_tags(&text),
+        is_empty: text.trim().is_empty(),
+    }
+}
+
+// Inspect the caption belonging to an image; `image_modified` is used to spot
+// captions that were written before the image last changed. `counts` supplies
+// the text statistics for an existing caption file (e.g. `read_counts`, or a
+// cached value keyed on the file's metadata).
+pub fn caption_status<F>(naming: &CaptionNaming, image_path: &Path, image_modified: Option<u64>, counts: F) -> Option<CaptionStatus>
+where
+    F: FnOnce(&Path, &fs::Metadata) -> CaptionCounts,
+{
+    let caption_path = naming.caption_path_for(image_path)?;
+    let path = caption_path.to_string_lossy().to_string();
+
+    let metadata = match fs::metadata(&caption_path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => {
+            return Some(CaptionStatus {
+                path,
+                exists: false,
+                size: 0,
+                modified: None,
+                word_count: 0,
+                tag_count: 0,
+                is_empty: true,
+                state: CaptionState::Uncaptioned,
+            })
+        }
+    };
+
+    let modified = modified_millis(&metadata);
+    let counts = counts(&caption_path, &metadata);
+
+    let state = if counts.is_empty {
+        CaptionState::Uncaptioned
+    } else {
+        match (modified, image_modified) {
+            (Some(caption_time), Some(image_time)) if caption_time < image_time => CaptionState::Stale,
+            _ => CaptionState::Captioned,
+        }
+    };
+
+    Some(CaptionStatus {
+        path,
+        exists: true,
+        size: metadata.len(),
+        modified,
+        word_count: counts.word_count,
+        tag_count: counts.tag_count,
+        is_empty: counts.is_empty,
+        state,
+    })
+}
+
+// Everything a caption write touches besides the file itself: the watcher
+// that must not echo it, the naming policy, and the history it's recorded in
+pub struct CaptionWriter<'a> {
+    pub watcher: &'a DatasetWatcher,
+    pub naming: CaptionNaming,
+    pub history: &'a CaptionHistory,
+    // Tag dictionaries respelling tags on every save; None for restores and
+    // rollbacks, which must write back exactly what was recorded, and for
+    // caption jobs, which run them before their filters
+    pub dictionaries: Option<&'a TagDictionaries>,
+}
+
+impl CaptionWriter<'_> {
+    // Write the caption for one image, reporting what was done instead of
+    // failing, so one bad path doesn't hide the outcome of the others. The
+    // caption it replaces is kept in the history under `source`.
+    pub fn save(
+        &self,
+        image_path: &Path,
+        caption: &str,
+        options: &SaveOptions,
+        source: &CaptionSource,
+        batch_id: Option<&str>,
+    ) -> SaveResult {
+        let (mut result, caption_path) = self.prepare(image_path, options.create_missing);
+        let Some(caption_path) = caption_path else {
+            return result;
+        };
+        result.dictionary = self.apply_dictionaries(caption);
+        let caption = result.dictionary.as_ref().map_or_else(|| caption.to_string(), |report| report.caption.clone());
+        self.write(result, &caption_path, &caption, options, source, batch_id)
+    }
+
+    // Put a caption file recorded in the history back to `text`, deleting it
+    // when `text` is None. The recorded path is written as is rather than
+    // derived from the current naming policy, which may have changed since.
+    pub fn restore(&self, image_path: &Path, caption_path: &Path, text: Option<&str>, source: &CaptionSource) -> SaveResult {
+        let result = SaveResult {
+            path: image_path.to_string_lossy().to_string(),
+            caption_path: Some(caption_path.to_string_lossy().to_string()),
+            outcome: SaveOutcome::Failed,
+            error: None,
+            version: None,
+            dictionary: None,
+        };
+        match text {
+            Some(text) => {
+                let options = SaveOptions { create_missing: true, ..SaveOptions::default() };
+                self.write(result, caption_path, text, &options, source, None)
+            }
+            None => self.delete(result, caption_path, source),
+        }
+    }
+
+    // What the save-stage tag dictionaries would make of `caption`, so
+    // previews can show the text that will actually be written
+    pub fn apply_dictionaries(&self, caption: &str) -> Option<DictionaryReport> {
+        self.dictionaries?.apply(caption, ApplyStage::Save)
+    }
+
+    fn write(
+        &self,
+        mut result: SaveResult,
+        caption_path: &Path,
+        caption: &str,
+        options: &SaveOptions,
+        source: &CaptionSource,
+        batch_id: Option<&str>,
+    ) -> SaveResult {
+        let existing = fs::read(caption_path).ok();
+        let contents = encode_caption(caption, options, existing.as_deref());
+        if existing.as_deref() == Some(contents.as_slice()) {
+            result.outcome = SaveOutcome::Unchanged;
+            return result;
+        }
+
+        self.watcher.note_own_write(caption_path);
+        if let Err(e) = write_atomic(caption_path, &contents, options.sync) {
+            result.error = Some(AppError::io("Failed to write caption", caption_path, e));
+            return result;
+        }
+        result.outcome = SaveOutcome::Written;
+
+        let previous = existing.map(|bytes| decode_caption(&bytes).0);
+        let text = decode_caption(&contents).0;
+        let image_path = PathBuf::from(&result.path);
+        result.version = self.record(caption_path, &image_path, previous.as_deref(), Some(&text), source, batch_id);
+        result
+    }
+
+    // Delete a caption file, keeping its text in the history
+    fn delete(&self, mut result: SaveResult, caption_path: &Path, source: &CaptionSource) -> SaveResult {
+        let existing = match fs::read(caption_path) {
+            Ok(bytes) => decode_caption(&bytes).0,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                result.outcome = SaveOutcome::Unchanged;
+                return result;
+            }
+            Err(e) => {
+                result.error = Some(AppError::io("Failed to read caption", caption_path, e));
+                return result;
+            }
+        };
+
+        self.watcher.note_own_write(caption_path);
+        if let Err(e) = fs::remove_file(caption_path) {
+            result.error = Some(AppError::io("Failed to remove caption", caption_path, e));
+            return result;
+        }
+        result.outcome = SaveOutcome::Written;
+        let image_path = PathBuf::from(&result.path);
+        result.version = self.record(caption_path, &image_path, Some(&existing), None, source, None);
+        result
+    }
+
+    // Resolve the caption path for a write, or fill in why there can't be one
+    fn prepare(&self, image_path: &Path, create_missing: bool) -> (SaveResult, Option<PathBuf>) {
+        let mut result = SaveResult {
+            path: image_path.to_string_lossy().to_string(),
+            caption_path: None,
+            outcome: SaveOutcome::Failed,
+            error: None,
+            version: None,
+            dictionary: None,
+        };
+
+        let Some(caption_path) = self.naming.caption_path_for(image_path) else {
+            result.error = Some(AppError::invalid(format!("Invalid image path: {}", image_path.display())).with_path(image_path));
+            return (result, None);
+        };
+        result.caption_path = Some(caption_path.to_string_lossy().to_string());
+
+        if !image_path.is_file() && !create_missing {
+            result.outcome = SaveOutcome::SkippedMissing;
+            result.error = Some(AppError::not_found(image_path));
+            return (result, None);
+        }
+
+        // Don't let one image's caption overwrite another's. Captions that
+        // keep the image extension can't collide.
+        if !self.naming.keep_image_extension {
+            let own = file_id(image_path);
+            let others: Vec<String> = self
+                .naming
+                .images_for_caption(&caption_path)
+                .into_iter()
+                .filter(|other| other.file_name() != image_path.file_name() && (own.is_none() || file_id(other) != own))
+                .map(|other| other.to_string_lossy().to_string())
+                .collect();
+            if !others.is_empty() {
+                result.error = Some(
+                    AppError::new(
+                        ErrorCode::Conflict,
+                        format!("{} is also the caption file for {}", caption_path.display(), others.join(", ")),
+                    )
+                    .with_path(&caption_path),
+                );
+                return (result, None);
+            }
+        }
+
+        (result, Some(caption_path))
+    }
+
+    // A history failure is logged rather than failing a save that already
+    // happened
+    fn record(
+        &self,
+        caption_path: &Path,
+        image_path: &Path,
+        previous: Option<&str>,
+        text: Option<&str>,
+        source: &CaptionSource,
+        batch_id: Option<&str>,
+    ) -> Option<i64> {
+        match self.history.record(caption_path, image_path, previous, text, source, batch_id) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        }
+    }
+}
+
+// The bytes to write for a caption once the save options are applied.
+// `existing` is the current caption file, used to preserve its BOM.
+pub fn encode_caption(caption: &str, options: &SaveOptions, existing: Option<&[u8]>) -> Vec<u8> {
+    let mut text = caption.strip_prefix('\u{feff}').unwrap_or(caption).to_string();
+
+    if options.trim_trailing_whitespace {
+        let trimmed: Vec<&str> = text.split('\n').map(|line| line.trim_end()).collect();
+        text = trimmed.join("\n").trim_end().to_string();
+    }
+
+    text = match options.newline {
+        NewlineStyle::Keep => text,
+        NewlineStyle::Lf => text.replace("\r\n", "\n"),
+        NewlineStyle::Crlf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+    };
+
+    let bom = match options.bom {
+        BomMode::Preserve => existing.is_some_and(|bytes| bytes.starts_with(UTF8_BOM)),
+        BomMode::Add => true,
+        BomMode::Strip => false,
+    };
+
+    let mut bytes = Vec::with_capacity(text.len() + UTF8_BOM.len());
+    if bom {
+        bytes.extend_from_slice(UTF8_BOM);
+    }
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+// Write `contents` to a temporary file next to `path` and rename it into
+// place, so readers (and training runs) see either the old caption or the new
+// one, never a truncated file
+pub fn write_atomic(path: &Path, contents: &[u8], sync: SyncMode) -> io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let parent = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "caption path has no parent directory"))?;
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let temp_path = parent.join(format!(
+        ".{}.{}-{}.tmp",
+        file_name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        if sync != SyncMode::None {
+            file.sync_all()?;
+        }
+        // Keep the permissions of the caption being replaced
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        drop(file);
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    if sync == SyncMode::FileAndDirectory {
+        sync_directory(parent)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_directory(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened for syncing on Windows; the rename is already
+// durable once it returns there
+#[cfg(not(unix))]
+fn sync_directory(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Decode a caption file, going by its BOM if it has one. Without a BOM,
+// UTF-16 is recognised by its zero high bytes (captions are mostly ASCII);
+// otherwise the text is UTF-8 if it's valid and Latin-1 if not.
+pub fn decode_caption(bytes: &[u8]) -> (String, TextEncoding) {
+    if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        return (String::from_utf8_lossy(rest).into_owned(), TextEncoding::Utf8Bom);
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        return (decode_utf16(rest, u16::from_le_bytes), TextEncoding::Utf16Le);
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        return (decode_utf16(rest, u16::from_be_bytes), TextEncoding::Utf16Be);
+    }
+
+    if bytes.len() >= 2 && bytes.len().is_multiple_of(2) {
+        let pairs = bytes.len() / 2;
+        let zeros_at = |offset: usize| bytes.iter().skip(offset).step_by(2).filter(|b| **b == 0).count();
+        if zeros_at(1) * 2 > pairs && zeros_at(0) == 0 {
+            return (decode_utf16(bytes, u16::from_le_bytes), TextEncoding::Utf16Le);
+        }
+        if zeros_at(0) * 2 > pairs && zeros_at(1) == 0 {
+            return (decode_utf16(bytes, u16::from_be_bytes), TextEncoding::Utf16Be);
+        }
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), TextEncoding::Utf8),
+        Err(_) => (bytes.iter().map(|b| char::from(*b)).collect(), TextEncoding::Latin1),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
+
+// Read the caption for one image; a missing caption file isn't an error
+pub fn load_caption(naming: &CaptionNaming, image_path: &Path) -> LoadedCaption {
+    let mut loaded = LoadedCaption {
+        path: image_path.to_string_lossy().to_string(),
+        caption_path: None,
+        exists: false,
+        text: None,
+        encoding: None,
+        error: None,
+    };
+
+    let Some(caption_path) = naming.caption_path_for(image_path) else {
+        loaded.error = Some(AppError::invalid(format!("Invalid image path: {}", image_path.display())).with_path(image_path));
+        return loaded;
+    };
+    loaded.caption_path = Some(caption_path.to_string_lossy().to_string());
+
+    match fs::read(&caption_path) {
+        Ok(bytes) => {
+            let (text, encoding) = decode_caption(&bytes);
+            loaded.exists = true;
+            loaded.text = Some(text);
+            loaded.encoding = Some(encoding);
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => loaded.error = Some(AppError::io("Failed to read caption", &caption_path, e)),
+    }
+    loaded
+}
+
+// Read the captions for many images at once, one worker per CPU core.
+// Results come back in the order requested.
+pub fn load_many(naming: &CaptionNaming, paths: &[PathBuf]) -> Vec<LoadedCaption> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<LoadedCaption>>> = Mutex::new(paths.iter().map(|_| None).collect());
+    let workers = num_cpus::get().clamp(1, paths.len().max(1));
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(i) else { break };
+
+                let loaded = load_caption(naming, path);
+                results.lock().unwrap_or_else(PoisonError::into_inner)[i] = Some(loaded);
+            });
+        }
+    });
+
+    // Every path gets an entry, so callers can line results up with requests
+    let results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    paths
+        .iter()
+        .zip(results)
+        .map(|(path, loaded)| {
+            loaded.unwrap_or_else(|| LoadedCaption {
+                path: path.to_string_lossy().to_string(),
+                caption_path: None,
+                exists: false,
+                text: None,
+                encoding: None,
+                error: Some(AppError::new(ErrorCode::Internal, "Caption was not loaded").with_path(path)),
+            })
+        })
+        .collect()
+}
+
+// Every image in `dir` (and its subfolders when `recursive`, skipping hidden
+// ones), sorted by path. Files are judged by their header the way the
+// directory scan judges them, so both agree on what counts as an image.
+pub fn images_in(dir: &Path, recursive: bool) -> io::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(entries) => entries,
+            // Only the root itself has to be readable
+            Err(e) if current == dir => return Err(e),
+            Err(e) => {
+                println!("Skipping unreadable folder {}: {}", current.display(), e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else { continue };
+            if file_type.is_dir() {
+                if recursive && !entry.file_name().to_string_lossy().starts_with('.') {
+                    pending.push(path);
+                }
+            } else if matches!(formats::detect(&path), Detection::Image { .. }) {
+                images.push(path);
+            }
+        }
+    }
+    images.sort();
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tagmeister-captions-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn colliding_images_share_a_caption_path() {
+        let dir = test_dir("collide");
+        for name in ["a.png", "a.JPG", "ab.png", "a.txt", "b.Png", "b.txt", "c.Webp"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        fs::create_dir_all(dir.join("c.png")).unwrap();
+
+        let naming = CaptionNaming::default();
+        assert_eq!(naming.images_for_caption(&dir.join("a.txt")), [dir.join("a.JPG"), dir.join("a.png")]);
+        assert_eq!(naming.images_for_caption(&dir.join("b.txt")), [dir.join("b.Png")]);
+        assert_eq!(naming.images_for_caption(&dir.join("c.txt")), [dir.join("c.Webp")]);
+        assert!(naming.images_for_caption(&dir.join("missing/a.txt")).is_empty());
+        assert_eq!(naming.images_for_caption(&dir.join("ab.txt")), [dir.join("ab.png")]);
+
+        let naming = CaptionNaming { keep_image_extension: true, ..CaptionNaming::default() };
+        assert_eq!(naming.caption_path_for(&dir.join("a.png")), Some(dir.join("a.png.txt")));
+        assert_eq!(naming.images_for_caption(&dir.join("a.png.txt")), [dir.join("a.png")]);
+        assert!(naming.is_caption_path(&dir.join("a.png.txt")));
+        assert!(!naming.is_caption_path(&dir.join("notes.txt")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_refuse_to_overwrite_another_images_caption() {
+        let dir = test_dir("save");
+        fs::write(dir.join("a.png"), b"").unwrap();
+        fs::write(dir.join("a.jpg"), b"").unwrap();
+        fs::write(dir.join("b.png"), b"").unwrap();
+
+        let watcher = DatasetWatcher::default();
+        let history = CaptionHistory::in_memory().unwrap();
+        let writer = |naming| CaptionWriter { watcher: &watcher, naming, history: &history, dictionaries: None };
+        let options = SaveOptions::default();
+        let source = CaptionSource::Manual;
+
+        let result = writer(CaptionNaming::default()).save(&dir.join("a.png"), "cat", &options, &source, None);
+        assert_eq!(result.outcome, SaveOutcome::Failed);
+        assert_eq!(result.error.map(|e| e.code), Some(ErrorCode::Conflict));
+        assert!(!dir.join("a.txt").exists());
+
+        let result = writer(CaptionNaming::default()).save(&dir.join("b.png"), "dog", &options, &source, None);
+        assert_eq!(result.outcome, SaveOutcome::Written);
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "dog");
+
+        let keep = CaptionNaming { keep_image_extension: true, ..CaptionNaming::default() };
+        let result = writer(keep.clone()).save(&dir.join("a.png"), "cat", &options, &source, None);
+        assert_eq!(result.outcome, SaveOutcome::Written);
+        assert_eq!(fs::read_to_string(dir.join("a.png.txt")).unwrap(), "cat");
+
+        // Restores go to the recorded path even under another naming policy
+        writer(keep).restore(&dir.join("b.png"), &dir.join("b.txt"), None, &source);
+        assert!(!dir.join("b.txt").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hand_removed_implied_tags_stay_removed() {
+        let dir = test_dir("implied");
+        fs::write(dir.join("a.png"), b"").unwrap();
+        let implications = dir.join("implications.csv");
+        fs::write(&implications, "long_hair,hair\n").unwrap();
+
+        let watcher = DatasetWatcher::default();
+        let history = CaptionHistory::in_memory().unwrap();
+        let dictionaries = TagDictionaries::default();
+        dictionaries.load(&implications, crate::dictionary::DictionaryKind::Implications).unwrap();
+        let writer = CaptionWriter { watcher: &watcher, naming: CaptionNaming::default(), history: &history, dictionaries: Some(&dictionaries) };
+
+        let result = writer.save(&dir.join("a.png"), "1girl, long hair", &SaveOptions::default(), &CaptionSource::Manual, None);
+        assert_eq!(result.outcome, SaveOutcome::Written);
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "1girl, long hair");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn encoding_applies_the_save_options() {
+        let options = SaveOptions { newline: NewlineStyle::Crlf, trim_trailing_whitespace: true, ..SaveOptions::default() };
+        assert_eq!(encode_caption("a  \nb\n\n", &options, None), b"a\r\nb");
+
+        let existing = [UTF8_BOM, b"old"].concat();
+        assert_eq!(encode_caption("new", &SaveOptions::default(), Some(&existing)), [UTF8_BOM, b"new"].concat());
+        let strip = SaveOptions { bom: BomMode::Strip, ..SaveOptions::default() };
+        assert_eq!(encode_caption("\u{feff}new", &strip, Some(&existing)), b"new");
+    }
+
+    #[test]
+    fn load_many_returns_one_entry_per_path_in_order() {
+        let dir = test_dir("load-many");
+        fs::write(dir.join("a.txt"), "cat").unwrap();
+        fs::create_dir_all(dir.join("c.txt")).unwrap();
+        let paths = [dir.join("b.png"), PathBuf::from(""), dir.join("c.png"), dir.join("a.png")];
+
+        let loaded = load_many(&CaptionNaming::default(), &paths);
+        assert_eq!(loaded.iter().map(|l| l.path.clone()).collect::<Vec<_>>(), paths.map(|p| p.to_string_lossy().to_string()));
+        assert!(!loaded[0].exists && loaded[0].error.is_none());
+        assert_eq!(loaded[1].error.as_ref().map(|e| e.code), Some(ErrorCode::InvalidArgument));
+        assert!(loaded[2].error.is_some());
+        assert_eq!(loaded[3].text.as_deref(), Some("cat"));
+        assert!(load_many(&CaptionNaming::default(), &[]).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
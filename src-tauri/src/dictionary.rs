@@ -0,0 +1,573 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::captions;
+use crate::error::AppError;
+use crate::tags::{self, Tag, TagFormat};
+
+// How far alias chains (`a -> b -> c`) and implication chains are followed;
+// anything longer is a cycle in a hand-written file
+const MAX_CHAIN: usize = 16;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DictionaryKind {
+    // `antecedent,consequent` rows as in Danbooru's tag alias export; the
+    // first tag is rewritten to the second
+    Aliases,
+    // `antecedent,consequent` rows; a caption with the first tag also has the second
+    Implications,
+    // Known tags in the tag-autocomplete layout `name,category,count,"alias,alias"`.
+    // Only the name is required; listed aliases are rewritten to the name.
+    Vocabulary,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImplicationMode {
+    // Add the tags a caption's tags imply
+    #[default]
+    Add,
+    // Drop tags a more specific tag already implies (`long hair` next to `very long hair`)
+    Prune,
+    Ignore,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DictionarySettings {
+    // Rewrite captions as they come back from a model
+    #[serde(default = "default_true")]
+    pub apply_on_generate: bool,
+    // Respell tags in captions as they're saved. Implications only apply
+    // to generated captions, so a tag removed by hand stays removed.
+    #[serde(default = "default_true")]
+    pub apply_on_save: bool,
+    #[serde(default)]
+    pub implications: ImplicationMode,
+    #[serde(default)]
+    pub format: TagFormat,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for DictionarySettings {
+    fn default() -> Self {
+        DictionarySettings {
+            apply_on_generate: true,
+            apply_on_save: true,
+            implications: ImplicationMode::Add,
+            format: TagFormat::default(),
+        }
+    }
+}
+
+// A loaded dictionary file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DictionarySource {
+    pub path: String,
+    pub kind: DictionaryKind,
+    pub entries: usize,
+    // Rows that were incomplete or marked anything but active
+    pub skipped: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AliasChange {
+    pub from: String,
+    pub to: String,
+}
+
+// What the dictionaries did to one caption
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DictionaryReport {
+    pub caption: String,
+    pub aliased: Vec<AliasChange>,
+    // Tags added (or with `Prune`, removed) because of implications
+    pub implied: Vec<String>,
+    // Tags not in any vocabulary file; empty when no vocabulary is loaded
+    pub unknown: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+pub enum ApplyStage {
+    Generate,
+    Save,
+}
+
+// Aliases, implications and known tags merged from every loaded file. Later
+// files win, so project files loaded after a Danbooru export override it.
+#[derive(Debug, Default)]
+pub struct TagDictionary {
+    sources: Vec<DictionarySource>,
+    // Tag key -> canonical spelling
+    aliases: HashMap<String, String>,
+    implications: HashMap<String, Vec<String>>,
+    known: HashSet<String>,
+}
+
+// Column positions within a dictionary file's rows
+struct Columns {
+    first: usize,
+    second: Option<usize>,
+    status: Option<usize>,
+}
+
+impl Columns {
+    fn default_for(kind: DictionaryKind) -> Self {
+        match kind {
+            DictionaryKind::Aliases | DictionaryKind::Implications => Columns { first: 0, second: Some(1), status: None },
+            DictionaryKind::Vocabulary => Columns { first: 0, second: Some(3), status: None },
+        }
+    }
+
+    // Columns named by a header row, if `row` is one
+    fn from_header(row: &[String], kind: DictionaryKind) -> Option<Self> {
+        let find = |names: &[&str]| row.iter().position(|column| names.contains(&column.trim().to_lowercase().as_str()));
+        match kind {
+            DictionaryKind::Aliases | DictionaryKind::Implications => Some(Columns {
+                first: find(&["antecedent_name", "antecedent"])?,
+                second: Some(find(&["consequent_name", "consequent"])?),
+                status: find(&["status"]),
+            }),
+            DictionaryKind::Vocabulary => Some(Columns {
+                first: find(&["name", "tag"])?,
+                second: find(&["aliases"]),
+                status: None,
+            }),
+        }
+    }
+}
+
+impl TagDictionary {
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    fn has_vocabulary(&self) -> bool {
+        self.sources.iter().any(|source| source.kind == DictionaryKind::Vocabulary)
+    }
+
+    // Merge one CSV file into the dictionary. Blank lines and `#` comments
+    // are skipped, and a header row, if present, picks the columns.
+    fn load(&mut self, path: &Path, kind: DictionaryKind) -> Result<DictionarySource, AppError> {
+        let bytes = fs::read(path).map_err(|e| AppError::io("Failed to read tag dictionary", path, e))?;
+        let (text, _) = captions::decode_caption(&bytes);
+        let mut rows = text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(parse_csv_row)
+            .peekable();
+
+        let mut columns = Columns::default_for(kind);
+        if let Some(header) = rows.peek().and_then(|row| Columns::from_header(row, kind)) {
+            columns = header;
+            rows.next();
+        }
+
+        let mut source = DictionarySource { path: path.to_string_lossy().to_string(), kind, entries: 0, skipped: 0 };
+        for row in rows {
+            let field = |index: Option<usize>| index.and_then(|i| row.get(i)).map(|f| f.trim()).filter(|f| !f.is_empty());
+            if field(columns.status).is_some_and(|status| !status.eq_ignore_ascii_case("active")) {
+                source.skipped += 1;
+                continue;
+            }
+            let Some(first) = field(Some(columns.first)) else {
+                source.skipped += 1;
+                continue;
+            };
+
+            match kind {
+                DictionaryKind::Aliases | DictionaryKind::Implications => {
+                    let Some(second) = field(columns.second) else {
+                        source.skipped += 1;
+                        continue;
+                    };
+                    let (from, to) = (tags::tag_key(first), tags::tag_key(second));
+                    if from == to {
+                        source.skipped += 1;
+                        continue;
+                    }
+                    if kind == DictionaryKind::Aliases {
+                        self.aliases.insert(from, second.to_string());
+                    } else {
+                        self.implications.entry(from).or_default().push(second.to_string());
+                        self.known.insert(tags::tag_key(first));
+                    }
+                    self.known.insert(to);
+                }
+                DictionaryKind::Vocabulary => {
+                    self.known.insert(tags::tag_key(first));
+                    for alias in field(columns.second).into_iter().flat_map(|aliases| aliases.split(',')) {
+                        let alias = tags::tag_key(alias);
+                        if !alias.is_empty() && alias != tags::tag_key(first) {
+                            self.aliases.entry(alias).or_insert_with(|| first.to_string());
+                        }
+                    }
+                }
+            }
+            source.entries += 1;
+        }
+
+        if source.entries == 0 {
+            return Err(AppError::invalid(format!("No tags found in {}", path.display())).with_path(path));
+        }
+        self.sources.push(source.clone());
+        Ok(source)
+    }
+
+    // Canonical spelling of a tag, following alias chains
+    fn canonical(&self, name: &str) -> Option<&str> {
+        let mut key = tags::tag_key(name);
+        let mut found = None;
+        for _ in 0..MAX_CHAIN {
+            let Some(next) = self.aliases.get(&key) else { break };
+            found = Some(next.as_str());
+            key = tags::tag_key(next);
+        }
+        found
+    }
+
+    // Every tag `name` implies, directly or through other implications
+    fn implied_by(&self, name: &str) -> Vec<String> {
+        let mut implied: Vec<String> = Vec::new();
+        let mut seen = HashSet::from([tags::tag_key(name)]);
+        let mut frontier = vec![tags::tag_key(name)];
+        for _ in 0..MAX_CHAIN {
+            let next: Vec<String> = frontier
+                .iter()
+                .filter_map(|key| self.implications.get(key))
+                .flatten()
+                .map(|tag| self.canonical(tag).unwrap_or(tag).to_string())
+                .filter(|tag| seen.insert(tags::tag_key(tag)))
+                .collect();
+            if next.is_empty() {
+                break;
+            }
+            frontier = next.iter().map(|tag| tags::tag_key(tag)).collect();
+            implied.extend(next);
+        }
+        implied
+    }
+
+    // Rewrite aliased tags to their canonical names, apply implications and
+    // list tags the vocabulary doesn't know. Dictionary spellings are adapted
+    // to the caption's own underscore or space style. The caption is only
+    // re-serialised when an alias or implication changed a tag, so prose and
+    // hand-formatted tag lists come back byte for byte otherwise.
+    pub fn apply(&self, caption: &str, settings: &DictionarySettings) -> DictionaryReport {
+        let mut report = DictionaryReport {
+            caption: caption.to_string(),
+            aliased: Vec::new(),
+            implied: Vec::new(),
+            unknown: Vec::new(),
+        };
+        if self.is_empty() {
+            return report;
+        }
+
+        let format = &settings.format;
+        let parsed = tags::normalize(tags::parse(caption), format);
+        let caption_style = parsed.iter().find_map(|tag| spacing_of(&tag.name));
+        let mut list: Vec<Tag> = Vec::with_capacity(parsed.len());
+        for tag in parsed {
+            match self.canonical(&tag.name) {
+                Some(canonical) if tags::tag_key(canonical) != tags::tag_key(&tag.name) => {
+                    let name = respell(canonical, spacing_of(&tag.name).or(caption_style));
+                    report.aliased.push(AliasChange { from: tag.name, to: name.clone() });
+                    list.push(Tag { name, weight: tag.weight });
+                }
+                _ => list.push(tag),
+            }
+        }
+
+        match settings.implications {
+            ImplicationMode::Add => {
+                let mut present: HashSet<String> = list.iter().map(|tag| tags::tag_key(&tag.name)).collect();
+                let implied: Vec<String> = list
+                    .iter()
+                    .flat_map(|tag| self.implied_by(&tag.name))
+                    .filter(|name| present.insert(tags::tag_key(name)))
+                    .map(|name| respell(&name, caption_style))
+                    .collect();
+                list.extend(implied.iter().map(|name| Tag { name: name.clone(), weight: None }));
+                report.implied = implied;
+            }
+            ImplicationMode::Prune => {
+                let implied: HashSet<String> = list
+                    .iter()
+                    .flat_map(|tag| self.implied_by(&tag.name))
+                    .map(|name| tags::tag_key(&name))
+                    .collect();
+                list.retain(|tag| {
+                    let keep = !implied.contains(&tags::tag_key(&tag.name));
+                    if !keep {
+                        report.implied.push(tag.name.clone());
+                    }
+                    keep
+                });
+            }
+            ImplicationMode::Ignore => {}
+        }
+
+        let list = tags::normalize(list, format);
+        if self.has_vocabulary() {
+            report.unknown = list
+                .iter()
+                .filter(|tag| !self.known.contains(&tags::tag_key(&tag.name)))
+                .map(|tag| tag.name.clone())
+                .collect();
+        }
+        if !report.aliased.is_empty() || !report.implied.is_empty() {
+            report.caption = tags::serialize(&list, format);
+        }
+        report
+    }
+}
+
+// Whether a tag is written with spaces (true) or underscores (false); None
+// for single words
+fn spacing_of(name: &str) -> Option<bool> {
+    if name.contains(' ') {
+        Some(true)
+    } else if name.contains('_') && !tags::is_kaomoji(name) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn respell(name: &str, spaces: Option<bool>) -> String {
+    match spaces {
+        _ if tags::is_kaomoji(name) => name.to_string(),
+        Some(true) => name.replace('_', " "),
+        Some(false) => name.replace(' ', "_"),
+        None => name.to_string(),
+    }
+}
+
+// Split one CSV line, honouring double-quoted fields with `""` escapes
+fn parse_csv_row(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+// The loaded dictionaries and when they apply, shared by every command
+#[derive(Default)]
+pub struct TagDictionaries {
+    dictionary: Mutex<TagDictionary>,
+    settings: Mutex<DictionarySettings>,
+}
+
+impl TagDictionaries {
+    pub fn sources(&self) -> Vec<DictionarySource> {
+        self.dictionary.lock().map(|dictionary| dictionary.sources.clone()).unwrap_or_default()
+    }
+
+    // Load a dictionary file, replacing it if it was loaded before. Every
+    // file is read again so the merge order stays the load order.
+    pub fn load(&self, path: &Path, kind: DictionaryKind) -> Result<Vec<DictionarySource>, AppError> {
+        let path_string = path.to_string_lossy().to_string();
+        let mut files: Vec<(String, DictionaryKind)> = self
+            .sources()
+            .into_iter()
+            .filter(|source| source.path != path_string)
+            .map(|source| (source.path, source.kind))
+            .collect();
+        files.push((path_string, kind));
+        self.rebuild(&files, Some(path))
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<Vec<DictionarySource>, AppError> {
+        let path = path.to_string_lossy();
+        let files: Vec<(String, DictionaryKind)> = self
+            .sources()
+            .into_iter()
+            .filter(|source| source.path != path)
+            .map(|source| (source.path, source.kind))
+            .collect();
+        self.rebuild(&files, None)
+    }
+
+    // Read `files` into a fresh dictionary. Only a failure to read `required`
+    // is an error; other files that have since gone missing are dropped.
+    fn rebuild(&self, files: &[(String, DictionaryKind)], required: Option<&Path>) -> Result<Vec<DictionarySource>, AppError> {
+        let mut dictionary = TagDictionary::default();
+        for (path, kind) in files {
+            if let Err(e) = dictionary.load(Path::new(path), *kind) {
+                if required == Some(Path::new(path)) {
+                    return Err(e);
+                }
+                println!("Dropping tag dictionary {}: {}", path, e);
+            }
+        }
+        let sources = dictionary.sources.clone();
+        let mut current = self.dictionary.lock().map_err(|_| "Tag dictionary lock poisoned".to_string())?;
+        *current = dictionary;
+        Ok(sources)
+    }
+
+    pub fn settings(&self) -> DictionarySettings {
+        self.settings.lock().map(|settings| settings.clone()).unwrap_or_default()
+    }
+
+    pub fn set_settings(&self, settings: DictionarySettings) -> Result<(), AppError> {
+        let mut current = self.settings.lock().map_err(|_| "Dictionary settings lock poisoned".to_string())?;
+        *current = settings;
+        Ok(())
+    }
+
+    // Run a caption through the dictionaries regardless of the apply settings
+    pub fn check(&self, caption: &str) -> DictionaryReport {
+        let settings = self.settings();
+        let dictionary = self.dictionary.lock().unwrap_or_else(|e| e.into_inner());
+        dictionary.apply(caption, &settings)
+    }
+
+    // The dictionaries' report for a caption being generated or saved, or
+    // None when they're off for that stage or nothing is loaded
+    pub fn apply(&self, caption: &str, stage: ApplyStage) -> Option<DictionaryReport> {
+        let mut settings = self.settings();
+        let enabled = match stage {
+            ApplyStage::Generate => settings.apply_on_generate,
+            // Saves only respell tags; adding or pruning implied tags there
+            // would undo tags the user just removed or added by hand
+            ApplyStage::Save => {
+                settings.implications = ImplicationMode::Ignore;
+                settings.apply_on_save
+            }
+        };
+        let dictionary = self.dictionary.lock().unwrap_or_else(|e| e.into_inner());
+        if !enabled || dictionary.is_empty() || caption.trim().is_empty() {
+            return None;
+        }
+        Some(dictionary.apply(caption, &settings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    fn write_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tagmeister-dictionary-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn dictionary(files: &[(&str, DictionaryKind, &str)]) -> TagDictionary {
+        let mut dictionary = TagDictionary::default();
+        for (name, kind, contents) in files {
+            dictionary.load(&write_file(name, contents), *kind).unwrap();
+        }
+        dictionary
+    }
+
+    #[test]
+    fn aliases_follow_chains_and_skip_inactive_rows() {
+        let aliases = "id,antecedent_name,consequent_name,status\n\
+                       1,longhair,long_hair,active\n\
+                       2,very_long,very_long_hair,deleted\n\
+                       3,kitty,cat_girl,active\n\
+                       4,cat_girl,catgirl,active\n";
+        let dictionary = dictionary(&[("aliases.csv", DictionaryKind::Aliases, aliases)]);
+        assert_eq!(dictionary.sources[0].entries, 3);
+        assert_eq!(dictionary.sources[0].skipped, 1);
+
+        let report = dictionary.apply("kitty, very long, blue eyes", &DictionarySettings::default());
+        assert_eq!(report.caption, "catgirl, very long, blue eyes");
+        assert_eq!(report.aliased.len(), 1);
+        assert_eq!(report.aliased[0].from, "kitty");
+    }
+
+    #[test]
+    fn aliases_keep_the_caption_spelling() {
+        let dictionary = dictionary(&[("spelling.csv", DictionaryKind::Aliases, "blonde_hair,blond_hair\n")]);
+        let report = dictionary.apply("1girl, blonde hair", &DictionarySettings::default());
+        assert_eq!(report.caption, "1girl, blond hair");
+    }
+
+    #[test]
+    fn implications_add_or_prune() {
+        let implications = "very_long_hair,long_hair\nlong_hair,hair\n";
+        let dictionary = dictionary(&[("implications.csv", DictionaryKind::Implications, implications)]);
+
+        let added = dictionary.apply("very long hair, smile", &DictionarySettings::default());
+        assert_eq!(added.caption, "very long hair, smile, long hair, hair");
+        assert_eq!(added.implied, ["long hair", "hair"]);
+
+        let prune = DictionarySettings { implications: ImplicationMode::Prune, ..DictionarySettings::default() };
+        let pruned = dictionary.apply("long hair, very long hair, smile", &prune);
+        assert_eq!(pruned.caption, "very long hair, smile");
+        assert_eq!(pruned.implied, ["long hair"]);
+    }
+
+    #[test]
+    fn vocabulary_reports_unknown_tags_and_rewrites_its_aliases() {
+        let vocabulary = "1girl,0,100,\"1girls,sole_female\"\nsmile,0,50,\n";
+        let dictionary = dictionary(&[("vocabulary.csv", DictionaryKind::Vocabulary, vocabulary)]);
+        let report = dictionary.apply("sole female, smile, made up tag", &DictionarySettings::default());
+        assert_eq!(report.caption, "1girl, smile, made up tag");
+        assert_eq!(report.unknown, ["made up tag"]);
+    }
+
+    #[test]
+    fn captions_without_changes_come_back_untouched() {
+        let dictionary = dictionary(&[("untouched.csv", DictionaryKind::Aliases, "kitty,cat\n")]);
+        let caption = "A photo of a dog,  sitting (on a mat).";
+        assert_eq!(dictionary.apply(caption, &DictionarySettings::default()).caption, caption);
+    }
+
+    #[test]
+    fn stages_can_be_switched_off() {
+        let dictionaries = TagDictionaries::default();
+        assert!(dictionaries.apply("kitty", ApplyStage::Save).is_none());
+
+        let path = write_file("stages.csv", "kitty,cat\n");
+        dictionaries.load(&path, DictionaryKind::Aliases).unwrap();
+        dictionaries.set_settings(DictionarySettings { apply_on_save: false, ..DictionarySettings::default() }).unwrap();
+        assert!(dictionaries.apply("kitty", ApplyStage::Save).is_none());
+        assert_eq!(dictionaries.apply("kitty", ApplyStage::Generate).unwrap().caption, "cat");
+
+        assert!(dictionaries.remove(&path).unwrap().is_empty());
+        assert!(dictionaries.apply("kitty", ApplyStage::Generate).is_none());
+    }
+
+    #[test]
+    fn saves_never_bring_back_implied_tags() {
+        let dictionaries = TagDictionaries::default();
+        dictionaries.load(&write_file("saves.csv", "long_hair,hair\n"), DictionaryKind::Implications).unwrap();
+        assert_eq!(dictionaries.apply("long hair", ApplyStage::Generate).unwrap().caption, "long hair, hair");
+
+        let saved = dictionaries.apply("long hair", ApplyStage::Save).unwrap();
+        assert_eq!(saved.caption, "long hair");
+        assert!(saved.implied.is_empty());
+    }
+
+    #[test]
+    fn csv_rows_honour_quotes() {
+        assert_eq!(parse_csv_row(r#"a,"b, c","say ""hi""",,"#), ["a", "b, c", "say \"hi\"", "", ""]);
+    }
+}
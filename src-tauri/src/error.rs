@@ -0,0 +1,135 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::providers::ProviderKind;
+
+// Machine-readable error category, so the UI can react to the kind of
+// failure instead of matching on message text
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    PermissionDenied,
+    Io,
+    InvalidArgument,
+    Unsupported,
+    // The operation would clobber something else, e.g. two images sharing a caption file
+    Conflict,
+    UnsupportedFormat,
+    InvalidImage,
+    ImageTooSmall,
+    ImageTooLarge,
+    MissingApiKey,
+    AuthFailed,
+    RateLimited,
+    // The provider refused the request itself (bad parameters, unknown model)
+    ProviderRejected,
+    // The provider couldn't be reached or is overloaded
+    ProviderUnavailable,
+    Timeout,
+    // The provider answered with something we couldn't use
+    ProviderError,
+    Cancelled,
+    Internal,
+}
+
+// Which provider an error came from and what it said about retrying
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProviderErrorDetails {
+    pub provider: ProviderKind,
+    // HTTP status, when the provider answered at all
+    pub status: Option<u16>,
+    pub retryable: bool,
+    pub retry_after_ms: Option<u64>,
+}
+
+// Error returned by every fallible command. `message` is meant for people;
+// `code`, `path` and `provider` are for the UI to act on.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub message: String,
+    // The file or directory the error is about, if any
+    pub path: Option<String>,
+    pub provider: Option<ProviderErrorDetails>,
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        AppError { code, message: message.into(), path: None, provider: None }
+    }
+
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_string_lossy().to_string());
+        self
+    }
+
+    pub fn not_found(path: &Path) -> Self {
+        AppError::new(ErrorCode::NotFound, format!("File not found: {}", path.display())).with_path(path)
+    }
+
+    // An I/O failure on `path`, categorised by what went wrong
+    pub fn io(context: &str, path: &Path, error: io::Error) -> Self {
+        let code = match error.kind() {
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            _ => ErrorCode::Io,
+        };
+        AppError::new(code, format!("{} {}: {}", context, path.display(), error)).with_path(path)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        AppError::new(ErrorCode::InvalidArgument, message)
+    }
+
+    pub fn cancelled() -> Self {
+        AppError::new(ErrorCode::Cancelled, crate::providers::stream::CANCELLED)
+    }
+
+    pub fn provider(provider: ProviderKind, code: ErrorCode, message: impl Into<String>) -> Self {
+        AppError {
+            provider: Some(ProviderErrorDetails { provider, status: None, retryable: false, retry_after_ms: None }),
+            ..AppError::new(code, message)
+        }
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        if let Some(details) = &mut self.provider {
+            details.status = Some(status);
+        }
+        self
+    }
+
+    pub fn retryable(mut self, retry_after: Option<Duration>) -> Self {
+        if let Some(details) = &mut self.provider {
+            details.retryable = true;
+            details.retry_after_ms = retry_after.map(|after| after.as_millis() as u64);
+        }
+        self
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.provider.as_ref().is_some_and(|details| details.retryable)
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.provider.as_ref()?.retry_after_ms.map(Duration::from_millis)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// Internal helpers that still report plain strings (index, journal) surface
+// as internal errors
+impl From<String> for AppError {
+    fn from(message: String) -> Self {
+        AppError::new(ErrorCode::Internal, message)
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::captions::{self, now_millis, SyncMode};
+use crate::error::AppError;
+use crate::tags::{self, TagFormat};
+
+// Stripped items kept in memory for `get_filter_log`
+const LOG_LIMIT: usize = 1000;
+
+// A tag name (compared the way dedupe compares tags) or, with `regex`, a
+// case-insensitive regular expression
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FilterRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+}
+
+// Rules for one project (dataset root). Blacklist and whitelist work on the
+// tags of a caption; phrases are cut out of the text itself, so they also
+// clean up natural-language captions.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CaptionFilters {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub blacklist: Vec<FilterRule>,
+    // When not empty, only tags matching one of these rules are kept
+    #[serde(default)]
+    pub whitelist: Vec<FilterRule>,
+    // Text such as "the image shows"; plain phrases match whole words,
+    // ignoring case
+    #[serde(default)]
+    pub phrases: Vec<FilterRule>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for CaptionFilters {
+    fn default() -> Self {
+        CaptionFilters { enabled: true, blacklist: Vec::new(), whitelist: Vec::new(), phrases: Vec::new() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterReason {
+    Blacklisted,
+    NotWhitelisted,
+    Phrase,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StrippedItem {
+    pub text: String,
+    pub reason: FilterReason,
+    // The pattern of the rule that matched
+    pub rule: Option<String>,
+}
+
+// What the filters did to one caption
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilterReport {
+    pub caption: String,
+    pub stripped: Vec<StrippedItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilterLogEntry {
+    pub image_path: String,
+    pub project: String,
+    pub stripped: Vec<StrippedItem>,
+    pub at: u64,
+}
+
+enum Matcher {
+    Tag(String),
+    Regex(Regex),
+}
+
+struct CompiledRule {
+    pattern: String,
+    matcher: Matcher,
+}
+
+impl CompiledRule {
+    fn new(rule: &FilterRule) -> Result<Self, AppError> {
+        let matcher = if rule.regex {
+            let regex = RegexBuilder::new(&rule.pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| AppError::invalid(format!("Invalid filter pattern {:?}: {}", rule.pattern, e)))?;
+            Matcher::Regex(regex)
+        } else {
+            let key = tags::tag_key(&rule.pattern);
+            if key.is_empty() {
+                return Err(AppError::invalid("Filter rules can't be empty"));
+            }
+            Matcher::Tag(key)
+        };
+        Ok(CompiledRule { pattern: rule.pattern.clone(), matcher })
+    }
+
+    fn matches_tag(&self, name: &str) -> bool {
+        match &self.matcher {
+            Matcher::Tag(key) => *key == tags::tag_key(name),
+            Matcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+// A project's filters with their patterns compiled
+struct CompiledFilters {
+    filters: CaptionFilters,
+    blacklist: Vec<CompiledRule>,
+    whitelist: Vec<CompiledRule>,
+    phrases: Vec<Regex>,
+}
+
+impl CompiledFilters {
+    fn new(filters: CaptionFilters) -> Result<Self, AppError> {
+        let compile = |rules: &[FilterRule]| rules.iter().map(CompiledRule::new).collect::<Result<Vec<_>, _>>();
+        let blacklist = compile(&filters.blacklist)?;
+        let whitelist = compile(&filters.whitelist)?;
+        let phrases = filters
+            .phrases
+            .iter()
+            .map(|rule| {
+                let pattern = if rule.regex { rule.pattern.clone() } else { phrase_pattern(rule.pattern.trim()) };
+                if pattern.is_empty() {
+                    return Err(AppError::invalid("Filter phrases can't be empty"));
+                }
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| AppError::invalid(format!("Invalid filter pattern {:?}: {}", rule.pattern, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(CompiledFilters { filters, blacklist, whitelist, phrases })
+    }
+
+    fn apply(&self, caption: &str) -> FilterReport {
+        let mut stripped = Vec::new();
+        if !self.filters.enabled {
+            return FilterReport { caption: caption.to_string(), stripped };
+        }
+
+        let mut text = caption.to_string();
+        for (rule, regex) in self.filters.phrases.iter().zip(&self.phrases) {
+            let mut found = Vec::new();
+            text = cut(&text, regex, &mut found);
+            stripped.extend(
+                found.into_iter().map(|text| StrippedItem { text, reason: FilterReason::Phrase, rule: Some(rule.pattern.clone()) }),
+            );
+        }
+
+        if self.blacklist.is_empty() && self.whitelist.is_empty() {
+            return FilterReport { caption: text, stripped };
+        }
+
+        // Tag lists are only rewritten when a tag is actually dropped, so
+        // captions without unwanted tags keep their exact formatting
+        let mut kept = Vec::new();
+        let mut dropped = false;
+        for tag in tags::parse(&text) {
+            if let Some(rule) = self.blacklist.iter().find(|rule| rule.matches_tag(&tag.name)) {
+                stripped.push(StrippedItem { text: tag.name, reason: FilterReason::Blacklisted, rule: Some(rule.pattern.clone()) });
+                dropped = true;
+            } else if !self.whitelist.is_empty() && !self.whitelist.iter().any(|rule| rule.matches_tag(&tag.name)) {
+                stripped.push(StrippedItem { text: tag.name, reason: FilterReason::NotWhitelisted, rule: None });
+                dropped = true;
+            } else {
+                kept.push(tag);
+            }
+        }
+        if dropped {
+            let format = TagFormat { dedupe: false, escape_parentheses: text.contains("\\("), ..TagFormat::default() };
+            text = tags::serialize(&kept, &format);
+        }
+        FilterReport { caption: text, stripped }
+    }
+}
+
+// A plain phrase as a regex matching it as whole words. Ends that aren't
+// word characters (e.g. a trailing ':') get no boundary, which would
+// otherwise require a word to follow.
+fn phrase_pattern(phrase: &str) -> String {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if is_word(phrase.chars().next()) { r"\b" } else { "" };
+    let end = if is_word(phrase.chars().next_back()) { r"\b" } else { "" };
+    format!("{}{}{}", start, regex::escape(phrase), end)
+}
+
+// Remove every match of `regex` from `text`, collecting the removed text.
+// Only the joins left by each cut are cleaned up: the spaces around it,
+// punctuation left dangling, and a capital letter when a sentence's opening
+// words were removed. The rest of the caption keeps its exact formatting.
+fn cut(text: &str, regex: &Regex, found: &mut Vec<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    // Set while a cut is waiting to be joined to the text after it; true
+    // when that text should start with a capital letter
+    let mut pending = None;
+    for m in regex.find_iter(text).filter(|m| !m.is_empty()) {
+        push_segment(&mut out, &text[last..m.start()], &mut pending);
+        if pending.is_none() {
+            pending = Some(at_sentence_start(&out) && m.as_str().starts_with(char::is_uppercase));
+        }
+        found.push(m.as_str().to_string());
+        last = m.end();
+    }
+    push_segment(&mut out, &text[last..], &mut pending);
+    if pending.is_some() {
+        // Cut at the very end, e.g. "a cat, the image shows"
+        let trimmed = out.trim_end_matches([' ', '\t', ',', ';', ':']).len();
+        out.truncate(trimmed);
+    }
+    out
+}
+
+fn push_segment(out: &mut String, segment: &str, pending: &mut Option<bool>) {
+    let Some(capitalize) = *pending else {
+        out.push_str(segment);
+        return;
+    };
+    let trimmed = out.trim_end_matches([' ', '\t']).len();
+    out.truncate(trimmed);
+
+    let mut segment = segment.trim_start_matches([' ', '\t']);
+    let left = out.chars().next_back();
+    let line_start = matches!(left, None | Some('\n'));
+    if segment.starts_with([',', ';', ':', '.']) && (line_start || matches!(left, Some(',' | ';' | ':'))) {
+        segment = segment[1..].trim_start_matches([' ', '\t']);
+    }
+    if segment.is_empty() {
+        return;
+    }
+
+    if !line_start && !segment.starts_with(['\n', '\r', ',', ';', ':', '.', '!', '?']) {
+        out.push(' ');
+    }
+    let mut chars = segment.chars();
+    match chars.next() {
+        Some(first) if capitalize => out.extend(first.to_uppercase().chain(chars)),
+        _ => out.push_str(segment),
+    }
+    *pending = None;
+}
+
+fn at_sentence_start(text: &str) -> bool {
+    let text = text.trim_end_matches([' ', '\t']);
+    text.is_empty() || text.ends_with(['\n', '.', '!', '?'])
+}
+
+// Filters are keyed by the canonical project path, so `C:\data` and
+// `C:\data\` (or a path through a symlink) share one set of rules.
+// Paths that can't be resolved are only cleaned up lexically.
+fn project_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.components().filter(|c| !matches!(c, Component::CurDir)).collect())
+}
+
+// Filters for every project, keyed by dataset root and saved as JSON in app data
+#[derive(Default)]
+pub struct FilterStore {
+    path: Option<PathBuf>,
+    projects: Mutex<HashMap<PathBuf, CompiledFilters>>,
+    log: Mutex<VecDeque<FilterLogEntry>>,
+}
+
+impl FilterStore {
+    // Load saved filters from `path`; a missing file means no filters yet
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let saved: HashMap<PathBuf, CaptionFilters> = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+
+        let mut projects = HashMap::new();
+        for (project, filters) in saved {
+            match CompiledFilters::new(filters) {
+                Ok(compiled) => {
+                    projects.insert(project_key(&project), compiled);
+                }
+                Err(e) => println!("Ignoring caption filters for {}: {}", project.display(), e),
+            }
+        }
+        Ok(FilterStore { path: Some(path), projects: Mutex::new(projects), log: Mutex::default() })
+    }
+
+    pub fn get(&self, project: &Path) -> CaptionFilters {
+        self.projects
+            .lock()
+            .ok()
+            .and_then(|projects| projects.get(&project_key(project)).map(|compiled| compiled.filters.clone()))
+            .unwrap_or_default()
+    }
+
+    pub fn set(&self, project: &Path, filters: CaptionFilters) -> Result<(), AppError> {
+        let compiled = CompiledFilters::new(filters)?;
+        let mut projects = self.projects.lock().map_err(|_| "Caption filters lock poisoned".to_string())?;
+        projects.insert(project_key(project), compiled);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let saved: HashMap<&PathBuf, &CaptionFilters> =
+            projects.iter().map(|(project, compiled)| (project, &compiled.filters)).collect();
+        let json = serde_json::to_vec_pretty(&saved).map_err(|e| format!("Failed to encode caption filters: {}", e))?;
+        captions::write_atomic(path, &json, SyncMode::File).map_err(|e| AppError::io("Failed to save caption filters to", path, e))
+    }
+
+    // Run `filters` over a caption without saving them, for previews
+    pub fn test(filters: CaptionFilters, caption: &str) -> Result<FilterReport, AppError> {
+        Ok(CompiledFilters::new(filters)?.apply(caption))
+    }
+
+    // Filter a generated caption with the rules of the project the image is
+    // in (the innermost one if projects are nested), logging what was
+    // stripped. None when no project has filters for the image.
+    pub fn apply(&self, image_path: &Path, caption: &str) -> Option<FilterReport> {
+        let key = project_key(image_path);
+        let projects = self.projects.lock().ok()?;
+        let (project, compiled) = projects
+            .iter()
+            .filter(|(project, _)| key.starts_with(project))
+            .max_by_key(|(project, _)| project.components().count())?;
+        let report = compiled.apply(caption);
+        if report.stripped.is_empty() {
+            return Some(report);
+        }
+
+        let items: Vec<&str> = report.stripped.iter().map(|item| item.text.as_str()).collect();
+        println!("Filtered from {}: {}", image_path.display(), items.join(" | "));
+        if let Ok(mut log) = self.log.lock() {
+            if log.len() == LOG_LIMIT {
+                log.pop_front();
+            }
+            log.push_back(FilterLogEntry {
+                image_path: image_path.to_string_lossy().to_string(),
+                project: project.to_string_lossy().to_string(),
+                stripped: report.stripped.clone(),
+                at: now_millis(),
+            });
+        }
+        Some(report)
+    }
+
+    // Most recent log entries first
+    pub fn log(&self, limit: usize) -> Vec<FilterLogEntry> {
+        self.log.lock().map(|log| log.iter().rev().take(limit).cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn clear_log(&self) {
+        if let Ok(mut log) = self.log.lock() {
+            log.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str) -> FilterRule {
+        FilterRule { pattern: pattern.to_string(), regex: false }
+    }
+
+    fn regex(pattern: &str) -> FilterRule {
+        FilterRule { pattern: pattern.to_string(), regex: true }
+    }
+
+    fn filtered(filters: CaptionFilters, caption: &str) -> String {
+        FilterStore::test(filters, caption).unwrap().caption
+    }
+
+    #[test]
+    fn phrases_match_whole_words_only() {
+        let filters = CaptionFilters { phrases: vec![rule("cat")], ..CaptionFilters::default() };
+        assert_eq!(filtered(filters.clone(), "a catalog, cat, dog"), "a catalog, dog");
+        assert_eq!(filtered(filters, "Concatenate"), "Concatenate");
+
+        let filters = CaptionFilters { phrases: vec![rule("Caption:")], ..CaptionFilters::default() };
+        assert_eq!(filtered(filters.clone(), "Caption: a dog on a beach"), "A dog on a beach");
+        assert_eq!(filtered(filters, "caption: a dog on a beach"), "a dog on a beach");
+    }
+
+    #[test]
+    fn phrases_tidy_up_where_they_were_cut() {
+        let filters = CaptionFilters { phrases: vec![rule("the image shows")], ..CaptionFilters::default() };
+        assert_eq!(filtered(filters.clone(), "tag1, the image shows, tag2"), "tag1, tag2");
+        assert_eq!(filtered(filters.clone(), "The image shows a dog.  It runs."), "A dog.  It runs.");
+        assert_eq!(filtered(filters, "A dog, the image shows"), "A dog");
+    }
+
+    #[test]
+    fn blacklist_and_whitelist_work_on_tags() {
+        let filters = CaptionFilters { blacklist: vec![rule("watermark"), regex("^artist:")], ..CaptionFilters::default() };
+        let report = FilterStore::test(filters, "1girl, Watermark, artist:someone, (smile:1.2)").unwrap();
+        assert_eq!(report.caption, "1girl, (smile:1.2)");
+        assert_eq!(report.stripped.len(), 2);
+        assert!(report.stripped.iter().all(|item| item.reason == FilterReason::Blacklisted));
+
+        let filters = CaptionFilters { whitelist: vec![rule("1girl"), regex("hair$")], ..CaptionFilters::default() };
+        assert_eq!(filtered(filters, "1girl, long_hair, smile"), "1girl, long_hair");
+    }
+
+    #[test]
+    fn untouched_tag_lists_keep_their_formatting() {
+        let filters = CaptionFilters { blacklist: vec![rule("watermark")], ..CaptionFilters::default() };
+        assert_eq!(filtered(filters, "1girl,smile ,  solo"), "1girl,smile ,  solo");
+    }
+
+    #[test]
+    fn disabled_filters_change_nothing() {
+        let filters = CaptionFilters { enabled: false, blacklist: vec![rule("watermark")], ..CaptionFilters::default() };
+        assert_eq!(filtered(filters, "watermark"), "watermark");
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let filters = CaptionFilters { blacklist: vec![regex("(unclosed")], ..CaptionFilters::default() };
+        assert!(FilterStore::test(filters, "").is_err());
+        let filters = CaptionFilters { phrases: vec![rule("  ")], ..CaptionFilters::default() };
+        assert!(FilterStore::test(filters, "").is_err());
+    }
+
+    #[test]
+    fn projects_are_matched_by_canonical_path() {
+        let root = std::env::temp_dir().join(format!("tagmeister-filters-{}", std::process::id()));
+        let nested = root.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+
+        let store = FilterStore::default();
+        let outer = CaptionFilters { blacklist: vec![rule("outer")], ..CaptionFilters::default() };
+        let inner = CaptionFilters { blacklist: vec![rule("inner")], ..CaptionFilters::default() };
+        store.set(&root.join("."), outer.clone()).unwrap();
+        store.set(&nested, inner).unwrap();
+        assert_eq!(store.get(&root), outer);
+
+        let report = store.apply(&nested.join("a.png"), "outer, inner").unwrap();
+        assert_eq!(report.caption, "outer");
+        assert_eq!(store.log(10)[0].project, fs::canonicalize(&nested).unwrap().to_string_lossy());
+        let report = store.apply(&root.join("a.png"), "outer, inner").unwrap();
+        assert_eq!(report.caption, "inner");
+        assert!(store.apply(&std::env::temp_dir().join("elsewhere.png"), "outer").is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
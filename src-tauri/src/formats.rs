@@ -0,0 +1,189 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use image::ImageFormat;
+
+// Everything we know about an image format in one place, so directory scanning,
+// validation and base64 encoding all agree on what counts as an image.
+pub struct FormatSpec {
+    pub format: ImageFormat,
+    pub name: &'static str,
+    pub mime_type: &'static str,
+    pub extensions: &'static [&'static str],
+}
+
+pub const FORMATS: &[FormatSpec] = &[
+    FormatSpec { format: ImageFormat::Jpeg, name: "jpeg", mime_type: "image/jpeg", extensions: &["jpg", "jpeg", "jpe", "jfif"] },
+    FormatSpec { format: ImageFormat::Png, name: "png", mime_type: "image/png", extensions: &["png"] },
+    FormatSpec { format: ImageFormat::Gif, name: "gif", mime_type: "image/gif", extensions: &["gif"] },
+    FormatSpec { format: ImageFormat::WebP, name: "webp", mime_type: "image/webp", extensions: &["webp"] },
+    FormatSpec { format: ImageFormat::Bmp, name: "bmp", mime_type: "image/bmp", extensions: &["bmp"] },
+    FormatSpec { format: ImageFormat::Tiff, name: "tiff", mime_type: "image/tiff", extensions: &["tif", "tiff"] },
+    FormatSpec { format: ImageFormat::Avif, name: "avif", mime_type: "image/avif", extensions: &["avif"] },
+    FormatSpec { format: ImageFormat::Pnm, name: "pnm", mime_type: "image/x-portable-anymap", extensions: &["pbm", "pgm", "ppm", "pam", "pnm"] },
+    FormatSpec { format: ImageFormat::Tga, name: "tga", mime_type: "image/x-tga", extensions: &["tga"] },
+    FormatSpec { format: ImageFormat::Dds, name: "dds", mime_type: "image/vnd.ms-dds", extensions: &["dds"] },
+    FormatSpec { format: ImageFormat::Ico, name: "ico", mime_type: "image/x-icon", extensions: &["ico"] },
+    FormatSpec { format: ImageFormat::Hdr, name: "hdr", mime_type: "image/vnd.radiance", extensions: &["hdr"] },
+    FormatSpec { format: ImageFormat::OpenExr, name: "exr", mime_type: "image/x-exr", extensions: &["exr"] },
+    FormatSpec { format: ImageFormat::Farbfeld, name: "farbfeld", mime_type: "image/farbfeld", extensions: &["ff", "farbfeld"] },
+    FormatSpec { format: ImageFormat::Qoi, name: "qoi", mime_type: "image/x-qoi", extensions: &["qoi"] },
+];
+
+// Number of header bytes needed to recognise any format image can sniff
+const SNIFF_LEN: usize = 64;
+
+pub fn spec_for(format: ImageFormat) -> Option<&'static FormatSpec> {
+    FORMATS.iter().find(|spec| spec.format == format)
+}
+
+pub fn spec_by_name(name: &str) -> Option<&'static FormatSpec> {
+    FORMATS.iter().find(|spec| spec.name == name)
+}
+
+// Look up the format a path claims to be by its extension
+pub fn spec_for_path(path: &Path) -> Option<&'static FormatSpec> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    FORMATS.iter().find(|spec| spec.extensions.contains(&ext.as_str()))
+}
+
+pub fn mime_type(format: ImageFormat) -> &'static str {
+    spec_for(format).map(|spec| spec.mime_type).unwrap_or("application/octet-stream")
+}
+
+pub fn format_name(format: ImageFormat) -> &'static str {
+    spec_for(format).map(|spec| spec.name).unwrap_or("unknown")
+}
+
+// Whether the image crate was built with a decoder for this format
+pub fn can_decode(format: ImageFormat) -> bool {
+    match format {
+        // reading_enabled() reports AVIF as readable whenever the encoder is on,
+        // but decoding needs the separate dav1d-backed feature
+        ImageFormat::Avif => false,
+        _ => format.reading_enabled(),
+    }
+}
+
+// Detect a file's real format from its leading bytes
+pub fn sniff_format(path: &Path) -> std::io::Result<Option<ImageFormat>> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    fs::File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+    Ok(image::guess_format(&header).ok())
+}
+
+// What a file on disk turned out to be
+pub enum Detection {
+    // A decodable image; `claimed` is the format its extension suggests, if any
+    Image { format: ImageFormat, claimed: Option<ImageFormat> },
+    // A recognised image format we have no decoder for
+    Unsupported { format: ImageFormat, claimed: Option<ImageFormat> },
+    // Has an image extension but the contents don't match any known format
+    Unrecognized { claimed: ImageFormat },
+    NotImage,
+}
+
+impl Detection {
+    // Stable name used when persisting a detection in the dataset index
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Detection::Image { .. } => "image",
+            Detection::Unsupported { .. } => "unsupported",
+            Detection::Unrecognized { .. } => "unrecognized",
+            Detection::NotImage => "not_image",
+        }
+    }
+
+    pub fn format(&self) -> Option<ImageFormat> {
+        match self {
+            Detection::Image { format, .. } | Detection::Unsupported { format, .. } => Some(*format),
+            _ => None,
+        }
+    }
+
+    pub fn claimed(&self) -> Option<ImageFormat> {
+        match self {
+            Detection::Image { claimed, .. } | Detection::Unsupported { claimed, .. } => *claimed,
+            Detection::Unrecognized { claimed } => Some(*claimed),
+            Detection::NotImage => None,
+        }
+    }
+
+    // Rebuild a detection from the parts stored by `kind`, `format` and `claimed`
+    pub fn from_parts(kind: &str, format: Option<&str>, claimed: Option<&str>) -> Detection {
+        let format = format.and_then(spec_by_name).map(|spec| spec.format);
+        let claimed = claimed.and_then(spec_by_name).map(|spec| spec.format);
+        match (kind, format, claimed) {
+            ("image", Some(format), claimed) => Detection::Image { format, claimed },
+            ("unsupported", Some(format), claimed) => Detection::Unsupported { format, claimed },
+            ("unrecognized", _, Some(claimed)) => Detection::Unrecognized { claimed },
+            _ => Detection::NotImage,
+        }
+    }
+}
+
+pub fn detect(path: &Path) -> Detection {
+    let claimed = spec_for_path(path).map(|spec| spec.format);
+
+    // Unreadable files are treated as if they had no recognisable header
+    let sniffed = sniff_format(path).unwrap_or_default();
+
+    match (sniffed, claimed) {
+        (Some(format), claimed) if can_decode(format) => Detection::Image { format, claimed },
+        (Some(format), claimed) => Detection::Unsupported { format, claimed },
+        // TGA has no magic number, so the extension is all we have to go on
+        (None, Some(ImageFormat::Tga)) if can_decode(ImageFormat::Tga) => Detection::Image {
+            format: ImageFormat::Tga,
+            claimed: Some(ImageFormat::Tga),
+        },
+        (None, Some(claimed)) => Detection::Unrecognized { claimed },
+        (None, None) => Detection::NotImage,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection_trusts_the_header_over_the_extension() {
+        let dir = std::env::temp_dir().join(format!("tagmeister-formats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("photo.png");
+        image::RgbImage::new(2, 2).save(&png).unwrap();
+        let bytes = fs::read(&png).unwrap();
+        for name in ["mislabeled.JPG", "no_extension"] {
+            fs::write(dir.join(name), &bytes).unwrap();
+        }
+        fs::write(dir.join("fake.png"), "not an image").unwrap();
+        fs::write(dir.join("notes.txt"), "not an image").unwrap();
+        fs::write(dir.join("photo.avif"), b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf").unwrap();
+
+        assert!(matches!(detect(&png), Detection::Image { format: ImageFormat::Png, claimed: Some(ImageFormat::Png) }));
+        assert!(matches!(detect(&dir.join("mislabeled.JPG")), Detection::Image { format: ImageFormat::Png, claimed: Some(ImageFormat::Jpeg) }));
+        assert!(matches!(detect(&dir.join("no_extension")), Detection::Image { format: ImageFormat::Png, claimed: None }));
+        assert!(matches!(detect(&dir.join("fake.png")), Detection::Unrecognized { claimed: ImageFormat::Png }));
+        assert!(matches!(detect(&dir.join("notes.txt")), Detection::NotImage));
+        assert!(matches!(detect(&dir.join("photo.avif")), Detection::Unsupported { format: ImageFormat::Avif, .. }));
+        assert!(matches!(detect(&dir.join("missing.png")), Detection::Unrecognized { .. }));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detections_survive_the_index_round_trip() {
+        let detection = Detection::Image { format: ImageFormat::Png, claimed: Some(ImageFormat::Jpeg) };
+        let stored = (detection.kind(), detection.format().map(format_name), detection.claimed().map(format_name));
+        assert_eq!(stored, ("image", Some("png"), Some("jpeg")));
+        let restored = Detection::from_parts(stored.0, stored.1, stored.2);
+        assert!(matches!(restored, Detection::Image { format: ImageFormat::Png, claimed: Some(ImageFormat::Jpeg) }));
+        assert!(matches!(Detection::from_parts("image", None, None), Detection::NotImage));
+    }
+
+    #[test]
+    fn extensions_map_to_formats_ignoring_case() {
+        assert_eq!(spec_for_path(Path::new("a.JPEG")).map(|spec| spec.name), Some("jpeg"));
+        assert_eq!(spec_for_path(Path::new("a.tif")).map(|spec| spec.mime_type), Some("image/tiff"));
+        assert!(spec_for_path(Path::new("a.txt")).is_none());
+        assert!(spec_for_path(Path::new("png")).is_none());
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::bulk::TagOperation;
+use crate::captions::{self, now_millis, CaptionWriter, SaveOutcome, SaveResult};
+use crate::error::{AppError, ErrorCode};
+use crate::providers::ProviderKind;
+
+// Saves kept per caption file; older ones are pruned as new ones come in
+const MAX_VERSIONS_PER_CAPTION: i64 = 100;
+
+// Token count past which a diff gives up on alignment and reports a plain
+// replacement, to bound the quadratic table
+const MAX_DIFF_TOKENS: usize = 4_000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS versions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        caption_path TEXT NOT NULL,
+        image_path TEXT NOT NULL,
+        previous TEXT,
+        text TEXT,
+        source TEXT NOT NULL,
+        batch_id TEXT,
+        saved_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS versions_caption ON versions (caption_path, id);
+    CREATE INDEX IF NOT EXISTS versions_batch ON versions (batch_id);
+";
+
+// Who or what produced a caption, recorded with every save
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CaptionSource {
+    #[default]
+    Manual,
+    Model {
+        provider: ProviderKind,
+        model: String,
+        prompt: Option<String>,
+    },
+    // Put back from an earlier version
+    Restore { version: i64 },
+    // Undo of a whole batch
+    Rollback { batch_id: String },
+    BulkEdit { operations: Vec<TagOperation> },
+}
+
+// One save of one caption file. `previous` and `text` are None when the
+// caption file didn't exist before or after the save.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CaptionVersion {
+    pub id: i64,
+    pub caption_path: String,
+    pub image_path: String,
+    pub previous: Option<String>,
+    pub text: Option<String>,
+    pub source: CaptionSource,
+    // Groups the saves of one batch job or bulk edit so they can be undone together
+    pub batch_id: Option<String>,
+    // Milliseconds since the Unix epoch
+    pub saved_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchSummary {
+    pub batch_id: String,
+    pub source: CaptionSource,
+    pub caption_count: usize,
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiffSegment {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+// Every caption save, kept in the app data directory so overwritten captions
+// can be compared and brought back
+pub struct CaptionHistory {
+    conn: Mutex<Connection>,
+}
+
+impl CaptionHistory {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create history directory: {}", e))?;
+        }
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open caption history: {}", e))?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(|e| format!("Failed to configure caption history: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    // A throwaway history for when the on-disk one can't be opened
+    pub fn in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory().map_err(|e| format!("Failed to open caption history: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create caption history schema: {}", e))?;
+        Ok(CaptionHistory { conn: Mutex::new(conn) })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.conn.lock().map_err(|_| "Caption history lock poisoned".to_string())
+    }
+
+    // Record one save and return its version id
+    pub fn record(
+        &self,
+        caption_path: &Path,
+        image_path: &Path,
+        previous: Option<&str>,
+        text: Option<&str>,
+        source: &CaptionSource,
+        batch_id: Option<&str>,
+    ) -> Result<i64, String> {
+        let caption_path = caption_path.to_string_lossy();
+        let source = serde_json::to_string(source).map_err(|e| format!("Failed to encode caption source: {}", e))?;
+        let conn = self.lock()?;
+        conn.execute(
+            "INSERT INTO versions (caption_path, image_path, previous, text, source, batch_id, saved_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![caption_path, image_path.to_string_lossy(), previous, text, source, batch_id, now_millis() as i64],
+        )
+        .map_err(|e| format!("Failed to record caption history for {}: {}", caption_path, e))?;
+        let id = conn.last_insert_rowid();
+
+        conn.execute(
+            "DELETE FROM versions WHERE caption_path = ?1 AND id NOT IN
+             (SELECT id FROM versions WHERE caption_path = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![caption_path, MAX_VERSIONS_PER_CAPTION],
+        )
+        .map_err(|e| format!("Failed to prune caption history for {}: {}", caption_path, e))?;
+        Ok(id)
+    }
+
+    // Saves of one caption file, newest first
+    pub fn versions(&self, caption_path: &Path) -> Result<Vec<CaptionVersion>, String> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare(&format!("{} WHERE caption_path = ?1 ORDER BY id DESC", SELECT_VERSION))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![caption_path.to_string_lossy()], version_from_row)
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| format!("Failed to read caption history: {}", e))
+    }
+
+    pub fn version(&self, id: i64) -> Result<Option<CaptionVersion>, String> {
+        let conn = self.lock()?;
+        conn.query_row(&format!("{} WHERE id = ?1", SELECT_VERSION), params![id], version_from_row)
+            .optional()
+            .map_err(|e| format!("Failed to read caption history: {}", e))
+    }
+
+    // Saves made by one batch, oldest first
+    pub fn batch(&self, batch_id: &str) -> Result<Vec<CaptionVersion>, String> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare(&format!("{} WHERE batch_id = ?1 ORDER BY id", SELECT_VERSION))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![batch_id], version_from_row).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| format!("Failed to read caption history: {}", e))
+    }
+
+    // The most recent batches, newest first
+    pub fn batches(&self, limit: usize) -> Result<Vec<BatchSummary>, String> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT batch.batch_id, first.source, batch.captions, batch.started, batch.finished
+                 FROM (
+                     SELECT batch_id, MIN(id) AS first_id, MAX(id) AS last_id, COUNT(DISTINCT caption_path) AS captions,
+                            MIN(saved_at) AS started, MAX(saved_at) AS finished
+                     FROM versions WHERE batch_id IS NOT NULL GROUP BY batch_id
+                 ) AS batch
+                 JOIN versions AS first ON first.id = batch.first_id
+                 ORDER BY batch.last_id DESC LIMIT ?1",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![limit as i64], |row| {
+                Ok(BatchSummary {
+                    batch_id: row.get(0)?,
+                    source: parse_source(&row.get::<_, String>(1)?),
+                    caption_count: row.get::<_, i64>(2)? as usize,
+                    started_at: row.get::<_, i64>(3)? as u64,
+                    finished_at: row.get::<_, i64>(4)? as u64,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| format!("Failed to read caption history: {}", e))
+    }
+}
+
+const SELECT_VERSION: &str =
+    "SELECT id, caption_path, image_path, previous, text, source, batch_id, saved_at FROM versions";
+
+fn version_from_row(row: &Row) -> rusqlite::Result<CaptionVersion> {
+    Ok(CaptionVersion {
+        id: row.get(0)?,
+        caption_path: row.get(1)?,
+        image_path: row.get(2)?,
+        previous: row.get(3)?,
+        text: row.get(4)?,
+        source: parse_source(&row.get::<_, String>(5)?),
+        batch_id: row.get(6)?,
+        saved_at: row.get::<_, i64>(7)? as u64,
+    })
+}
+
+// Sources written by a newer version of the app read as manual edits
+fn parse_source(json: &str) -> CaptionSource {
+    serde_json::from_str(json).unwrap_or_default()
+}
+
+// Word-level diff from `old` to `new`. Whitespace and punctuation are kept as
+// their own tokens, so joining the segments of either side gives back the
+// original text exactly.
+pub fn diff(old: &str, new: &str) -> Vec<DiffSegment> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+
+    let mut segments: Vec<DiffSegment> = Vec::new();
+    let mut push = |kind: DiffKind, text: &str| match segments.last_mut() {
+        Some(last) if last.kind == kind => last.text.push_str(text),
+        _ => segments.push(DiffSegment { kind, text: text.to_string() }),
+    };
+
+    if n * m > MAX_DIFF_TOKENS * MAX_DIFF_TOKENS / 4 {
+        if !old.is_empty() {
+            push(DiffKind::Delete, old);
+        }
+        if !new.is_empty() {
+            push(DiffKind::Insert, new);
+        }
+        return segments;
+    }
+
+    // Longest common subsequence lengths of every pair of suffixes
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_tokens[i] == new_tokens[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_tokens[i] == new_tokens[j] {
+            push(DiffKind::Equal, old_tokens[i]);
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            push(DiffKind::Delete, old_tokens[i]);
+            i += 1;
+        } else {
+            push(DiffKind::Insert, new_tokens[j]);
+            j += 1;
+        }
+    }
+    segments
+}
+
+// Split into runs of word characters, runs of whitespace, and single
+// punctuation characters
+fn tokenize(text: &str) -> Vec<&str> {
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            0
+        } else if c.is_whitespace() {
+            1
+        } else {
+            2
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<u8> = None;
+    for (index, c) in text.char_indices() {
+        let current = class(c);
+        if let Some(prev) = previous {
+            if prev != current || current == 2 {
+                tokens.push(&text[start..index]);
+                start = index;
+            }
+        }
+        previous = Some(current);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+// Put every caption a batch touched back to what it was before the batch.
+// Captions edited since the batch are left alone and reported as conflicts,
+// unless `force` is set.
+pub fn rollback_batch(writer: &CaptionWriter, batch_id: &str, force: bool) -> Result<Vec<SaveResult>, AppError> {
+    let versions = writer.history.batch(batch_id)?;
+    if versions.is_empty() {
+        return Err(AppError::new(ErrorCode::NotFound, format!("No captions were saved by batch {}", batch_id)));
+    }
+
+    // First and last save of each caption within the batch, in save order
+    let mut order: Vec<&str> = Vec::new();
+    let mut spans: HashMap<&str, (&CaptionVersion, &CaptionVersion)> = HashMap::new();
+    for version in &versions {
+        spans
+            .entry(version.caption_path.as_str())
+            .and_modify(|span| span.1 = version)
+            .or_insert_with(|| {
+                order.push(version.caption_path.as_str());
+                (version, version)
+            });
+    }
+
+    let source = CaptionSource::Rollback { batch_id: batch_id.to_string() };
+    let results = order
+        .into_iter()
+        .map(|caption_path| {
+            let (first, last) = spans[caption_path];
+            let image_path = Path::new(&first.image_path);
+            let current = fs::read(caption_path).ok().map(|bytes| captions::decode_caption(&bytes).0);
+            if !force && current != last.text {
+                return SaveResult {
+                    path: first.image_path.clone(),
+                    caption_path: Some(caption_path.to_string()),
+                    outcome: SaveOutcome::Failed,
+                    error: Some(
+                        AppError::new(
+                            ErrorCode::Conflict,
+                            format!("{} was changed after the batch; not rolling it back", caption_path),
+                        )
+                        .with_path(Path::new(caption_path)),
+                    ),
+                    version: None,
+                    dictionary: None,
+                };
+            }
+            writer.restore(image_path, Path::new(caption_path), first.previous.as_deref(), &source)
+        })
+        .collect();
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_report_the_source_of_their_first_save() {
+        let history = CaptionHistory::in_memory().unwrap();
+        let model = |prompt: &str| CaptionSource::Model {
+            provider: ProviderKind::Ollama,
+            model: "llava".to_string(),
+            prompt: Some(prompt.to_string()),
+        };
+        for (i, source) in [model("tags"), model("a sentence"), CaptionSource::Manual].iter().enumerate() {
+            let caption = format!("/data/{}.txt", i);
+            let image = format!("/data/{}.png", i);
+            history.record(Path::new(&caption), Path::new(&image), None, Some("cat"), source, Some("job-1")).unwrap();
+        }
+
+        let batches = history.batches(10).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].source, model("tags"));
+        assert_eq!(batches[0].caption_count, 3);
+    }
+
+    #[test]
+    fn diff_sides_join_back_to_the_originals() {
+        let (old, new) = ("a cat, sitting on a mat", "a black cat, on a mat.");
+        let segments = diff(old, new);
+        let side = |skip: DiffKind| segments.iter().filter(|s| s.kind != skip).map(|s| s.text.as_str()).collect::<String>();
+        assert_eq!(side(DiffKind::Insert), old);
+        assert_eq!(side(DiffKind::Delete), new);
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::captions::{self, CaptionCounts};
+
+// Bytes hashed from each end of a file when fingerprinting
+const FINGERPRINT_CHUNK: u64 = 64 * 1024;
+// Index writes per transaction during a scan
+const COMMIT_EVERY: usize = 500;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        folder TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        format TEXT,
+        claimed_format TEXT,
+        width INTEGER,
+        height INTEGER,
+        hash TEXT
+    );
+    CREATE INDEX IF NOT EXISTS files_folder ON files (folder);
+    CREATE TABLE IF NOT EXISTS captions (
+        path TEXT PRIMARY KEY,
+        folder TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        word_count INTEGER NOT NULL,
+        tag_count INTEGER NOT NULL,
+        is_empty INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS captions_folder ON captions (folder);
+";
+
+// Persistent cache of per-file scan results, kept in the app data directory.
+// Entries are only trusted while the file's size and mtime are unchanged.
+pub struct DatasetIndex {
+    conn: Mutex<Connection>,
+}
+
+// What we learned about a file the last time it was scanned
+#[derive(Debug, Clone)]
+pub struct IndexedFile {
+    pub size: u64,
+    pub modified: u64,
+    // "image", "unsupported", "unrecognized" or "not_image"
+    pub kind: String,
+    pub format: Option<String>,
+    pub claimed_format: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub hash: Option<String>,
+}
+
+impl DatasetIndex {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create index directory: {}", e))?;
+        }
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open index: {}", e))?;
+        // WAL keeps large scans from fsyncing on every row
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(|e| format!("Failed to configure index: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    // A throwaway index for when the on-disk one can't be opened
+    pub fn in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory().map_err(|e| format!("Failed to open index: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create index schema: {}", e))?;
+        Ok(DatasetIndex { conn: Mutex::new(conn) })
+    }
+
+    // Run `f` against the cache. Writes are batched into transactions of
+    // `COMMIT_EVERY` rows, and the connection is only locked for each
+    // lookup or write, so a long first scan neither holds one huge
+    // transaction nor blocks other scans until it finishes.
+    pub fn with_cache<T>(&self, f: impl FnOnce(&IndexCache) -> T) -> Result<T, String> {
+        let cache = IndexCache { index: self, writes: AtomicUsize::new(0) };
+        let result = f(&cache);
+        let conn = self.lock()?;
+        if !conn.is_autocommit() {
+            conn.execute_batch("COMMIT").map_err(|e| format!("Failed to commit index: {}", e))?;
+        }
+        Ok(result)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, String> {
+        self.conn.lock().map_err(|_| "Index lock poisoned".to_string())
+    }
+}
+
+pub struct IndexCache<'a> {
+    index: &'a DatasetIndex,
+    // Writes since the last commit
+    writes: AtomicUsize,
+}
+
+impl IndexCache<'_> {
+    fn read<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T, String> {
+        let conn = self.index.lock()?;
+        f(&conn).map_err(|e| e.to_string())
+    }
+
+    // Run a write inside the current chunk's transaction, committing once
+    // the chunk is full
+    fn write(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<()>) -> Result<(), String> {
+        let conn = self.index.lock()?;
+        if conn.is_autocommit() {
+            conn.execute_batch("BEGIN").map_err(|e| e.to_string())?;
+        }
+        f(&conn).map_err(|e| e.to_string())?;
+        if self.writes.fetch_add(1, Ordering::Relaxed) + 1 >= COMMIT_EVERY {
+            self.writes.store(0, Ordering::Relaxed);
+            conn.execute_batch("COMMIT").map_err(|e| format!("Failed to commit index: {}", e))?;
+        }
+        Ok(())
+    }
+
+    // Cached entry for `path`, if it is still valid for the given size and mtime
+    pub fn file(&self, path: &str, size: u64, modified: u64) -> Option<IndexedFile> {
+        let result = self.read(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT size, modified, kind, format, claimed_format, width, height, hash
+                 FROM files WHERE path = ?1",
+            )?;
+            stmt.query_row(params![path], |row| {
+                Ok(IndexedFile {
+                    size: row.get::<_, i64>(0)? as u64,
+                    modified: row.get::<_, i64>(1)? as u64,
+                    kind: row.get(2)?,
+                    format: row.get(3)?,
+                    claimed_format: row.get(4)?,
+                    width: row.get(5)?,
+                    height: row.get(6)?,
+                    hash: row.get(7)?,
+                })
+            })
+            .optional()
+        });
+
+        match result {
+            Ok(Some(entry)) if entry.size == size && entry.modified == modified => Some(entry),
+            Ok(_) => None,
+            Err(e) => {
+                println!("Index lookup failed for {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn put_file(&self, path: &str, folder: &str, entry: &IndexedFile) {
+        let result = self.write(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "INSERT OR REPLACE INTO files
+                 (path, folder, size, modified, kind, format, claimed_format, width, height, hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            stmt.execute(params![
+                path,
+                folder,
+                entry.size as i64,
+                entry.modified as i64,
+                entry.kind,
+                entry.format,
+                entry.claimed_format,
+                entry.width,
+                entry.height,
+                entry.hash,
+            ])?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            println!("Failed to update index for {}: {}", path, e);
+        }
+    }
+
+    pub fn caption(&self, path: &str, size: u64, modified: u64) -> Option<CaptionCounts> {
+        let result = self.read(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT word_count, tag_count, is_empty FROM captions
+                 WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            )?;
+            stmt.query_row(params![path, size as i64, modified as i64], |row| {
+                Ok(CaptionCounts {
+                    word_count: row.get::<_, i64>(0)? as usize,
+                    tag_count: row.get::<_, i64>(1)? as usize,
+                    is_empty: row.get(2)?,
+                })
+            })
+            .optional()
+        });
+
+        result.unwrap_or_else(|e| {
+            println!("Index lookup failed for {}: {}", path, e);
+            None
+        })
+    }
+
+    pub fn put_caption(&self, path: &str, folder: &str, size: u64, modified: u64, counts: &CaptionCounts) {
+        let result = self.write(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "INSERT OR REPLACE INTO captions (path, folder, size, modified, word_count, tag_count, is_empty)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            stmt.execute(params![
+                path,
+                folder,
+                size as i64,
+                modified as i64,
+                counts.word_count as i64,
+                counts.tag_count as i64,
+                counts.is_empty,
+            ])?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            println!("Failed to update index for {}: {}", path, e);
+        }
+    }
+
+    // Drop entries for files that have disappeared from a folder
+    pub fn prune_folder(&self, folder: &str, seen: &HashSet<String>) {
+        for table in ["files", "captions"] {
+            let select = format!("SELECT path FROM {} WHERE folder = ?1", table);
+            let stale = self.read(|conn| {
+                let mut stmt = conn.prepare_cached(&select)?;
+                let rows = stmt.query_map(params![folder], |row| row.get::<_, String>(0))?;
+                Ok(rows.flatten().filter(|path| !seen.contains(path)).collect::<Vec<_>>())
+            });
+            let Ok(stale) = stale else { continue };
+
+            let delete = format!("DELETE FROM {} WHERE path = ?1", table);
+            for path in stale {
+                let _ = self.write(|conn| conn.execute(&delete, params![path]).map(|_| ()));
+            }
+        }
+    }
+}
+
+// Cheap content fingerprint: blake3 over the file's size and mtime plus its
+// first and last 64 KiB, so multi-MB images aren't read whole. An edit that
+// keeps the size and both ends (e.g. retouching the middle of an uncompressed
+// BMP) is only caught through the mtime, and touching a file without changing
+// it gives it a new fingerprint.
+pub fn fingerprint(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    let size = metadata.len();
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&size.to_le_bytes());
+    hasher.update(&captions::modified_millis(&metadata).unwrap_or(0).to_le_bytes());
+
+    let mut buffer = Vec::with_capacity(FINGERPRINT_CHUNK as usize);
+    (&mut file).take(FINGERPRINT_CHUNK).read_to_end(&mut buffer)?;
+    hasher.update(&buffer);
+
+    if size > FINGERPRINT_CHUNK * 2 {
+        buffer.clear();
+        file.seek(SeekFrom::End(-(FINGERPRINT_CHUNK as i64)))?;
+        file.take(FINGERPRINT_CHUNK).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    } else if size > FINGERPRINT_CHUNK {
+        buffer.clear();
+        file.read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use super::*;
+
+    fn entry(size: u64) -> IndexedFile {
+        IndexedFile {
+            size,
+            modified: 1,
+            kind: "image".to_string(),
+            format: Some("png".to_string()),
+            claimed_format: Some("png".to_string()),
+            width: Some(2),
+            height: Some(2),
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn with_cache_commits_in_chunks() {
+        let index = DatasetIndex::in_memory().unwrap();
+        let written = index
+            .with_cache(|cache| {
+                for i in 0..COMMIT_EVERY + 10 {
+                    cache.put_file(&format!("/data/{}.png", i), "/data", &entry(i as u64));
+                }
+                // The first chunk was committed; the rest is still open
+                assert!(!index.lock().unwrap().is_autocommit());
+                cache.file("/data/3.png", 3, 1).is_some()
+            })
+            .unwrap();
+        assert!(written);
+        assert!(index.lock().unwrap().is_autocommit());
+
+        let seen: HashSet<String> = ["/data/1.png".to_string()].into();
+        index
+            .with_cache(|cache| {
+                cache.prune_folder("/data", &seen);
+                assert!(cache.file("/data/1.png", 1, 1).is_some());
+                assert!(cache.file("/data/2.png", 2, 1).is_none());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn fingerprint_changes_with_mtime() {
+        let path = std::env::temp_dir().join(format!("tagmeister-fingerprint-{}.bin", std::process::id()));
+        fs::write(&path, vec![7u8; 200 * 1024]).unwrap();
+        let file = fs::File::options().append(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+        let before = fingerprint(&path).unwrap();
+        assert_eq!(fingerprint(&path).unwrap(), before);
+
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000)).unwrap();
+        assert_ne!(fingerprint(&path).unwrap(), before);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,858 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+
+use crate::captions::{now_millis, CaptionWriter, NamingPolicy, SaveOptions};
+use crate::dictionary::{ApplyStage, DictionaryReport, TagDictionaries};
+use crate::error::{AppError, ErrorCode};
+use crate::filters::{FilterReport, FilterStore};
+use crate::history::{CaptionHistory, CaptionSource};
+use crate::journal::{self, Journal, JournalEntry};
+use crate::providers::limits::RateLimiter;
+use crate::providers::stream::{cancel_pair, CancelHandle, CancelToken};
+use crate::providers::{self, CaptionJob, GenerationOptions, ProviderKind};
+use crate::tags::{self, TagFormat};
+use crate::watcher::DatasetWatcher;
+
+// Per-item progress, emitted as each image starts, finishes or fails
+pub const JOB_PROGRESS_EVENT: &str = "caption-job-progress";
+// Whole-job state changes (started, paused, resumed, finished)
+pub const JOB_STATE_EVENT: &str = "caption-job-state";
+
+// Concurrency limits are shared by all jobs using a provider. Local servers
+// generally process one image at a time, so queueing more only adds latency.
+fn default_concurrency(provider: ProviderKind) -> usize {
+    match provider {
+        ProviderKind::Anthropic => 2,
+        ProviderKind::OpenAi => 4,
+        ProviderKind::Ollama | ProviderKind::LmStudio => 1,
+    }
+}
+
+// What to caption with and what to do with the result
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobConfig {
+    pub provider: ProviderKind,
+    pub model: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub options: GenerationOptions,
+    // Joined onto the generated caption with ", ", or merged as tags when
+    // the model answered with a tag list
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    // Write each caption next to its image as soon as it is generated
+    #[serde(default = "default_save")]
+    pub save: bool,
+    // How captions are written when `save` is on
+    #[serde(default)]
+    pub save_options: SaveOptions,
+}
+
+fn default_save() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Paused,
+    Completed,
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(self) -> bool {
+        matches!(self, JobState::Completed | JobState::Cancelled)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct JobItem {
+    pub path: String,
+    pub state: ItemState,
+    pub caption: Option<String>,
+    pub error: Option<AppError>,
+    pub duration_ms: Option<u64>,
+    // What the tag dictionaries and caption filters changed in the caption
+    // (not kept in the journal)
+    pub dictionary: Option<DictionaryReport>,
+    pub filtered: Option<FilterReport>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct JobProgress {
+    pub job_id: String,
+    pub index: usize,
+    pub item: JobItem,
+    pub completed: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+// Snapshot of a job; `items` is only filled in by `get_caption_job`
+#[derive(Debug, Serialize, Clone)]
+pub struct JobStatus {
+    pub id: String,
+    pub provider: ProviderKind,
+    pub model: String,
+    pub state: JobState,
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub items: Option<Vec<JobItem>>,
+}
+
+struct JobProgressState {
+    state: JobState,
+    items: Vec<JobItem>,
+    finished_at: Option<u64>,
+}
+
+struct Job {
+    id: String,
+    // Journaled as-is, so the API key is kept apart from it
+    config: JobConfig,
+    api_key: Mutex<Option<String>>,
+    created_at: u64,
+    progress: Mutex<JobProgressState>,
+    // Indices of items still waiting for a worker
+    queue: Mutex<VecDeque<usize>>,
+    paused: watch::Sender<bool>,
+    cancel: CancelHandle,
+    token: CancelToken,
+    workers: AtomicUsize,
+    journal: Option<Journal>,
+}
+
+impl Job {
+    fn status(&self, with_items: bool) -> JobStatus {
+        let progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        let count = |state: ItemState| progress.items.iter().filter(|item| item.state == state).count();
+        JobStatus {
+            id: self.id.clone(),
+            provider: self.config.provider,
+            model: self.config.model.clone(),
+            state: progress.state,
+            total: progress.items.len(),
+            completed: count(ItemState::Done),
+            failed: count(ItemState::Failed),
+            created_at: self.created_at,
+            finished_at: progress.finished_at,
+            items: with_items.then(|| progress.items.clone()),
+        }
+    }
+
+    // Update one item and build the progress event for it
+    fn update_item(&self, index: usize, update: impl FnOnce(&mut JobItem)) -> JobProgress {
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        update(&mut progress.items[index]);
+        let count = |state: ItemState| progress.items.iter().filter(|item| item.state == state).count();
+        JobProgress {
+            job_id: self.id.clone(),
+            index,
+            item: progress.items[index].clone(),
+            completed: count(ItemState::Done),
+            failed: count(ItemState::Failed),
+            total: progress.items.len(),
+        }
+    }
+
+    fn set_state(&self, state: JobState) -> bool {
+        let at = now_millis();
+        {
+            let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+            if progress.state.is_finished() || progress.state == state {
+                return false;
+            }
+            progress.state = state;
+            if state.is_finished() {
+                progress.finished_at = Some(at);
+            }
+        }
+        self.record(&JournalEntry::State { state, at });
+        true
+    }
+
+    // A failed journal write only costs resumability, so it doesn't stop the job
+    fn record(&self, entry: &JournalEntry) {
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.append(entry) {
+                println!("{}", e);
+            }
+        }
+    }
+
+    fn options(&self) -> GenerationOptions {
+        let mut options = self.config.options.clone();
+        options.api_key = self.api_key.lock().ok().and_then(|key| key.clone());
+        options
+    }
+
+    fn next_item(&self) -> Option<usize> {
+        self.queue.lock().ok()?.pop_front()
+    }
+
+    // Wait until the job is not paused; returns false if it was cancelled meanwhile
+    async fn wait_until_runnable(&self) -> bool {
+        let mut paused = self.paused.subscribe();
+        tokio::select! {
+            _ = paused.wait_for(|paused| !*paused) => !self.token.is_cancelled(),
+            _ = self.token.cancelled() => false,
+        }
+    }
+}
+
+// The concurrency limit of one provider. The semaphore is resized in place,
+// never replaced, so images already being captioned still count against it.
+#[derive(Clone)]
+struct Slots {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    // Permits to retire as they come back, when the limit was lowered while
+    // they were held
+    owed: Arc<AtomicUsize>,
+}
+
+impl Slots {
+    fn new(limit: usize) -> Self {
+        Slots { limit, semaphore: Arc::new(Semaphore::new(limit)), owed: Arc::default() }
+    }
+
+    fn resize(&mut self, limit: usize) {
+        if limit > self.limit {
+            // Cancel retirements still owed before adding new permits
+            let mut extra = limit - self.limit;
+            let owed = self.owed.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |owed| Some(owed.saturating_sub(extra)));
+            extra -= owed.unwrap_or(0).min(extra);
+            self.semaphore.add_permits(extra);
+        } else {
+            let fewer = self.limit - limit;
+            let forgotten = self.semaphore.forget_permits(fewer);
+            self.owed.fetch_add(fewer - forgotten, Ordering::SeqCst);
+        }
+        self.limit = limit;
+    }
+
+    async fn acquire(&self) -> Option<Slot> {
+        let permit = self.semaphore.clone().acquire_owned().await.ok()?;
+        Some(Slot { permit: Some(permit), owed: self.owed.clone() })
+    }
+}
+
+// A held concurrency slot, handed back when dropped
+struct Slot {
+    permit: Option<OwnedSemaphorePermit>,
+    owed: Arc<AtomicUsize>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let retire = self.owed.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |owed| owed.checked_sub(1)).is_ok();
+        if let (true, Some(permit)) = (retire, self.permit.take()) {
+            permit.forget();
+        }
+    }
+}
+
+// Runs batch caption jobs in the background, independently of the webview,
+// so a reload doesn't lose progress. With a journal directory, jobs are also
+// written to disk and picked up again after a restart.
+#[derive(Default)]
+pub struct JobManager {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    limits: Mutex<HashMap<ProviderKind, Slots>>,
+    next_id: AtomicUsize,
+    journal_dir: Option<PathBuf>,
+}
+
+impl JobManager {
+    pub fn new(journal_dir: PathBuf) -> Self {
+        JobManager { journal_dir: Some(journal_dir), ..Default::default() }
+    }
+
+    // Reload journaled jobs. Unfinished ones come back paused rather than
+    // running, so a restart never starts spending API credits by itself.
+    pub fn restore(&self) {
+        let Some(dir) = &self.journal_dir else {
+            return;
+        };
+        for (journal, entries) in journal::load_all(dir) {
+            match restore_job(journal, entries) {
+                Some(job) => {
+                    let status = job.status(false);
+                    if !status.state.is_finished() {
+                        println!(
+                            "Restored caption job {}: {} of {} images left",
+                            job.id,
+                            status.total - status.completed - status.failed,
+                            status.total
+                        );
+                    }
+                    if let Ok(mut jobs) = self.jobs.lock() {
+                        jobs.insert(job.id.clone(), Arc::new(job));
+                    }
+                }
+                None => println!("Skipping job journal without a start record"),
+            }
+        }
+    }
+
+    fn slots(&self, provider: ProviderKind) -> Slots {
+        let mut limits = self.limits.lock().unwrap_or_else(|e| e.into_inner());
+        limits.entry(provider).or_insert_with(|| Slots::new(default_concurrency(provider))).clone()
+    }
+
+    // Takes effect right away: running jobs keep their worker count, but no
+    // more than `limit` images per provider are captioned at once
+    pub fn set_concurrency(&self, provider: ProviderKind, limit: usize) -> Result<(), AppError> {
+        if limit == 0 || limit > 32 {
+            return Err(AppError::invalid(format!("Concurrency must be between 1 and 32, got {}", limit)));
+        }
+        let mut limits = self.limits.lock().unwrap_or_else(|e| e.into_inner());
+        limits.entry(provider).or_insert_with(|| Slots::new(default_concurrency(provider))).resize(limit);
+        Ok(())
+    }
+
+    pub fn concurrency(&self, provider: ProviderKind) -> usize {
+        self.slots(provider).limit
+    }
+
+    pub fn start(&self, app: AppHandle, client: reqwest::Client, paths: Vec<String>, config: JobConfig) -> Result<JobStatus, AppError> {
+        if paths.is_empty() {
+            return Err(AppError::invalid("No images to caption"));
+        }
+        // Fail fast on missing API keys rather than once per image
+        providers::provider_for(config.provider, &config.options)?;
+
+        let created_at = now_millis();
+        let id = format!("job-{}-{}", created_at, self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut config = config;
+        let api_key = config.options.api_key.take();
+        let journal = self.journal_dir.as_deref().and_then(|dir| {
+            let started = JournalEntry::Started { id: id.clone(), created_at, config: Box::new(config.clone()), paths: paths.clone() };
+            Journal::create(dir, &id, &started)
+                .map_err(|e| println!("{}; job {} won't survive a restart", e, id))
+                .ok()
+        });
+        let items = paths
+            .into_iter()
+            .map(|path| JobItem { path, state: ItemState::Pending, caption: None, error: None, duration_ms: None, dictionary: None, filtered: None })
+            .collect::<Vec<_>>();
+        let (cancel, token) = cancel_pair();
+        let job = Arc::new(Job {
+            id: id.clone(),
+            config,
+            api_key: Mutex::new(api_key),
+            created_at,
+            queue: Mutex::new((0..items.len()).collect()),
+            progress: Mutex::new(JobProgressState { state: JobState::Running, items, finished_at: None }),
+            paused: watch::channel(false).0,
+            cancel,
+            token,
+            workers: AtomicUsize::new(0),
+            journal,
+        });
+
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.insert(id.clone(), job.clone());
+        }
+        let status = job.status(false);
+        emit_state(&app, &job);
+        self.spawn_workers(app, client, job);
+        Ok(status)
+    }
+
+    // Start workers for a job that has none, e.g. one restored from its journal
+    fn spawn_workers(&self, app: AppHandle, client: reqwest::Client, job: Arc<Job>) {
+        let pending = job.queue.lock().map(|queue| queue.len()).unwrap_or(0);
+        let workers = self.concurrency(job.config.provider).min(pending);
+        if workers == 0 {
+            if job.workers.load(Ordering::Acquire) == 0 {
+                finish_job(&app, &job, JobState::Completed);
+            }
+            return;
+        }
+        if job.workers.compare_exchange(0, workers, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return;
+        }
+        println!("Running caption job {} with {} workers", job.id, workers);
+
+        for _ in 0..workers {
+            let (app, client, job) = (app.clone(), client.clone(), job.clone());
+            tauri::async_runtime::spawn(async move {
+                run_worker(&app, &client, &job).await;
+                // The last worker out settles the job's final state
+                if job.workers.fetch_sub(1, Ordering::AcqRel) == 1 {
+                    let state = if job.token.is_cancelled() { JobState::Cancelled } else { JobState::Completed };
+                    finish_job(&app, &job, state);
+                }
+            });
+        }
+    }
+
+    fn job(&self, id: &str) -> Result<Arc<Job>, AppError> {
+        self.jobs
+            .lock()
+            .ok()
+            .and_then(|jobs| jobs.get(id).cloned())
+            .ok_or_else(|| AppError::new(ErrorCode::NotFound, format!("Caption job not found: {}", id)))
+    }
+
+    pub fn pause(&self, app: &AppHandle, id: &str) -> Result<JobStatus, AppError> {
+        let job = self.job(id)?;
+        if job.set_state(JobState::Paused) {
+            job.paused.send_replace(true);
+            emit_state(app, &job);
+        }
+        Ok(job.status(false))
+    }
+
+    // Jobs restored after a restart lost their API key and need it passed again
+    pub fn resume(&self, app: &AppHandle, client: &reqwest::Client, id: &str, api_key: Option<String>) -> Result<JobStatus, AppError> {
+        let job = self.job(id)?;
+        if let Some(api_key) = api_key {
+            *job.api_key.lock().unwrap_or_else(|e| e.into_inner()) = Some(api_key);
+        }
+        providers::provider_for(job.config.provider, &job.options())?;
+
+        if job.set_state(JobState::Running) {
+            job.paused.send_replace(false);
+            emit_state(app, &job);
+        }
+        self.spawn_workers(app.clone(), client.clone(), job.clone());
+        Ok(job.status(false))
+    }
+
+    // Stops in-flight requests as well as queued ones
+    pub fn cancel(&self, app: &AppHandle, id: &str) -> Result<JobStatus, AppError> {
+        let job = self.job(id)?;
+        job.cancel.cancel();
+        // A restored job that was never resumed has no workers to wind it down
+        if job.workers.load(Ordering::Acquire) == 0 {
+            finish_job(app, &job, JobState::Cancelled);
+        }
+        Ok(job.status(false))
+    }
+
+    pub fn status(&self, id: &str) -> Result<JobStatus, AppError> {
+        Ok(self.job(id)?.status(true))
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut statuses: Vec<JobStatus> = self
+            .jobs
+            .lock()
+            .map(|jobs| jobs.values().map(|job| job.status(false)).collect())
+            .unwrap_or_default();
+        statuses.sort_by_key(|status| status.created_at);
+        statuses
+    }
+
+    // Drop finished jobs from the list; running jobs are kept
+    pub fn clear_finished(&self) -> usize {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return 0;
+        };
+        let before = jobs.len();
+        jobs.retain(|_, job| {
+            let finished = job.status(false).state.is_finished();
+            if let (true, Some(journal)) = (finished, &job.journal) {
+                journal.remove();
+            }
+            !finished
+        });
+        before - jobs.len()
+    }
+}
+
+async fn run_worker(app: &AppHandle, client: &reqwest::Client, job: &Job) {
+    while job.wait_until_runnable().await {
+        let Some(index) = job.next_item() else {
+            return;
+        };
+
+        let slots = app.state::<JobManager>().slots(job.config.provider);
+        let _slot = tokio::select! {
+            slot = slots.acquire() => match slot {
+                Some(slot) => slot,
+                None => return,
+            },
+            _ = job.token.cancelled() => return,
+        };
+
+        let progress = job.update_item(index, |item| item.state = ItemState::Running);
+        emit_progress(app, progress);
+
+        let outcome = caption_item(app, client, job, index).await;
+        let cancelled = job.token.is_cancelled();
+        let progress = job.update_item(index, |item| match outcome {
+            Ok(generated) => {
+                item.state = ItemState::Done;
+                item.caption = Some(generated.caption);
+                item.duration_ms = Some(generated.duration_ms);
+                item.dictionary = generated.dictionary;
+                item.filtered = generated.filtered;
+            }
+            Err(_) if cancelled => item.state = ItemState::Cancelled,
+            Err(e) => {
+                println!("Failed to caption {}: {}", item.path, e);
+                item.state = ItemState::Failed;
+                item.error = Some(e);
+            }
+        });
+        if matches!(progress.item.state, ItemState::Done | ItemState::Failed) {
+            job.record(&JournalEntry::Item {
+                index,
+                state: progress.item.state,
+                caption: progress.item.caption.clone(),
+                error: progress.item.error.clone(),
+                duration_ms: progress.item.duration_ms,
+            });
+        }
+        emit_progress(app, progress);
+    }
+}
+
+// A caption generated by a job, with what was done to it on the way
+struct GeneratedItem {
+    caption: String,
+    dictionary: Option<DictionaryReport>,
+    filtered: Option<FilterReport>,
+    duration_ms: u64,
+}
+
+async fn caption_item(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    job: &Job,
+    index: usize,
+) -> Result<GeneratedItem, AppError> {
+    let path = job.progress.lock().unwrap_or_else(|e| e.into_inner()).items[index].path.clone();
+    let request = CaptionJob {
+        provider: job.config.provider,
+        image_path: PathBuf::from(&path),
+        model: job.config.model.clone(),
+        prompt: job.config.prompt.clone(),
+        options: job.options(),
+    };
+    let limiter = app.state::<RateLimiter>();
+    let result = providers::generate_caption(client, &limiter, &request, None, &job.token).await?;
+    let caption = finish_caption(
+        job.config.provider,
+        &result.caption,
+        job.config.prefix.as_deref(),
+        job.config.suffix.as_deref(),
+    );
+
+    let (caption, dictionary, filtered) = refine_caption(
+        &app.state::<TagDictionaries>(),
+        &app.state::<FilterStore>(),
+        &request.image_path,
+        caption,
+        job.config.save,
+    );
+    if job.config.save {
+        let writer = CaptionWriter {
+            watcher: &app.state::<DatasetWatcher>(),
+            naming: app.state::<NamingPolicy>().get(),
+            history: &app.state::<CaptionHistory>(),
+            // Both dictionary stages already ran, before the filters
+            dictionaries: None,
+        };
+        let source = CaptionSource::Model {
+            provider: job.config.provider,
+            model: job.config.model.clone(),
+            prompt: Some(job.config.prompt.clone()),
+        };
+        // Each job is one batch in the caption history
+        let saved = writer.save(&request.image_path, &caption, &job.config.save_options, &source, Some(&job.id));
+        if let Some(error) = saved.error {
+            return Err(error);
+        }
+    }
+    Ok(GeneratedItem { caption, dictionary, filtered, duration_ms: result.duration_ms })
+}
+
+// Run a finished caption through the tag dictionaries and then the
+// project's filters. Captions about to be saved get the save stage here
+// too, rather than in the writer, so unwanted tags are stripped last and
+// nothing can bring them back.
+fn refine_caption(
+    dictionaries: &TagDictionaries,
+    filters: &FilterStore,
+    image_path: &Path,
+    mut caption: String,
+    save: bool,
+) -> (String, Option<DictionaryReport>, Option<FilterReport>) {
+    // Dictionaries see the finished caption, so prefix and suffix tags count too
+    let mut dictionary = dictionaries.apply(&caption, ApplyStage::Generate);
+    if let Some(report) = &dictionary {
+        caption = report.caption.clone();
+    }
+    // The save stage may rewrite what the generate stage left alone
+    if let Some(report) = save.then(|| dictionaries.apply(&caption, ApplyStage::Save)).flatten() {
+        caption = report.caption.clone();
+        if !report.aliased.is_empty() || dictionary.is_none() {
+            dictionary = Some(report);
+        }
+    }
+    let filtered = filters.apply(image_path, &caption);
+    if let Some(report) = &filtered {
+        caption = report.caption.clone();
+    }
+    (caption, dictionary, filtered)
+}
+
+// Rebuild a job by replaying its journal. Items that were mid-request when
+// the app stopped have no record and simply run again.
+fn restore_job(journal: Journal, entries: Vec<JournalEntry>) -> Option<Job> {
+    let mut entries = entries.into_iter();
+    let Some(JournalEntry::Started { id, created_at, config, paths }) = entries.next() else {
+        return None;
+    };
+
+    let mut items: Vec<JobItem> = paths
+        .into_iter()
+        .map(|path| JobItem { path, state: ItemState::Pending, caption: None, error: None, duration_ms: None, dictionary: None, filtered: None })
+        .collect();
+    let mut state = JobState::Running;
+    let mut finished_at = None;
+    for entry in entries {
+        match entry {
+            JournalEntry::Item { index, state, caption, error, duration_ms } => {
+                if let Some(item) = items.get_mut(index) {
+                    *item = JobItem { path: item.path.clone(), state, caption, error, duration_ms, dictionary: None, filtered: None };
+                }
+            }
+            JournalEntry::State { state: next, at } => {
+                state = next;
+                finished_at = next.is_finished().then_some(at);
+            }
+            JournalEntry::Started { .. } => {}
+        }
+    }
+
+    let pending: VecDeque<usize> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.state == ItemState::Pending)
+        .map(|(index, _)| index)
+        .collect();
+    if state.is_finished() {
+        for index in &pending {
+            items[*index].state = ItemState::Cancelled;
+        }
+    } else {
+        state = JobState::Paused;
+    }
+
+    let (cancel, token) = cancel_pair();
+    Some(Job {
+        id,
+        config: *config,
+        api_key: Mutex::new(None),
+        created_at,
+        queue: Mutex::new(if state.is_finished() { VecDeque::new() } else { pending }),
+        progress: Mutex::new(JobProgressState { state, items, finished_at }),
+        paused: watch::channel(state == JobState::Paused).0,
+        cancel,
+        token,
+        workers: AtomicUsize::new(0),
+        journal: Some(journal),
+    })
+}
+
+// Tidy a raw model answer the way the editor always has: cloud models answer
+// in sentences, which become comma-separated phrases; local models only lose
+// a trailing period. Prefix and suffix are then joined on with commas. Tag
+// lists are merged as tags instead, keeping their weights and escapes and
+// dropping prefix or suffix tags the model already gave.
+pub fn finish_caption(provider: ProviderKind, caption: &str, prefix: Option<&str>, suffix: Option<&str>) -> String {
+    let caption = caption.trim();
+    let prefix = prefix.map(|p| p.trim().trim_end_matches(',').trim()).filter(|p| !p.is_empty());
+    let suffix = suffix.map(|s| s.trim().trim_start_matches(',').trim()).filter(|s| !s.is_empty());
+
+    if looks_like_tags(caption) {
+        let body = caption.strip_suffix('.').unwrap_or(caption);
+        let parts: Vec<&str> = prefix.into_iter().chain([body]).chain(suffix).collect();
+        let format = TagFormat { escape_parentheses: parts.iter().any(|part| part.contains("\\(")), ..TagFormat::default() };
+        return tags::join(&parts, &format);
+    }
+
+    let mut caption = match provider {
+        ProviderKind::Anthropic | ProviderKind::OpenAi => sentences(caption).join(", "),
+        ProviderKind::Ollama | ProviderKind::LmStudio => caption.strip_suffix('.').unwrap_or(caption).trim().to_string(),
+    };
+    if let Some(stripped) = caption.strip_suffix(',') {
+        caption = stripped.trim().to_string();
+    }
+    if let Some(prefix) = prefix {
+        caption = format!("{}, {}", prefix, caption);
+    }
+    if let Some(suffix) = suffix {
+        caption = format!("{}, {}", caption, suffix);
+    }
+    caption
+}
+
+// Split prose at real sentence ends, a '.' followed by whitespace or the end
+// of the text, so decimals and weights such as `(tag:1.2)` stay whole
+fn sentences(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        if c == '.' && text[index + 1..].chars().next().is_none_or(char::is_whitespace) {
+            parts.push(text[start..index].trim());
+            start = index + 1;
+        }
+    }
+    parts.push(text[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// A single run of short comma-separated items, as taggers answer, rather
+// than prose
+fn looks_like_tags(caption: &str) -> bool {
+    let body = caption.strip_suffix('.').unwrap_or(caption);
+    body.contains(',') && sentences(body).len() == 1 && body.split(',').all(|item| item.split_whitespace().count() <= 4)
+}
+
+fn finish_job(app: &AppHandle, job: &Job, state: JobState) {
+    // Anything a worker never picked up didn't run
+    let skipped: Vec<usize> = job.queue.lock().map(|mut queue| queue.drain(..).collect()).unwrap_or_default();
+    for index in skipped {
+        job.update_item(index, |item| item.state = ItemState::Cancelled);
+    }
+    job.set_state(state);
+    let status = job.status(false);
+    println!(
+        "Caption job {} {:?}: {} done, {} failed of {}",
+        job.id, status.state, status.completed, status.failed, status.total
+    );
+    emit_state(app, job);
+}
+
+fn emit_progress(app: &AppHandle, progress: JobProgress) {
+    if let Err(e) = app.emit(JOB_PROGRESS_EVENT, progress) {
+        println!("Failed to emit job progress: {}", e);
+    }
+}
+
+fn emit_state(app: &AppHandle, job: &Job) {
+    if let Err(e) = app.emit(JOB_STATE_EVENT, job.status(false)) {
+        println!("Failed to emit job state: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences_become_phrases() {
+        let caption = finish_caption(ProviderKind::Anthropic, "A cat on a mat. It is 3.5 years old.", Some("photo,"), None);
+        assert_eq!(caption, "photo, A cat on a mat, It is 3.5 years old");
+    }
+
+    #[test]
+    fn tag_lists_keep_weights() {
+        let caption = finish_caption(ProviderKind::OpenAi, "1girl, (smile:1.2), solo.", Some("1girl"), Some("masterpiece"));
+        assert_eq!(caption, "1girl, (smile:1.2), solo, masterpiece");
+    }
+
+    #[test]
+    fn filters_strip_tags_the_dictionaries_imply() {
+        let dir = std::env::temp_dir().join(format!("tagmeister-jobs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let implications = dir.join("implications.csv");
+        std::fs::write(&implications, "long_hair,hair\nsolo,1girl\n").unwrap();
+
+        let dictionaries = TagDictionaries::default();
+        dictionaries.load(&implications, crate::dictionary::DictionaryKind::Implications).unwrap();
+        let filters = FilterStore::default();
+        let blacklist = vec![crate::filters::FilterRule { pattern: "hair".to_string(), regex: false }];
+        filters.set(&dir, crate::filters::CaptionFilters { blacklist, ..Default::default() }).unwrap();
+
+        for save in [false, true] {
+            let (caption, dictionary, filtered) =
+                refine_caption(&dictionaries, &filters, &dir.join("a.png"), "solo, long hair".to_string(), save);
+            assert_eq!(caption, "solo, long hair, 1girl");
+            assert_eq!(dictionary.unwrap().implied, ["1girl", "hair"]);
+            assert_eq!(filtered.unwrap().stripped[0].text, "hair");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restored_jobs_resume_where_the_journal_left_off() {
+        let dir = std::env::temp_dir().join(format!("tagmeister-jobs-journal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = JobConfig {
+            provider: ProviderKind::Ollama,
+            model: "llava".to_string(),
+            prompt: "Describe the image".to_string(),
+            options: GenerationOptions::default(),
+            prefix: None,
+            suffix: None,
+            save: false,
+            save_options: SaveOptions::default(),
+        };
+        let paths: Vec<String> = ["a.png", "b.png", "c.png"].map(String::from).to_vec();
+        let started = JournalEntry::Started { id: "job".to_string(), created_at: 1, config: Box::new(config), paths };
+        let journal = Journal::create(&dir, "job", &started).unwrap();
+        let done = |index| JournalEntry::Item { index, state: ItemState::Done, caption: Some("cat".to_string()), error: None, duration_ms: None };
+        journal.append(&done(0)).unwrap();
+        drop(journal);
+
+        let (journal, entries) = journal::load_all(&dir).pop().unwrap();
+        let job = restore_job(journal, entries).unwrap();
+        assert_eq!(job.progress.lock().unwrap().state, JobState::Paused);
+        assert_eq!(*job.queue.lock().unwrap(), [1, 2]);
+        job.journal.as_ref().unwrap().append(&done(1)).unwrap();
+        drop(job);
+
+        let (journal, entries) = journal::load_all(&dir).pop().unwrap();
+        let job = restore_job(journal, entries).unwrap();
+        let states: Vec<ItemState> = job.progress.lock().unwrap().items.iter().map(|item| item.state).collect();
+        assert_eq!(states, [ItemState::Done, ItemState::Done, ItemState::Pending]);
+        assert_eq!(*job.queue.lock().unwrap(), [2]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn slots_resize_in_place() {
+        // Both slots are busy when the limit drops to 1, so one of them is
+        // retired when it comes back
+        let slots = Slots::new(2);
+        let mut smaller = slots.clone();
+        let first = Slot { permit: slots.semaphore.clone().try_acquire_owned().ok(), owed: slots.owed.clone() };
+        let second = Slot { permit: slots.semaphore.clone().try_acquire_owned().ok(), owed: slots.owed.clone() };
+        smaller.resize(1);
+        drop(first);
+        drop(second);
+        assert_eq!(slots.semaphore.available_permits(), 1);
+
+        smaller.resize(3);
+        assert_eq!(slots.semaphore.available_permits(), 3);
+    }
+}
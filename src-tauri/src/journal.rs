@@ -0,0 +1,186 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::jobs::{ItemState, JobConfig, JobState};
+
+// One line of a job journal. A journal starts with `Started` and then records
+// every finished item and state change, so replaying it rebuilds the job.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Started {
+        id: String,
+        created_at: u64,
+        config: Box<JobConfig>,
+        paths: Vec<String>,
+    },
+    Item {
+        index: usize,
+        state: ItemState,
+        caption: Option<String>,
+        error: Option<AppError>,
+        duration_ms: Option<u64>,
+    },
+    State {
+        state: JobState,
+        at: u64,
+    },
+}
+
+// Append-only JSON-lines file for one job. Lines are written whole, so after
+// a crash at most the last line is torn. That line is skipped on load and
+// ended when the journal is reopened, so the next entry gets a line of its own.
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn create(dir: &Path, id: &str, started: &JournalEntry) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create job journal directory: {}", e))?;
+        let path = dir.join(format!("{}.jsonl", id));
+        let file = File::create(&path).map_err(|e| format!("Failed to create job journal {}: {}", path.display(), e))?;
+        let journal = Journal { path, file: Mutex::new(file) };
+        journal.append(started)?;
+        Ok(journal)
+    }
+
+    fn reopen(path: PathBuf) -> Result<Self, String> {
+        let open_error = |e: std::io::Error| format!("Failed to open job journal {}: {}", path.display(), e);
+        let mut file = OpenOptions::new().read(true).append(true).open(&path).map_err(open_error)?;
+        if file.metadata().map_err(open_error)?.len() > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::End(-1)).and_then(|_| file.read_exact(&mut last)).map_err(open_error)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n").map_err(open_error)?;
+            }
+        }
+        Ok(Journal { path, file: Mutex::new(file) })
+    }
+
+    pub fn append(&self, entry: &JournalEntry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|e| format!("Failed to encode journal entry: {}", e))?;
+        line.push('\n');
+        let mut file = self.file.lock().map_err(|e| e.to_string())?;
+        file.write_all(line.as_bytes())
+            .map_err(|e| format!("Failed to write job journal {}: {}", self.path.display(), e))
+    }
+
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            println!("Failed to remove job journal {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Every readable journal in `dir`, each with its entries in order
+pub fn load_all(dir: &Path) -> Vec<(Journal, Vec<JournalEntry>)> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut journals = Vec::new();
+    for path in read_dir.flatten().map(|entry| entry.path()) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("jsonl") {
+            continue;
+        }
+        let entries = match read_entries(&path) {
+            Ok(entries) if !entries.is_empty() => entries,
+            Ok(_) => continue,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        match Journal::reopen(path) {
+            Ok(journal) => journals.push((journal, entries)),
+            Err(e) => println!("{}", e),
+        }
+    }
+    journals
+}
+
+fn read_entries(path: &Path) -> Result<Vec<JournalEntry>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to read job journal {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read job journal {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => println!("Skipping unreadable line in job journal {}: {}", path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tagmeister-journal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn done(index: usize) -> JournalEntry {
+        JournalEntry::Item { index, state: ItemState::Done, caption: Some(format!("caption {}", index)), error: None, duration_ms: Some(1) }
+    }
+
+    fn indexes(entries: &[JournalEntry]) -> Vec<usize> {
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                JournalEntry::Item { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn entries_written_after_a_torn_line_survive() {
+        let dir = test_dir("torn");
+        let started = JournalEntry::State { state: JobState::Running, at: 1 };
+        let journal = Journal::create(&dir, "job", &started).unwrap();
+        journal.append(&done(0)).unwrap();
+        // A crash halfway through the next record
+        let mut file = OpenOptions::new().append(true).open(dir.join("job.jsonl")).unwrap();
+        file.write_all(br#"{"kind":"item","ind"#).unwrap();
+        drop((file, journal));
+
+        let (journal, entries) = load_all(&dir).pop().unwrap();
+        assert_eq!(indexes(&entries), [0]);
+        journal.append(&done(1)).unwrap();
+        drop(journal);
+
+        let (_, entries) = load_all(&dir).pop().unwrap();
+        assert_eq!(indexes(&entries), [0, 1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_replay_in_the_order_written() {
+        let dir = test_dir("replay");
+        let journal = Journal::create(&dir, "job", &JournalEntry::State { state: JobState::Running, at: 1 }).unwrap();
+        for entry in [done(2), done(0), JournalEntry::State { state: JobState::Paused, at: 2 }, done(1)] {
+            journal.append(&entry).unwrap();
+        }
+        drop(journal);
+        fs::write(dir.join("notes.txt"), "not a journal").unwrap();
+
+        let journals = load_all(&dir);
+        assert_eq!(journals.len(), 1);
+        let entries = &journals[0].1;
+        assert_eq!(entries.len(), 5);
+        assert_eq!(indexes(entries), [2, 0, 1]);
+        assert!(matches!(entries[3], JournalEntry::State { state: JobState::Paused, at: 2 }));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
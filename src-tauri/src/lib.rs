@@ -1,24 +1,48 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
-use std::io::Read;
-use base64::{Engine as _, engine::general_purpose};
 use serde::{Serialize, Deserialize};
-use reqwest::header::{HeaderMap, HeaderValue};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FileInfo {
-    path: String,
-    name: String,
-    is_dir: bool,
-    size: u64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DirectoryContents {
-    files: Vec<FileInfo>,
-    image_count: usize,
-}
+use tauri::ipc::Channel;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+mod bulk;
+mod captions;
+mod dictionary;
+mod error;
+mod filters;
+mod formats;
+mod history;
+mod index;
+mod jobs;
+mod journal;
+mod preprocess;
+mod protocol;
+mod providers;
+mod scan;
+mod stats;
+mod tags;
+mod thumbnails;
+mod watcher;
+
+use bulk::{BulkEditReport, BulkTarget, TagOperation};
+use captions::{CaptionNaming, CaptionWriter, LoadedCaption, NamingPolicy, SaveOptions, SaveResult};
+use dictionary::{ApplyStage, DictionaryKind, DictionaryReport, DictionarySettings, DictionarySource, TagDictionaries};
+use error::{AppError, ErrorCode};
+use filters::{CaptionFilters, FilterLogEntry, FilterReport, FilterStore};
+use history::{BatchSummary, CaptionHistory, CaptionSource, CaptionVersion, DiffSegment};
+use index::DatasetIndex;
+use jobs::{JobConfig, JobManager, JobStatus};
+use preprocess::{PreparedImage, PreprocessOptions};
+use protocol::DatasetScope;
+use providers::stream::{self as caption_stream, ActiveRequests};
+use providers::limits::{ProviderLimits, RateLimiter};
+use providers::ollama::{OllamaModelDetails, PullProgress};
+use providers::{CaptionDelta, CaptionJob, CaptionResult, GenerationOptions, ModelInfo, ProviderKind};
+use scan::DirectoryContents;
+use stats::{ExportFormat, ExportSection, TagStats};
+use tags::{Tag, TagFormat};
+use thumbnails::{Thumbnail, ThumbnailCache, ThumbnailFormat};
+use watcher::DatasetWatcher;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SystemInfo {
@@ -27,20 +51,6 @@ pub struct SystemInfo {
     memory_mb: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AnthropicRequest {
-    model: String,
-    messages: Vec<serde_json::Value>,
-    max_tokens: u32,
-    stream: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AnthropicResponse {
-    content: String,
-    error: Option<String>,
-}
-
 // Get system information
 #[tauri::command]
 fn get_system_info() -> SystemInfo {
@@ -58,272 +68,389 @@ fn get_system_info() -> SystemInfo {
     }
 }
 
-// Get directory contents with optimized file info
+// Get directory contents with optimized file info; `recursive` walks every
+// subfolder of the dataset root (e.g. kohya-style `10_concept` folders).
+// Unchanged files are answered from the persistent dataset index. The scanned
+// root also becomes the folder the `tagimg://` protocol may serve files from.
 #[tauri::command]
-fn get_directory_contents(path: &str) -> Result<DirectoryContents, String> {
-    let dir_path = Path::new(path);
-    if !dir_path.exists() || !dir_path.is_dir() {
-        return Err(format!("Directory not found: {}", path));
-    }
-    
-    let entries = match fs::read_dir(dir_path) {
-        Ok(entries) => entries,
-        Err(e) => return Err(format!("Failed to read directory: {}", e)),
-    };
-    
-    let mut files = Vec::new();
-    let mut image_count = 0;
-    
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let path_buf = entry.path();
-            let is_dir = path_buf.is_dir();
-            let path_str = path_buf.to_string_lossy().to_string();
-            
-            let name = match path_buf.file_name() {
-                Some(name) => name.to_string_lossy().to_string(),
-                None => continue,
-            };
-            
-            let size = if is_dir {
-                0
-            } else {
-                match entry.metadata() {
-                    Ok(metadata) => metadata.len(),
-                    Err(_) => 0,
-                }
-            };
-            
-            // Check if it's an image file
-            if !is_dir {
-                if let Some(ext) = path_buf.extension() {
-                    let ext_str = ext.to_string_lossy().to_lowercase();
-                    if ext_str == "jpg" || ext_str == "jpeg" || ext_str == "png" {
-                        image_count += 1;
-                    }
-                }
-            }
-            
-            files.push(FileInfo {
-                path: path_str,
-                name,
-                is_dir,
-                size,
-            });
-        }
-    }
-    
-    // Sort files: directories first, then by name
-    files.sort_by(|a, b| {
-        if a.is_dir && !b.is_dir {
-            std::cmp::Ordering::Less
-        } else if !a.is_dir && b.is_dir {
-            std::cmp::Ordering::Greater
-        } else {
-            a.name.to_lowercase().cmp(&b.name.to_lowercase())
-        }
-    });
-    
-    Ok(DirectoryContents {
-        files,
-        image_count,
-    })
+async fn get_directory_contents(
+    index: State<'_, DatasetIndex>,
+    scope: State<'_, DatasetScope>,
+    naming: State<'_, NamingPolicy>,
+    path: &str,
+    recursive: Option<bool>,
+) -> Result<DirectoryContents, AppError> {
+    let contents = scan::scan_directory(Path::new(path), recursive.unwrap_or(false), Some(&index), &naming.get())?;
+    scope.set_root(Path::new(path));
+    Ok(contents)
 }
 
-// Fallback directory selection function
+// Watch a dataset root for external changes; batches of changes are emitted
+// to the webview as `dataset-changed` events
 #[tauri::command]
-fn select_directory_fallback() -> Result<String, String> {
-    // Since we can't use the FileDialogBuilder directly, we'll return an error
-    // and let the frontend handle it
-    Err("Native directory selection not available".to_string())
+fn watch_dataset(
+    app: AppHandle,
+    watcher: State<'_, DatasetWatcher>,
+    path: &str,
+    recursive: Option<bool>,
+) -> Result<(), AppError> {
+    watcher.watch(app, Path::new(path), recursive.unwrap_or(false))
 }
 
-// Batch process captions - save multiple captions at once
+// Stop watching the current dataset root
 #[tauri::command]
-fn save_captions(captions: HashMap<String, String>) -> Result<usize, String> {
-    let mut success_count = 0;
-    
-    for (path, caption) in captions {
-        let path = Path::new(&path);
-        
-        // Skip if the path doesn't exist
-        if !path.exists() {
-            continue;
-        }
-        
-        // Get the caption file path (same name but .txt extension)
-        let file_stem = match path.file_stem() {
-            Some(stem) => stem.to_string_lossy().to_string(),
-            None => continue,
-        };
-        
-        let parent = match path.parent() {
-            Some(parent) => parent,
-            None => continue,
-        };
-        
-        let caption_path = parent.join(format!("{}.txt", file_stem));
-        
-        // Write the caption to the file
-        if let Err(_) = fs::write(&caption_path, caption) {
-            continue;
-        }
-        
-        success_count += 1;
-    }
-    
-    Ok(success_count)
+fn unwatch_dataset(watcher: State<'_, DatasetWatcher>) {
+    watcher.unwatch();
+}
+
+// Split a booru-style caption into tags, with weights like `(tag:1.2)` and
+// emphasis like `((tag))` resolved
+#[tauri::command]
+fn parse_tags(caption: &str) -> Vec<Tag> {
+    tags::parse(caption)
 }
 
-use image::io::Reader as ImageReader;
-use image::GenericImageView;
+// Rewrite a tag caption with consistent spacing, underscores, escaping and no
+// duplicate tags
+#[tauri::command]
+fn normalize_tags(caption: &str, format: Option<TagFormat>) -> String {
+    tags::normalize_caption(caption, &format.unwrap_or_default())
+}
 
+#[tauri::command]
+fn serialize_tags(tags: Vec<Tag>, format: Option<TagFormat>) -> String {
+    let format = format.unwrap_or_default();
+    tags::serialize(&tags::normalize(tags, &format), &format)
+}
 
-// Read an image file and return its contents as a base64-encoded string, with validation
+// Join tag captions (e.g. prefix, generated tags and suffix) into one list
 #[tauri::command]
-fn read_image_as_base64(path: &str) -> Result<String, String> {
-    let path = Path::new(path);
+fn join_tags(parts: Vec<String>, format: Option<TagFormat>) -> String {
+    let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+    tags::join(&parts, &format.unwrap_or_default())
+}
 
-    // Check if the file exists
-    if !path.exists() {
-        return Err(format!("File not found: {}", path.display()));
-    }
+// Apply tag operations (replace, remove, add, move) to every caption in a
+// folder or selection. With `dry_run` nothing is written and the report
+// previews the changes; otherwise they are saved as one history batch that
+// `rollback_caption_batch` can undo.
+#[tauri::command]
+async fn bulk_edit_tags(
+    app: AppHandle,
+    target: BulkTarget,
+    operations: Vec<TagOperation>,
+    format: Option<TagFormat>,
+    options: Option<SaveOptions>,
+    dry_run: bool,
+) -> Result<BulkEditReport, AppError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let watcher = app.state::<DatasetWatcher>();
+        let history = app.state::<CaptionHistory>();
+        let dictionaries = app.state::<TagDictionaries>();
+        let writer = CaptionWriter {
+            watcher: &watcher,
+            naming: app.state::<NamingPolicy>().get(),
+            history: &history,
+            dictionaries: Some(&dictionaries),
+        };
+        bulk::edit_tags(
+            &writer,
+            &target,
+            &operations,
+            &format.unwrap_or_default(),
+            &options.unwrap_or_default(),
+            dry_run,
+        )
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, format!("Bulk tag edit failed: {}", e)))?
+}
 
-    // Open the image file and always use with_guessed_format()
-    let mut img_reader = match image::io::Reader::open(path)
-        .and_then(|r| r.with_guessed_format()) {
-        Ok(reader) => reader,
-        Err(e) => return Err(format!("Failed to open or guess image format: {}", e)),
-    };
+// Images in a folder or selection whose captions have all of `tags`
+#[tauri::command]
+async fn find_tags(naming: State<'_, NamingPolicy>, target: BulkTarget, tags: Vec<String>) -> Result<Vec<String>, AppError> {
+    let naming = naming.get();
+    tauri::async_runtime::spawn_blocking(move || bulk::find_tags(&naming, &target, &tags))
+        .await
+        .map_err(|e| AppError::new(ErrorCode::Internal, format!("Tag search failed: {}", e)))?
+}
 
-    // Decode the image for validation
-    let img = match img_reader.decode() {
-        Ok(img) => img,
-        Err(e) => return Err(format!("Failed to decode image: {}", e)),
-    };
+// Tag frequencies, per-folder breakdowns, co-occurring pairs, singletons and
+// caption length distributions for every caption under a dataset root
+#[tauri::command]
+async fn get_tag_stats(
+    naming: State<'_, NamingPolicy>,
+    directory: String,
+    recursive: Option<bool>,
+    max_pairs: Option<usize>,
+) -> Result<TagStats, AppError> {
+    let naming = naming.get();
+    tauri::async_runtime::spawn_blocking(move || {
+        stats::collect(&naming, Path::new(&directory), recursive.unwrap_or(true), max_pairs.unwrap_or(200))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, format!("Tag statistics failed: {}", e)))?
+}
 
-    let (width, height) = img.dimensions();
+// Save statistics from `get_tag_stats` as JSON, or one section of them as CSV
+#[tauri::command]
+fn export_tag_stats(
+    stats: TagStats,
+    format: ExportFormat,
+    section: Option<ExportSection>,
+    path: &str,
+) -> Result<(), AppError> {
+    stats::export(&stats, format, section.unwrap_or_default(), Path::new(path))
+}
 
-    // Check size constraints (Anthropic: max 8000x8000, recommend <=1568px, min 200px)
-    if width > 8000 || height > 8000 {
-        return Err(format!(
-            "Image dimensions too large: {}x{} (max 8000x8000 px)",
-            width, height
-        ));
-    }
-    if width < 200 || height < 200 {
-        return Err(format!(
-            "Image dimensions too small: {}x{} (min 200x200 px)",
-            width, height
-        ));
-    }
+// Tag alias, implication and vocabulary files currently loaded
+#[tauri::command]
+fn get_tag_dictionaries(dictionaries: State<'_, TagDictionaries>) -> Vec<DictionarySource> {
+    dictionaries.sources()
+}
 
-    // Re-read the file as bytes for base64 encoding (to preserve original format)
-    let mut file = match fs::File::open(path) {
-        Ok(file) => file,
-        Err(e) => return Err(format!("Failed to open file: {}", e)),
-    };
+// Load a Danbooru-style CSV (or a project file in the same layout); loading
+// a path again picks up changes to it
+#[tauri::command]
+async fn load_tag_dictionary(app: AppHandle, path: String, kind: DictionaryKind) -> Result<Vec<DictionarySource>, AppError> {
+    tauri::async_runtime::spawn_blocking(move || app.state::<TagDictionaries>().load(Path::new(&path), kind))
+        .await
+        .map_err(|e| AppError::new(ErrorCode::Internal, format!("Loading tag dictionary failed: {}", e)))?
+}
 
-    let mut buffer = Vec::new();
-    if let Err(e) = file.read_to_end(&mut buffer) {
-        return Err(format!("Failed to read file: {}", e));
-    }
+#[tauri::command]
+fn remove_tag_dictionary(dictionaries: State<'_, TagDictionaries>, path: &str) -> Result<Vec<DictionarySource>, AppError> {
+    dictionaries.remove(Path::new(path))
+}
 
-    // Encode the file contents as base64
-    let base64_string = general_purpose::STANDARD.encode(&buffer);
+#[tauri::command]
+fn get_dictionary_settings(dictionaries: State<'_, TagDictionaries>) -> DictionarySettings {
+    dictionaries.settings()
+}
 
-    Ok(base64_string)
+#[tauri::command]
+fn set_dictionary_settings(dictionaries: State<'_, TagDictionaries>, settings: DictionarySettings) -> Result<(), AppError> {
+    dictionaries.set_settings(settings)
 }
 
-// Read an image file and return its contents as a base64-encoded string, with validation and detected media type (for Anthropic)
+// Preview what the dictionaries would do to a caption, including which tags
+// aren't in the vocabulary
 #[tauri::command]
-fn read_image_as_base64_with_type(path: &str) -> Result<String, String> {
-    let path = Path::new(path);
+fn check_tags(dictionaries: State<'_, TagDictionaries>, caption: &str) -> DictionaryReport {
+    dictionaries.check(caption)
+}
 
-    // Check if the file exists
-    if !path.exists() {
-        return Err(format!("File not found: {}", path.display()));
-    }
+// Blacklist, whitelist and phrase rules for the project rooted at `project`
+#[tauri::command]
+fn get_caption_filters(filters: State<'_, FilterStore>, project: &str) -> CaptionFilters {
+    filters.get(Path::new(project))
+}
 
-    // Open the image file and always use with_guessed_format()
-    let mut img_reader = match image::io::Reader::open(path)
-        .and_then(|r| r.with_guessed_format()) {
-        Ok(reader) => reader,
-        Err(e) => return Err(format!("Failed to open or guess image format: {}", e)),
-    };
+// Replace a project's caption filters; every generated caption for an image
+// under `project` is filtered before it is returned or saved
+#[tauri::command]
+fn set_caption_filters(store: State<'_, FilterStore>, project: &str, filters: CaptionFilters) -> Result<(), AppError> {
+    store.set(Path::new(project), filters)
+}
 
-    // Get the format
-    let format = match img_reader.format() {
-        Some(fmt) => fmt,
-        None => return Err("Failed to determine image format".to_string()),
-    };
+// Try filters on a caption without saving them
+#[tauri::command]
+fn test_caption_filters(filters: CaptionFilters, caption: &str) -> Result<FilterReport, AppError> {
+    FilterStore::test(filters, caption)
+}
 
-    // Decode the image for validation
-    let img = match img_reader.decode() {
-        Ok(img) => img,
-        Err(e) => return Err(format!("Failed to decode image: {}", e)),
-    };
+// What the filters stripped from recent captions, newest first
+#[tauri::command]
+fn get_filter_log(filters: State<'_, FilterStore>, limit: Option<usize>) -> Vec<FilterLogEntry> {
+    filters.log(limit.unwrap_or(200))
+}
 
-    let (width, height) = img.dimensions();
+#[tauri::command]
+fn clear_filter_log(filters: State<'_, FilterStore>) {
+    filters.clear_log();
+}
 
-    // Check size constraints (Anthropic: max 8000x8000, recommend <=1568px, min 200px)
-    if width > 8000 || height > 8000 {
-        return Err(format!(
-            "Image dimensions too large: {}x{} (max 8000x8000 px)",
-            width, height
-        ));
-    }
-    if width < 200 || height < 200 {
-        return Err(format!(
-            "Image dimensions too small: {}x{} (min 200x200 px)",
-            width, height
-        ));
+// Every recorded save of an image's caption, newest first
+#[tauri::command]
+fn get_caption_history(
+    naming: State<'_, NamingPolicy>,
+    history: State<'_, CaptionHistory>,
+    image_path: &str,
+) -> Result<Vec<CaptionVersion>, AppError> {
+    let image_path = Path::new(image_path);
+    let caption_path = naming
+        .get()
+        .caption_path_for(image_path)
+        .ok_or_else(|| AppError::invalid(format!("Invalid image path: {}", image_path.display())))?;
+    Ok(history.versions(&caption_path)?)
+}
+
+// What a save changed, or with `against_current` how the caption file has
+// changed since that save
+#[tauri::command]
+fn diff_caption_version(
+    history: State<'_, CaptionHistory>,
+    version_id: i64,
+    against_current: Option<bool>,
+) -> Result<Vec<DiffSegment>, AppError> {
+    let version = history
+        .version(version_id)?
+        .ok_or_else(|| AppError::new(ErrorCode::NotFound, format!("Caption version {} not found", version_id)))?;
+    let saved = version.text.unwrap_or_default();
+    if against_current.unwrap_or(false) {
+        let current = fs::read(&version.caption_path)
+            .map(|bytes| captions::decode_caption(&bytes).0)
+            .unwrap_or_default();
+        Ok(history::diff(&saved, &current))
+    } else {
+        Ok(history::diff(&version.previous.unwrap_or_default(), &saved))
     }
+}
 
-    // Re-read the file as bytes for base64 encoding (to preserve original format)
-    let mut file = match fs::File::open(path) {
-        Ok(file) => file,
-        Err(e) => return Err(format!("Failed to open file: {}", e)),
-    };
+// Bring the caption back to what a version saved (removing the file if that
+// version deleted it). The restore is itself recorded, so it can be undone too.
+#[tauri::command]
+fn restore_caption_version(
+    watcher: State<'_, DatasetWatcher>,
+    naming: State<'_, NamingPolicy>,
+    history: State<'_, CaptionHistory>,
+    version_id: i64,
+) -> Result<SaveResult, AppError> {
+    let version = history
+        .version(version_id)?
+        .ok_or_else(|| AppError::new(ErrorCode::NotFound, format!("Caption version {} not found", version_id)))?;
+    let writer = CaptionWriter { watcher: &watcher, naming: naming.get(), history: &history, dictionaries: None };
+    let source = CaptionSource::Restore { version: version_id };
+    Ok(writer.restore(
+        Path::new(&version.image_path),
+        Path::new(&version.caption_path),
+        version.text.as_deref(),
+        &source,
+    ))
+}
 
-    let mut buffer = Vec::new();
-    if let Err(e) = file.read_to_end(&mut buffer) {
-        return Err(format!("Failed to read file: {}", e));
-    }
+// Recent batch jobs and bulk edits that can be rolled back
+#[tauri::command]
+fn list_caption_batches(history: State<'_, CaptionHistory>, limit: Option<usize>) -> Result<Vec<BatchSummary>, AppError> {
+    Ok(history.batches(limit.unwrap_or(50))?)
+}
 
-    // Encode the file contents as base64
-    let base64_string = general_purpose::STANDARD.encode(&buffer);
-
-    // Map image::ImageFormat to MIME type
-    let media_type = match format {
-        image::ImageFormat::Jpeg => "image/jpeg",
-        image::ImageFormat::Png => "image/png",
-        image::ImageFormat::Gif => "image/gif",
-        image::ImageFormat::WebP => "image/webp",
-        image::ImageFormat::Bmp => "image/bmp",
-        image::ImageFormat::Tiff => "image/tiff",
-        image::ImageFormat::Avif => "image/avif",
-        image::ImageFormat::Pnm => "image/x-portable-anymap",
-        image::ImageFormat::Tga => "image/x-tga",
-        image::ImageFormat::Dds => "image/vnd.ms-dds",
-        image::ImageFormat::Ico => "image/x-icon",
-        image::ImageFormat::Hdr => "image/vnd.radiance",
-        image::ImageFormat::Farbfeld => "image/farbfeld",
-        _ => "application/octet-stream",
-    };
+// Put every caption a batch wrote back to its state before the batch.
+// Captions edited since are skipped as conflicts unless `force` is set.
+#[tauri::command]
+fn rollback_caption_batch(
+    watcher: State<'_, DatasetWatcher>,
+    naming: State<'_, NamingPolicy>,
+    history: State<'_, CaptionHistory>,
+    batch_id: &str,
+    force: Option<bool>,
+) -> Result<Vec<SaveResult>, AppError> {
+    let writer = CaptionWriter { watcher: &watcher, naming: naming.get(), history: &history, dictionaries: None };
+    history::rollback_batch(&writer, batch_id, force.unwrap_or(false))
+}
 
-    // Return as JSON string
-    let result = serde_json::json!({
-        "base64Data": base64_string,
-        "mediaType": media_type
-    });
+// Read the captions of every image in `directory`, or of the given image
+// `paths`, in parallel. Each result carries its own error, so one unreadable
+// file doesn't fail the rest.
+#[tauri::command]
+async fn load_captions(
+    naming: State<'_, NamingPolicy>,
+    directory: Option<String>,
+    paths: Option<Vec<String>>,
+    recursive: Option<bool>,
+) -> Result<Vec<LoadedCaption>, AppError> {
+    let naming = naming.get();
+    tauri::async_runtime::spawn_blocking(move || {
+        let images: Vec<PathBuf> = match (directory, paths) {
+            (_, Some(paths)) => paths.into_iter().map(PathBuf::from).collect(),
+            (Some(directory), None) => {
+                let dir = Path::new(&directory);
+                if !dir.is_dir() {
+                    return Err(AppError::new(ErrorCode::NotFound, format!("Directory not found: {}", directory)).with_path(dir));
+                }
+                // Reading headers to find the images is too slow for the async runtime
+                captions::images_in(dir, recursive.unwrap_or(false))
+                    .map_err(|e| AppError::io("Failed to read directory", dir, e))?
+            }
+            (None, None) => return Err(AppError::invalid("Pass either a directory or a list of image paths")),
+        };
+        Ok(captions::load_many(&naming, &images))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, format!("Caption loading failed: {}", e)))?
+}
+
+// Generate small thumbnails for a batch of images (default 256px edge),
+// reusing cached ones from app data when the source hasn't changed
+#[tauri::command]
+async fn get_thumbnails(
+    cache: State<'_, ThumbnailCache>,
+    paths: Vec<String>,
+    size: Option<u32>,
+    format: Option<ThumbnailFormat>,
+) -> Result<Vec<Thumbnail>, AppError> {
+    let cache = cache.inner().clone();
+    let size = size.unwrap_or(thumbnails::DEFAULT_THUMBNAIL_SIZE);
+    tauri::async_runtime::spawn_blocking(move || cache.get_many(&paths, size, format.unwrap_or_default()))
+        .await
+        .map_err(|e| AppError::new(ErrorCode::Internal, format!("Thumbnail generation failed: {}", e)))
+}
+
+// Fallback directory selection function
+#[tauri::command]
+fn select_directory_fallback() -> Result<String, AppError> {
+    // Since we can't use the FileDialogBuilder directly, we'll return an error
+    // and let the frontend handle it
+    Err(AppError::new(ErrorCode::Unsupported, "Native directory selection not available"))
+}
+
+// Batch process captions - save multiple captions at once. The captions they
+// replace go into the history under `source` (manual edits by default);
+// `batch_id` groups the saves so they can be rolled back together. Tag
+// dictionaries are applied by the writer when they're on for saving.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn save_captions(
+    watcher: State<'_, DatasetWatcher>,
+    naming: State<'_, NamingPolicy>,
+    history: State<'_, CaptionHistory>,
+    dictionaries: State<'_, TagDictionaries>,
+    captions: HashMap<String, String>,
+    options: Option<SaveOptions>,
+    source: Option<CaptionSource>,
+    batch_id: Option<String>,
+) -> Result<Vec<SaveResult>, AppError> {
+    let options = options.unwrap_or_default();
+    let source = source.unwrap_or_default();
+    let writer = CaptionWriter { watcher: &watcher, naming: naming.get(), history: &history, dictionaries: Some(&dictionaries) };
+    let mut paths: Vec<_> = captions.into_iter().collect();
+    paths.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(paths
+        .iter()
+        .map(|(path, caption)| writer.save(Path::new(path), caption, &options, &source, batch_id.as_deref()))
+        .collect())
+}
 
-    Ok(result.to_string())
+// Prepare an image for upload to a captioning provider: downscale to its size
+// budget, grow tiny images, flatten transparency and convert formats it can't
+// read. The result lists every transformation that was applied.
+#[tauri::command]
+async fn prepare_image(
+    path: String,
+    provider: ProviderKind,
+    options: Option<PreprocessOptions>,
+) -> Result<PreparedImage, AppError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        preprocess::prepare_image(Path::new(&path), provider, &options.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, format!("Image preprocessing failed: {}", e)))?
 }
 
 // Create directory in AppData with elevated permissions
 #[tauri::command]
-fn create_app_data_dir(path: &str) -> Result<bool, String> {
+fn create_app_data_dir(path: &str) -> Result<bool, AppError> {
     // Check if the directory already exists
     let path_obj = Path::new(path);
     if path_obj.exists() {
@@ -337,7 +464,7 @@ fn create_app_data_dir(path: &str) -> Result<bool, String> {
             println!("Creating parent directory: {}", parent.display());
             if let Err(e) = fs::create_dir_all(parent) {
                 println!("Failed to create parent directory {}: {}", parent.display(), e);
-                return Err(format!("Failed to create parent directory: {}", e));
+                return Err(AppError::io("Failed to create parent directory", parent, e));
             }
         }
     }
@@ -355,206 +482,253 @@ fn create_app_data_dir(path: &str) -> Result<bool, String> {
                 Ok(true)
             } else {
                 println!("Failed to create directory {}: {}", path, e);
-                Err(format!("Failed to create directory: {}", e))
+                Err(AppError::io("Failed to create directory", path_obj, e))
             }
         }
     }
 }
 
-use reqwest::Client;
+// Event emitted while `ollama_pull_model` downloads a model
+const OLLAMA_PULL_EVENT: &str = "ollama-pull-progress";
 
-// Proxy request to Ollama API
+// Proxy a raw request to the Ollama API at `base_url` (default localhost).
+// `endpoint` is the path after `/api/`, e.g. "tags", "chat" or "show";
+// `request_data` is the JSON body for endpoints that take one.
 #[tauri::command]
-async fn proxy_ollama_request(endpoint: String, request_data: String) -> Result<String, String> {
-    // endpoint: e.g. "generate" or "tags"
-    // request_data: JSON string for POST body (for generate), or empty for GET (for tags)
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(120))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-
-    let url = format!("http://127.0.0.1:11434/api/{}", endpoint);
+async fn proxy_ollama_request(
+    client: State<'_, reqwest::Client>,
+    endpoint: String,
+    request_data: String,
+    base_url: Option<String>,
+) -> Result<String, AppError> {
+    let endpoint = endpoint.trim_matches('/');
+    let url = format!("{}/api/{}", providers::ollama::base_url(base_url.as_deref()), endpoint);
+
+    let mut request = client
+        .request(providers::ollama::method_for(endpoint), &url)
+        .timeout(std::time::Duration::from_secs(120));
+    if !request_data.trim().is_empty() {
+        request = request.header("Content-Type", "application/json").body(request_data);
+    }
 
-    let response = if endpoint == "tags" {
-        // GET request for /api/tags
-        client.get(&url)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send GET request: {}", e))?
-    } else {
-        // POST request for /api/generate and others
-        client.post(&url)
-            .header("Content-Type", "application/json")
-            .body(request_data)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send POST request: {}", e))?
-    };
+    let response = request
+        .send()
+        .await
+        .map_err(|e| providers::transport_error(ProviderKind::Ollama, e))?;
 
     let status = response.status();
-    let body = response.text().await.map_err(|e| format!("Failed to read response: {}", e))?;
+    let body = response
+        .text()
+        .await
+        .map_err(|e| providers::transport_error(ProviderKind::Ollama, e))?;
 
     if !status.is_success() {
-        return Err(format!("Ollama API request failed with status {}: {}", status, body));
+        return Err(providers::status_error(ProviderKind::Ollama, status, &body, None));
     }
 
     Ok(body)
 }
 
-// Proxy request to Anthropic API
+// Model details from Ollama's `/api/show`, including whether it accepts images
 #[tauri::command]
-async fn proxy_anthropic_request(api_key: String, request_data: String) -> Result<String, String> {
-    println!("Proxying request to Anthropic API");
-    
-    // Create a client with custom timeout
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(60))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-    
-    // Create headers
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
-    headers.insert("x-api-key", HeaderValue::from_str(&api_key).map_err(|e| format!("Invalid API key: {}", e))?);
-    headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
-    
-    // Parse the request data to fix any base64 encoding issues
-    let mut request_obj: serde_json::Value = match serde_json::from_str(&request_data) {
-        Ok(obj) => obj,
-        Err(e) => {
-            println!("Error parsing request JSON: {}", e);
-            return Err(format!("Invalid JSON: {}", e));
+async fn ollama_show_model(
+    client: State<'_, reqwest::Client>,
+    model: String,
+    base_url: Option<String>,
+) -> Result<OllamaModelDetails, AppError> {
+    let base_url = providers::ollama::base_url(base_url.as_deref());
+    providers::ollama::show_model(&client, &base_url, &model).await
+}
+
+// Download a model into Ollama, emitting `ollama-pull-progress` events as it goes
+#[tauri::command]
+async fn ollama_pull_model(
+    app: AppHandle,
+    client: State<'_, reqwest::Client>,
+    active: State<'_, ActiveRequests>,
+    model: String,
+    base_url: Option<String>,
+) -> Result<(), AppError> {
+    let base_url = providers::ollama::base_url(base_url.as_deref());
+    let request_id = format!("ollama-pull:{}", model);
+    let token = active.register(&request_id)?;
+
+    let on_progress = |progress: PullProgress| {
+        if let Err(e) = app.emit(OLLAMA_PULL_EVENT, progress) {
+            println!("Failed to emit pull progress: {}", e);
         }
     };
-    
-    // Check if we need to fix the base64 data
-    if let Some(messages) = request_obj.get_mut("messages").and_then(|m| m.as_array_mut()) {
-        if let Some(message) = messages.get_mut(0) {
-            if let Some(content) = message.get_mut("content").and_then(|c| c.as_array_mut()) {
-                for item in content.iter_mut() {
-                    if let Some(image_type) = item.get("type").and_then(|t| t.as_str()) {
-                        if image_type == "image" {
-                            if let Some(source) = item.get_mut("source") {
-                                if let Some(source_type) = source.get("type").and_then(|t| t.as_str()) {
-                                    if source_type == "base64" {
-                                        if let Some(data) = source.get_mut("data").and_then(|d| d.as_str()) {
-                                            // Ensure the base64 data is properly formatted
-                                            // Remove any whitespace, brackets, quotes, or other non-base64 characters
-                                            let mut clean_data = data.replace("\n", "").replace("\r", "").replace(" ", "");
-                                            
-                                            // Remove any potential JSON artifacts like brackets or quotes
-                                            if clean_data.starts_with("[") && clean_data.ends_with("]") {
-                                                clean_data = clean_data[1..clean_data.len()-1].to_string();
-                                            }
-                                            if clean_data.starts_with("\"") && clean_data.ends_with("\"") {
-                                                clean_data = clean_data[1..clean_data.len()-1].to_string();
-                                            }
-                                            
-                                            // Remove any other non-base64 characters
-                                            clean_data = clean_data.chars()
-                                                .filter(|c| c.is_ascii_alphanumeric() || *c == '+' || *c == '/' || *c == '=')
-                                                .collect();
-
-                                            // Log media type if available
-                                            if let Some(media_type) = source.get("media_type").and_then(|m| m.as_str()) {
-                                                println!("Anthropic proxy: media_type = {}", media_type);
-                                            }
-                                            // Log first and last 20 chars of base64
-                                            let len = clean_data.len();
-                                            let first20 = &clean_data.chars().take(20).collect::<String>();
-                                            let last20 = if len > 20 {
-                                                &clean_data.chars().rev().take(20).collect::<String>().chars().rev().collect::<String>()
-                                            } else {
-                                                ""
-                                            };
-                                            println!("Anthropic proxy: base64 first 20: {}", first20);
-                                            println!("Anthropic proxy: base64 last 20: {}", last20);
-                                            println!("Anthropic proxy: base64 length: {}", len);
-
-                                            // Decode and log first 16 bytes in hex
-                                            match base64::engine::general_purpose::STANDARD.decode(&clean_data) {
-                                                Ok(decoded) => {
-                                                    let hex_bytes: Vec<String> = decoded.iter().take(16).map(|b| format!("{:02X}", b)).collect();
-                                                    println!("Anthropic proxy: decoded first 16 bytes: {}", hex_bytes.join(" "));
-                                                    // Optionally: comment out re-encoding and just use cleaned base64
-                                                    // let reencoded = base64::engine::general_purpose::STANDARD.encode(&decoded);
-                                                    // if let Some(data_field) = source.get_mut("data") {
-                                                    //     *data_field = serde_json::Value::String(reencoded);
-                                                    //     println!("Re-encoded base64 data");
-                                                    // }
-                                                    // Instead, just use cleaned base64
-                                                    if let Some(data_field) = source.get_mut("data") {
-                                                        *data_field = serde_json::Value::String(clean_data.clone());
-                                                        println!("Set cleaned base64 data (no re-encode)");
-                                                    }
-                                                },
-                                                Err(e) => {
-                                                    println!("Error decoding base64: {}", e);
-                                                    return Err(format!("Invalid base64 data: {}", e));
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    
-    // Convert back to string
-    let fixed_request_data = match serde_json::to_string(&request_obj) {
-        Ok(data) => data,
-        Err(e) => {
-            println!("Error serializing fixed request: {}", e);
-            return Err(format!("Failed to serialize request: {}", e));
-        }
+    let result = providers::ollama::pull_model(&client, &base_url, &model, &on_progress, &token).await;
+
+    active.finish(&request_id);
+    result
+}
+
+// Stop a running `ollama_pull_model`; returns whether a pull was in progress
+#[tauri::command]
+fn cancel_ollama_pull(active: State<'_, ActiveRequests>, model: String) -> bool {
+    active.cancel(&format!("ollama-pull:{}", model))
+}
+
+// Caption one image with any provider. The image is preprocessed for the
+// provider, sent, and the generated text returned along with what was done
+// to the image and to the tags (by the tag dictionaries and the project's
+// caption filters) on the way. With `on_delta`, text is streamed to the channel as
+// it is generated; a `request_id` makes the request cancellable.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn generate_caption(
+    client: State<'_, reqwest::Client>,
+    dictionaries: State<'_, TagDictionaries>,
+    filters: State<'_, FilterStore>,
+    limiter: State<'_, RateLimiter>,
+    active: State<'_, ActiveRequests>,
+    path: String,
+    provider: ProviderKind,
+    model: String,
+    prompt: String,
+    options: Option<GenerationOptions>,
+    request_id: Option<String>,
+    on_delta: Option<Channel<CaptionDelta>>,
+) -> Result<CaptionResult, AppError> {
+    let job = CaptionJob {
+        provider,
+        image_path: path.into(),
+        model,
+        prompt,
+        options: options.unwrap_or_default(),
     };
-    
-    println!("Making request to Anthropic API");
-    
-    // Make the request
-    let response = match client.post("https://api.anthropic.com/v1/messages")
-        .headers(headers)
-        .body(fixed_request_data)
-        .send()
-        .await {
-            Ok(resp) => resp,
-            Err(e) => {
-                println!("Error making request: {}", e);
-                if e.is_timeout() {
-                    return Err(format!("Request timed out: {}", e));
-                } else if e.is_connect() {
-                    return Err(format!("Connection error: {}", e));
-                } else {
-                    return Err(format!("Request failed: {}", e));
-                }
-            }
-        };
-    
-    // Get the response status
-    let status = response.status();
-    println!("Received response with status: {}", status);
-    
-    // Get the response body
-    let body = match response.text().await {
-        Ok(text) => text,
-        Err(e) => {
-            println!("Error reading response body: {}", e);
-            return Err(format!("Failed to read response: {}", e));
-        }
+
+    // Requests without an id can't be cancelled; keep the handle alive until done
+    let (_handle, token) = caption_stream::cancel_pair();
+    let token = match &request_id {
+        Some(id) => active.register(id)?,
+        None => token,
     };
-    
-    // Check if the request was successful
-    if !status.is_success() {
-        println!("API request failed with status {}: {}", status, body);
-        return Err(format!("API request failed with status {}: {}", status, body));
+
+    let forward = on_delta.map(|channel| {
+        move |text: &str| {
+            let _ = channel.send(CaptionDelta { text: text.to_string() });
+        }
+    });
+    let on_delta = forward.as_ref().map(|f| f as &(dyn Fn(&str) + Send + Sync));
+    let result = providers::generate_caption(&client, &limiter, &job, on_delta, &token).await;
+
+    if let Some(id) = &request_id {
+        active.finish(id);
     }
-    
-    println!("Successfully received response from Anthropic API");
-    Ok(body)
+    let mut result = result?;
+    if let Some(report) = dictionaries.apply(&result.caption, ApplyStage::Generate) {
+        result.caption = report.caption.clone();
+        result.dictionary = Some(report);
+    }
+    if let Some(report) = filters.apply(&job.image_path, &result.caption) {
+        result.caption = report.caption.clone();
+        result.filtered = Some(report);
+    }
+    Ok(result)
+}
+
+// Cancel an in-flight `generate_caption` request; returns whether it was found
+#[tauri::command]
+fn cancel_caption(active: State<'_, ActiveRequests>, request_id: String) -> bool {
+    active.cancel(&request_id)
+}
+
+// List the models a provider offers (for local servers, what is installed)
+#[tauri::command]
+async fn list_models(
+    client: State<'_, reqwest::Client>,
+    provider: ProviderKind,
+    options: Option<GenerationOptions>,
+) -> Result<Vec<ModelInfo>, AppError> {
+    providers::list_models(&client, provider, &options.unwrap_or_default()).await
+}
+
+// Caption a batch of images in the background. Progress is reported through
+// `caption-job-progress` and `caption-job-state` events.
+#[tauri::command]
+fn start_caption_job(
+    app: AppHandle,
+    client: State<'_, reqwest::Client>,
+    jobs: State<'_, JobManager>,
+    paths: Vec<String>,
+    config: JobConfig,
+) -> Result<JobStatus, AppError> {
+    jobs.start(app.clone(), client.inner().clone(), paths, config)
+}
+
+#[tauri::command]
+fn pause_caption_job(app: AppHandle, jobs: State<'_, JobManager>, job_id: String) -> Result<JobStatus, AppError> {
+    jobs.pause(&app, &job_id)
+}
+
+// `api_key` is only needed for jobs restored after a restart, since keys
+// aren't written to the job journal
+#[tauri::command]
+fn resume_caption_job(
+    app: AppHandle,
+    client: State<'_, reqwest::Client>,
+    jobs: State<'_, JobManager>,
+    job_id: String,
+    api_key: Option<String>,
+) -> Result<JobStatus, AppError> {
+    jobs.resume(&app, &client, &job_id, api_key)
+}
+
+#[tauri::command]
+fn cancel_caption_job(app: AppHandle, jobs: State<'_, JobManager>, job_id: String) -> Result<JobStatus, AppError> {
+    jobs.cancel(&app, &job_id)
+}
+
+// Full status of one job, including every item
+#[tauri::command]
+fn get_caption_job(jobs: State<'_, JobManager>, job_id: String) -> Result<JobStatus, AppError> {
+    jobs.status(&job_id)
+}
+
+// Summaries of all jobs, oldest first
+#[tauri::command]
+fn list_caption_jobs(jobs: State<'_, JobManager>) -> Vec<JobStatus> {
+    jobs.list()
+}
+
+#[tauri::command]
+fn clear_finished_caption_jobs(jobs: State<'_, JobManager>) -> usize {
+    jobs.clear_finished()
+}
+
+// How many requests may run at once against a provider, across all jobs
+#[tauri::command]
+fn set_provider_concurrency(jobs: State<'_, JobManager>, provider: ProviderKind, limit: usize) -> Result<(), AppError> {
+    jobs.set_concurrency(provider, limit)
+}
+
+// Rate limits and retry policy applied to every request to a provider
+#[tauri::command]
+fn get_provider_limits(limiter: State<'_, RateLimiter>, provider: ProviderKind) -> ProviderLimits {
+    limiter.limits(provider)
+}
+
+#[tauri::command]
+fn set_provider_limits(limiter: State<'_, RateLimiter>, provider: ProviderKind, limits: ProviderLimits) -> Result<(), AppError> {
+    limiter.set_limits(provider, limits)
+}
+
+#[tauri::command]
+fn get_provider_concurrency(jobs: State<'_, JobManager>, provider: ProviderKind) -> usize {
+    jobs.concurrency(provider)
+}
+
+// How caption files are named; used when saving, scanning and watching
+#[tauri::command]
+fn get_caption_naming(naming: State<'_, NamingPolicy>) -> CaptionNaming {
+    naming.get()
+}
+
+#[tauri::command]
+fn set_caption_naming(naming: State<'_, NamingPolicy>, policy: CaptionNaming) -> Result<(), AppError> {
+    naming.set(policy)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -564,16 +738,134 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         // Store plugin removed as we're using localStorage instead
+        .manage(DatasetWatcher::default())
+        .manage(DatasetScope::default())
+        .manage(providers::http_client())
+        .manage(ActiveRequests::default())
+        .manage(RateLimiter::default())
+        .manage(NamingPolicy::default())
+        .manage(TagDictionaries::default())
+        // Serve dataset images straight to the webview without base64 round-trips
+        .register_asynchronous_uri_scheme_protocol(protocol::SCHEME, |ctx, request, responder| {
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                responder.respond(protocol::handle_request(&app, &request));
+            });
+        })
+        .setup(|app| {
+            let app_data_dir = app.path().app_data_dir();
+
+            // Open the dataset index in app data, falling back to an in-memory
+            // one so scanning still works if the directory is unusable
+            let index = match &app_data_dir {
+                Ok(dir) => DatasetIndex::open(&dir.join("dataset-index.sqlite")),
+                Err(e) => Err(format!("Failed to resolve app data directory: {}", e)),
+            };
+            let index = match index {
+                Ok(index) => index,
+                Err(e) => {
+                    println!("{}; using an in-memory dataset index", e);
+                    DatasetIndex::in_memory()?
+                }
+            };
+            app.manage(index);
+
+            let history = match &app_data_dir {
+                Ok(dir) => CaptionHistory::open(&dir.join("caption-history.sqlite")),
+                Err(e) => Err(format!("Failed to resolve app data directory: {}", e)),
+            };
+            let history = match history {
+                Ok(history) => history,
+                Err(e) => {
+                    println!("{}; caption history won't survive a restart", e);
+                    CaptionHistory::in_memory()?
+                }
+            };
+            app.manage(history);
+
+            let filters = match &app_data_dir {
+                Ok(dir) => FilterStore::open(dir.join("caption-filters.json")),
+                Err(e) => Err(format!("Failed to resolve app data directory: {}", e)),
+            };
+            let filters = filters.unwrap_or_else(|e| {
+                println!("{}; caption filters won't survive a restart", e);
+                FilterStore::default()
+            });
+            app.manage(filters);
+
+            // Batch jobs are journaled so they can be resumed after a restart
+            let jobs = match &app_data_dir {
+                Ok(dir) => JobManager::new(dir.join("jobs")),
+                Err(_) => JobManager::default(),
+            };
+            jobs.restore();
+            app.manage(jobs);
+
+            let thumbnail_dir = match app_data_dir {
+                Ok(dir) => dir.join("thumbnails"),
+                Err(_) => std::env::temp_dir().join("tagmeister-thumbnails"),
+            };
+            let thumbnails = ThumbnailCache::new(thumbnail_dir);
+            let pruner = thumbnails.clone();
+            tauri::async_runtime::spawn_blocking(move || pruner.prune());
+            app.manage(thumbnails);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_system_info,
             get_directory_contents,
+            watch_dataset,
+            unwatch_dataset,
+            get_thumbnails,
             save_captions,
+            load_captions,
+            parse_tags,
+            normalize_tags,
+            serialize_tags,
+            join_tags,
+            bulk_edit_tags,
+            find_tags,
+            get_tag_stats,
+            export_tag_stats,
+            get_tag_dictionaries,
+            load_tag_dictionary,
+            remove_tag_dictionary,
+            get_dictionary_settings,
+            set_dictionary_settings,
+            check_tags,
+            get_caption_filters,
+            set_caption_filters,
+            test_caption_filters,
+            get_filter_log,
+            clear_filter_log,
+            get_caption_history,
+            diff_caption_version,
+            restore_caption_version,
+            list_caption_batches,
+            rollback_caption_batch,
             select_directory_fallback,
-            read_image_as_base64,
-            read_image_as_base64_with_type,
+            prepare_image,
             create_app_data_dir,
             proxy_ollama_request,
-            proxy_anthropic_request
+            ollama_show_model,
+            ollama_pull_model,
+            cancel_ollama_pull,
+            generate_caption,
+            cancel_caption,
+            list_models,
+            start_caption_job,
+            pause_caption_job,
+            resume_caption_job,
+            cancel_caption_job,
+            get_caption_job,
+            list_caption_jobs,
+            clear_finished_caption_jobs,
+            set_provider_concurrency,
+            get_provider_concurrency,
+            get_provider_limits,
+            set_provider_limits,
+            get_caption_naming,
+            set_caption_naming
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,435 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use base64::{Engine as _, engine::general_purpose};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageFormat, Rgb, RgbImage};
+use serde::{Serialize, Deserialize};
+
+use crate::error::{AppError, ErrorCode};
+use crate::formats;
+use crate::providers::ProviderKind;
+
+const JPEG_QUALITY: u8 = 90;
+
+// What a provider accepts and what it is worth sending it. Anything above the
+// edge/pixel budget is downscaled by the provider anyway, so sending it only
+// costs upload time and tokens.
+#[derive(Debug, Clone)]
+pub struct ImageProfile {
+    pub max_edge: u32,
+    pub max_pixels: Option<u64>,
+    // Images with a shorter edge than this are upscaled or padded
+    pub min_edge: u32,
+    // Encoded size limit per image
+    pub max_bytes: Option<usize>,
+    // Formats that can be sent as-is
+    pub accepted: &'static [ImageFormat],
+}
+
+impl ImageProfile {
+    pub fn for_provider(provider: ProviderKind) -> Self {
+        match provider {
+            // https://docs.anthropic.com/en/docs/build-with-claude/vision
+            ProviderKind::Anthropic => ImageProfile {
+                max_edge: 1568,
+                max_pixels: Some(1_150_000),
+                min_edge: 200,
+                max_bytes: Some(5 * 1024 * 1024),
+                accepted: &[ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Gif, ImageFormat::WebP],
+            },
+            // High-detail images are fit into 2048x2048, then the short side to 768
+            ProviderKind::OpenAi => ImageProfile {
+                max_edge: 2048,
+                max_pixels: Some(2048 * 768),
+                min_edge: 0,
+                max_bytes: Some(20 * 1024 * 1024),
+                accepted: &[ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Gif, ImageFormat::WebP],
+            },
+            // Local vision models (llava, qwen-vl, ...) work on small tiles and
+            // some reject images under a couple of patches wide
+            ProviderKind::Ollama | ProviderKind::LmStudio => ImageProfile {
+                max_edge: 1344,
+                max_pixels: None,
+                min_edge: 64,
+                max_bytes: None,
+                accepted: &[ImageFormat::Jpeg, ImageFormat::Png],
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmallImageMode {
+    #[default]
+    Upscale,
+    // Center the image on a background-coloured canvas instead of resampling
+    Pad,
+}
+
+// Per-request overrides of the provider profile
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct PreprocessOptions {
+    pub max_edge: Option<u32>,
+    pub max_megapixels: Option<f64>,
+    pub min_edge: Option<u32>,
+    #[serde(default)]
+    pub small_image: SmallImageMode,
+    // Hex colour transparent pixels and padding are filled with (default white)
+    pub background: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Transformation {
+    Downscaled { from_width: u32, from_height: u32, to_width: u32, to_height: u32 },
+    Upscaled { from_width: u32, from_height: u32, to_width: u32, to_height: u32 },
+    Padded { from_width: u32, from_height: u32, to_width: u32, to_height: u32 },
+    AlphaFlattened { background: String },
+    Converted { from: String, to: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreparedImage {
+    // Base64-encoded image bytes, ready to embed in a provider request
+    pub data: String,
+    pub media_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub original_width: u32,
+    pub original_height: u32,
+    pub original_format: String,
+    pub byte_size: usize,
+    // Empty when the original file is sent unchanged
+    pub transformations: Vec<Transformation>,
+}
+
+// Load an image and fit it to what `provider` accepts
+pub fn prepare_image(path: &Path, provider: ProviderKind, options: &PreprocessOptions) -> Result<PreparedImage, AppError> {
+    let mut profile = ImageProfile::for_provider(provider);
+    if let Some(max_edge) = options.max_edge {
+        profile.max_edge = max_edge.max(1);
+    }
+    if let Some(megapixels) = options.max_megapixels {
+        profile.max_pixels = Some((megapixels * 1_000_000.0).max(1.0) as u64);
+    }
+    if let Some(min_edge) = options.min_edge {
+        profile.min_edge = min_edge;
+    }
+    let background = parse_color(options.background.as_deref().unwrap_or("#ffffff")).map_err(AppError::invalid)?;
+
+    if !path.exists() {
+        return Err(AppError::not_found(path));
+    }
+    let bytes = fs::read(path).map_err(|e| AppError::io("Failed to read", path, e))?;
+    let source_format = image::guess_format(&bytes).map_err(|_| {
+        AppError::new(ErrorCode::UnsupportedFormat, "Failed to determine image format").with_path(path)
+    })?;
+    if !formats::can_decode(source_format) {
+        return Err(AppError::new(
+            ErrorCode::UnsupportedFormat,
+            format!("Unsupported image format: {}", formats::format_name(source_format)),
+        )
+        .with_path(path));
+    }
+
+    let img = image::load_from_memory_with_format(&bytes, source_format)
+        .map_err(|e| AppError::new(ErrorCode::InvalidImage, format!("Failed to decode image: {}", e)).with_path(path))?;
+    let (original_width, original_height) = img.dimensions();
+    if original_width == 0 || original_height == 0 {
+        return Err(AppError::new(ErrorCode::InvalidImage, "Image has no pixels").with_path(path));
+    }
+
+    let mut transformations = Vec::new();
+    let img = fit_to_profile(img, &profile, options.small_image, background, &mut transformations);
+
+    // Providers treat transparency inconsistently (often as black), so composite
+    // onto the background colour before encoding. An alpha channel that is
+    // opaque everywhere is just dropped; that changes nothing worth reporting.
+    let img = if has_transparency(&img) {
+        transformations.push(Transformation::AlphaFlattened { background: format_color(background) });
+        DynamicImage::ImageRgb8(flatten_alpha(&img, background))
+    } else if img.color().has_alpha() {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    } else {
+        img
+    };
+
+    // Send the original file untouched when nothing needed changing
+    let within_limit = profile.max_bytes.map(|max| bytes.len() <= max).unwrap_or(true);
+    if transformations.is_empty() && profile.accepted.contains(&source_format) && within_limit {
+        return Ok(PreparedImage {
+            data: general_purpose::STANDARD.encode(&bytes),
+            media_type: formats::mime_type(source_format).to_string(),
+            width: original_width,
+            height: original_height,
+            original_width,
+            original_height,
+            original_format: formats::format_name(source_format).to_string(),
+            byte_size: bytes.len(),
+            transformations,
+        });
+    }
+
+    let (output, output_format) = encode_for_profile(&img, source_format, &profile)?;
+    if output_format != source_format {
+        transformations.push(Transformation::Converted {
+            from: formats::format_name(source_format).to_string(),
+            to: formats::format_name(output_format).to_string(),
+        });
+    }
+    if let Some(max_bytes) = profile.max_bytes {
+        if output.len() > max_bytes {
+            return Err(AppError::new(
+                ErrorCode::ImageTooLarge,
+                format!("Image is still {} bytes after preprocessing (limit {} bytes)", output.len(), max_bytes),
+            )
+            .with_path(path));
+        }
+    }
+
+    let (width, height) = img.dimensions();
+    Ok(PreparedImage {
+        data: general_purpose::STANDARD.encode(&output),
+        media_type: formats::mime_type(output_format).to_string(),
+        width,
+        height,
+        original_width,
+        original_height,
+        original_format: formats::format_name(source_format).to_string(),
+        byte_size: output.len(),
+        transformations,
+    })
+}
+
+// Downscale to the edge/pixel budget, or grow images under the minimum edge
+fn fit_to_profile(
+    img: DynamicImage,
+    profile: &ImageProfile,
+    small_image: SmallImageMode,
+    background: Rgb<u8>,
+    transformations: &mut Vec<Transformation>,
+) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let long_edge = width.max(height) as f64;
+    let short_edge = width.min(height);
+
+    let mut scale = (profile.max_edge as f64 / long_edge).min(1.0);
+    if let Some(max_pixels) = profile.max_pixels {
+        scale = scale.min((max_pixels as f64 / (width as f64 * height as f64)).sqrt());
+    }
+
+    if scale < 1.0 {
+        let to_width = ((width as f64 * scale).round() as u32).max(1);
+        let to_height = ((height as f64 * scale).round() as u32).max(1);
+        transformations.push(Transformation::Downscaled { from_width: width, from_height: height, to_width, to_height });
+        return img.resize_exact(to_width, to_height, FilterType::Lanczos3);
+    }
+
+    if short_edge >= profile.min_edge {
+        return img;
+    }
+
+    match small_image {
+        SmallImageMode::Upscale => {
+            // Grow the short edge to the minimum without pushing the long edge past the maximum
+            let scale = (profile.min_edge as f64 / short_edge as f64).min(profile.max_edge as f64 / long_edge);
+            let to_width = (width as f64 * scale).round() as u32;
+            let to_height = (height as f64 * scale).round() as u32;
+            if to_width <= width && to_height <= height {
+                return img;
+            }
+            transformations.push(Transformation::Upscaled { from_width: width, from_height: height, to_width, to_height });
+            img.resize_exact(to_width, to_height, FilterType::CatmullRom)
+        }
+        SmallImageMode::Pad => {
+            let to_width = width.max(profile.min_edge);
+            let to_height = height.max(profile.min_edge);
+            transformations.push(Transformation::Padded { from_width: width, from_height: height, to_width, to_height });
+            if has_transparency(&img) {
+                transformations.push(Transformation::AlphaFlattened { background: format_color(background) });
+            }
+
+            let mut canvas = RgbImage::from_pixel(to_width, to_height, background);
+            let content = flatten_alpha(&img, background);
+            image::imageops::overlay(
+                &mut canvas,
+                &content,
+                ((to_width - width) / 2) as i64,
+                ((to_height - height) / 2) as i64,
+            );
+            DynamicImage::ImageRgb8(canvas)
+        }
+    }
+}
+
+// Whether any pixel is less than fully opaque
+fn has_transparency(img: &DynamicImage) -> bool {
+    if !img.color().has_alpha() {
+        return false;
+    }
+    match img.as_rgba8() {
+        Some(rgba) => rgba.pixels().any(|pixel| pixel[3] != 255),
+        None => img.to_rgba8().pixels().any(|pixel| pixel[3] != 255),
+    }
+}
+
+fn flatten_alpha(img: &DynamicImage, background: Rgb<u8>) -> RgbImage {
+    let rgba = img.to_rgba8();
+    let mut flat = RgbImage::new(rgba.width(), rgba.height());
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        let alpha = pixel[3] as u32;
+        let blend = |channel: usize| ((pixel[channel] as u32 * alpha + background[channel] as u32 * (255 - alpha)) / 255) as u8;
+        flat.put_pixel(x, y, Rgb([blend(0), blend(1), blend(2)]));
+    }
+    flat
+}
+
+// Keep lossless sources (screenshots, line art, pixel art) as PNG and send
+// everything else as JPEG, falling back to JPEG if the PNG is over the limit
+fn encode_for_profile(img: &DynamicImage, source: ImageFormat, profile: &ImageProfile) -> Result<(Vec<u8>, ImageFormat), String> {
+    let prefers_png = matches!(
+        source,
+        ImageFormat::Png | ImageFormat::Gif | ImageFormat::Bmp | ImageFormat::Ico | ImageFormat::Qoi | ImageFormat::Tga | ImageFormat::Farbfeld
+    );
+
+    if prefers_png && profile.accepted.contains(&ImageFormat::Png) {
+        let png = encode(img, ImageFormat::Png)?;
+        if profile.max_bytes.map(|max| png.len() <= max).unwrap_or(true) {
+            return Ok((png, ImageFormat::Png));
+        }
+    }
+    Ok((encode(img, ImageFormat::Jpeg)?, ImageFormat::Jpeg))
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let rgb = img.to_rgb8();
+    let mut bytes = Cursor::new(Vec::new());
+    let result = match format {
+        ImageFormat::Png => PngEncoder::new(&mut bytes).write_image(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8),
+        _ => JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY).encode(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8),
+    };
+    result.map_err(|e| format!("Failed to encode image: {}", e))?;
+    Ok(bytes.into_inner())
+}
+
+// Parse `#rgb` or `#rrggbb`
+fn parse_color(color: &str) -> Result<Rgb<u8>, String> {
+    let hex = color.trim().trim_start_matches('#');
+    if !hex.is_ascii() {
+        return Err(format!("Invalid background colour: {}", color));
+    }
+    let expanded: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return Err(format!("Invalid background colour: {}", color)),
+    };
+    let channel = |i: usize| u8::from_str_radix(&expanded[i..i + 2], 16).map_err(|_| format!("Invalid background colour: {}", color));
+    Ok(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+fn format_color(color: Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use image::{Rgba, RgbaImage};
+    use super::*;
+
+    const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+
+    fn fit(width: u32, height: u32, provider: ProviderKind, mode: SmallImageMode) -> (DynamicImage, Vec<Transformation>) {
+        let mut transformations = Vec::new();
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([255, 0, 0])));
+        let img = fit_to_profile(img, &ImageProfile::for_provider(provider), mode, WHITE, &mut transformations);
+        (img, transformations)
+    }
+
+    fn write_png(name: &str, img: RgbaImage) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tagmeister-preprocess-{}-{}.png", name, std::process::id()));
+        img.save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn large_images_are_downscaled_to_the_budget() {
+        let (img, transformations) = fit(4096, 100, ProviderKind::OpenAi, SmallImageMode::Upscale);
+        assert_eq!(img.dimensions(), (2048, 50));
+        assert_eq!(
+            transformations,
+            [Transformation::Downscaled { from_width: 4096, from_height: 100, to_width: 2048, to_height: 50 }]
+        );
+
+        // The pixel budget wins when the edges are within the limit
+        let (img, _) = fit(1200, 1200, ProviderKind::Anthropic, SmallImageMode::Upscale);
+        assert_eq!(img.dimensions(), (1072, 1072));
+    }
+
+    #[test]
+    fn small_images_are_upscaled_or_padded() {
+        let (img, transformations) = fit(32, 16, ProviderKind::Ollama, SmallImageMode::Upscale);
+        assert_eq!(img.dimensions(), (128, 64));
+        assert!(matches!(transformations[..], [Transformation::Upscaled { to_width: 128, to_height: 64, .. }]));
+
+        let (img, transformations) = fit(32, 16, ProviderKind::Ollama, SmallImageMode::Pad);
+        assert_eq!(img.dimensions(), (64, 64));
+        assert!(matches!(transformations[..], [Transformation::Padded { to_width: 64, to_height: 64, .. }]));
+        let canvas = img.to_rgb8();
+        assert_eq!(*canvas.get_pixel(0, 0), WHITE);
+        assert_eq!(*canvas.get_pixel(32, 32), Rgb([255, 0, 0]));
+
+        let (img, transformations) = fit(300, 300, ProviderKind::Ollama, SmallImageMode::Pad);
+        assert_eq!(img.dimensions(), (300, 300));
+        assert!(transformations.is_empty());
+    }
+
+    #[test]
+    fn transparency_is_flattened_onto_the_background() {
+        let mut rgba = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]));
+        rgba.put_pixel(1, 0, Rgba([255, 0, 0, 0]));
+        let img = DynamicImage::ImageRgba8(rgba);
+        assert!(has_transparency(&img));
+        let flat = flatten_alpha(&img, Rgb([0, 0, 255]));
+        assert_eq!(*flat.get_pixel(0, 0), Rgb([255, 0, 0]));
+        assert_eq!(*flat.get_pixel(1, 0), Rgb([0, 0, 255]));
+
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 255])));
+        assert!(!has_transparency(&opaque));
+
+        let path = write_png("transparent", img.to_rgba8());
+        let prepared = prepare_image(&path, ProviderKind::OpenAi, &PreprocessOptions::default()).unwrap();
+        assert_eq!(prepared.transformations, [Transformation::AlphaFlattened { background: "#ffffff".to_string() }]);
+        assert_eq!(prepared.media_type, "image/png");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn images_that_fit_are_sent_untouched() {
+        // An alpha channel that is opaque everywhere doesn't count as a change
+        let path = write_png("untouched", RgbaImage::from_pixel(100, 80, Rgba([1, 2, 3, 255])));
+        let prepared = prepare_image(&path, ProviderKind::OpenAi, &PreprocessOptions::default()).unwrap();
+        assert!(prepared.transformations.is_empty());
+        assert_eq!(prepared.data, general_purpose::STANDARD.encode(fs::read(&path).unwrap()));
+        assert_eq!((prepared.width, prepared.height), (100, 80));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn colors_parse_from_hex() {
+        assert_eq!(parse_color("#fff"), Ok(WHITE));
+        assert_eq!(parse_color(" 00ff80 "), Ok(Rgb([0, 255, 128])));
+        assert_eq!(format_color(Rgb([0, 255, 128])), "#00ff80");
+        for bad in ["", "#12345", "#gggggg", "#ééé", "red"] {
+            assert!(parse_color(bad).is_err(), "{} should be rejected", bad);
+        }
+
+        let options = PreprocessOptions { background: Some("nope".to_string()), ..PreprocessOptions::default() };
+        let error = prepare_image(Path::new("missing.png"), ProviderKind::OpenAi, &options).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+    }
+}
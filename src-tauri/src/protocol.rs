@@ -0,0 +1,273 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use percent_encoding::percent_decode_str;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager};
+
+use crate::error::ErrorCode;
+use crate::formats;
+use crate::thumbnails::{ThumbnailCache, ThumbnailFormat};
+
+// Images are served as `tagimg://localhost/<url-encoded absolute path>`
+// (`http://tagimg.localhost/...` on Windows), which is what the frontend's
+// `convertFileSrc(path, 'tagimg')` produces. Optional query parameters:
+//   size=<px>           downscale so the longest edge is at most <px>
+//   format=jpeg|webp    output format when resizing
+pub const SCHEME: &str = "tagimg";
+
+// The dataset root the protocol is allowed to serve files from
+#[derive(Default)]
+pub struct DatasetScope {
+    root: Mutex<Option<PathBuf>>,
+}
+
+impl DatasetScope {
+    pub fn set_root(&self, root: &Path) {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        if let Ok(mut current) = self.root.lock() {
+            *current = Some(root);
+        }
+    }
+
+    // Canonical form of `path` if it lies inside the current dataset root
+    pub fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        let root = self.root.lock().ok()?.clone()?;
+        let path = path.canonicalize().ok()?;
+        if path.starts_with(&root) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn handle_request(app: &AppHandle, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let uri = request.uri();
+    let requested = percent_decode_str(uri.path().trim_start_matches('/')).decode_utf8_lossy().to_string();
+
+    let scope = app.state::<DatasetScope>();
+    let path = match scope.resolve(Path::new(&requested)) {
+        Some(path) => path,
+        None => {
+            println!("Refusing to serve file outside the dataset: {}", requested);
+            return error_response(StatusCode::FORBIDDEN, "File is outside the open dataset");
+        }
+    };
+
+    // Only serve actual images, identified by their header
+    let format = match formats::sniff_format(&path) {
+        Ok(Some(format)) => format,
+        Ok(None) => return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Not an image"),
+        Err(e) => return error_response(StatusCode::NOT_FOUND, &format!("Failed to open file: {}", e)),
+    };
+
+    let mut size = None;
+    let mut thumbnail_format = ThumbnailFormat::Auto;
+    for (key, value) in uri.query().unwrap_or("").split('&').filter_map(|pair| pair.split_once('=')) {
+        match key {
+            "size" => size = value.parse::<u32>().ok(),
+            "format" => {
+                thumbnail_format = match value {
+                    "jpeg" | "jpg" => ThumbnailFormat::Jpeg,
+                    "webp" => ThumbnailFormat::Webp,
+                    _ => ThumbnailFormat::Auto,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(size) = size {
+        let cache = app.state::<ThumbnailCache>();
+        return match cache.render(&path, size, thumbnail_format) {
+            Ok(rendered) => Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, formats::mime_type(rendered.format))
+                .header(header::CONTENT_LENGTH, rendered.bytes.len())
+                .header(header::CACHE_CONTROL, "no-cache")
+                .body(rendered.bytes)
+                .unwrap_or_else(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response")),
+            Err(e) => {
+                let status = match e.code {
+                    ErrorCode::NotFound => StatusCode::NOT_FOUND,
+                    ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+                    ErrorCode::InvalidImage | ErrorCode::UnsupportedFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                error_response(status, &e.message)
+            }
+        };
+    }
+
+    serve_file(&path, formats::mime_type(format), request.headers().get(header::RANGE).and_then(|v| v.to_str().ok()))
+}
+
+// Serve the original file, honouring a single `Range: bytes=` request
+fn serve_file(path: &Path, mime_type: &str, range: Option<&str>) -> Response<Vec<u8>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, &format!("Failed to open file: {}", e)),
+    };
+    let len = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to read file: {}", e)),
+    };
+
+    let (status, start, end) = match range.map(|r| parse_range(r, len)) {
+        None => (StatusCode::OK, 0, len.saturating_sub(1)),
+        Some(Some((start, end))) => (StatusCode::PARTIAL_CONTENT, start, end),
+        Some(None) => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .body(Vec::new())
+                .unwrap_or_else(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response"));
+        }
+    };
+
+    let mut body = Vec::new();
+    if len > 0 {
+        let read = file
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| (&mut file).take(end - start + 1).read_to_end(&mut body));
+        if let Err(e) = read {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to read file: {}", e));
+        }
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::CONTENT_LENGTH, body.len())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "no-cache");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len));
+    }
+    response
+        .body(body)
+        .unwrap_or_else(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response"))
+}
+
+// Parse a single-range `bytes=` header into inclusive offsets, or None if it
+// can't be satisfied for a file of `len` bytes
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    // Multiple ranges aren't supported; serve the first one
+    let spec = spec.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range: the last N bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (len.saturating_sub(suffix), len.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len.checked_sub(1)?)),
+    };
+
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start, end))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Vec<u8>> {
+    let mut response = Response::new(message.as_bytes().to_vec());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/plain; charset=utf-8"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tagmeister-protocol-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn scope_only_resolves_paths_inside_the_root() {
+        let dir = test_dir("scope");
+        let root = dir.join("dataset");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.png"), b"").unwrap();
+        fs::write(dir.join("secret.png"), b"").unwrap();
+
+        let scope = DatasetScope::default();
+        assert_eq!(scope.resolve(&root.join("a.png")), None);
+
+        scope.set_root(&root);
+        assert_eq!(scope.resolve(&root.join("a.png")), Some(root.join("a.png").canonicalize().unwrap()));
+        assert_eq!(scope.resolve(&root.join("../secret.png")), None);
+        assert_eq!(scope.resolve(&dir.join("secret.png")), None);
+        assert_eq!(scope.resolve(&root.join("missing.png")), None);
+        // A sibling whose name merely starts with the root's
+        fs::create_dir_all(dir.join("dataset2")).unwrap();
+        fs::write(dir.join("dataset2/b.png"), b"").unwrap();
+        assert_eq!(scope.resolve(&dir.join("dataset2/b.png")), None);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret.png"), root.join("link.png")).unwrap();
+            std::os::unix::fs::symlink(&dir, root.join("up")).unwrap();
+            assert_eq!(scope.resolve(&root.join("link.png")), None);
+            assert_eq!(scope.resolve(&root.join("up/secret.png")), None);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ranges_resolve_to_inclusive_offsets() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=0-9, 20-29", 1000), Some((0, 9)));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=1000-1100", 1000), None);
+        assert_eq!(parse_range("bytes=-0", 1000), None);
+        assert_eq!(parse_range("bytes=50-10", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+        assert_eq!(parse_range("items=0-9", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+    }
+
+    #[test]
+    fn files_are_served_whole_or_in_part() {
+        let dir = test_dir("serve");
+        let path = dir.join("a.png");
+        fs::write(&path, b"0123456789").unwrap();
+
+        let whole = serve_file(&path, "image/png", None);
+        assert_eq!(whole.status(), StatusCode::OK);
+        assert_eq!(whole.body(), b"0123456789");
+
+        let part = serve_file(&path, "image/png", Some("bytes=-3"));
+        assert_eq!(part.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(part.body(), b"789");
+        assert_eq!(part.headers()[header::CONTENT_RANGE], "bytes 7-9/10");
+
+        let unsatisfiable = serve_file(&path, "image/png", Some("bytes=10-"));
+        assert_eq!(unsatisfiable.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(unsatisfiable.headers()[header::CONTENT_RANGE], "bytes */10");
+        assert!(unsatisfiable.body().is_empty());
+
+        assert_eq!(serve_file(&dir.join("missing.png"), "image/png", None).status(), StatusCode::NOT_FOUND);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+use super::stream::{StreamEvent, StreamFormat};
+use super::{CaptionRequest, ModelInfo, Provider, ProviderKind};
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicProvider {
+    base_url: String,
+    api_key: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(base_url: Option<String>, api_key: &str) -> Self {
+        AnthropicProvider {
+            base_url: base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+    }
+}
+
+impl Provider for AnthropicProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Anthropic
+    }
+
+    fn caption_request(&self, client: &Client, request: &CaptionRequest) -> RequestBuilder {
+        let mut body = json!({
+            "model": request.model,
+            "max_tokens": request.options.max_tokens(),
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {
+                        "type": "image",
+                        "source": {
+                            "type": "base64",
+                            "media_type": request.image.media_type,
+                            "data": request.image.data,
+                        }
+                    },
+                    { "type": "text", "text": request.prompt }
+                ]
+            }]
+        });
+        if let Some(system) = &request.options.system_prompt {
+            body["system"] = json!(system);
+        }
+        if let Some(temperature) = request.options.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if request.stream {
+            body["stream"] = json!(true);
+        }
+
+        self.authorized(client.post(format!("{}/messages", self.base_url))).json(&body)
+    }
+
+    fn parse_caption(&self, body: &Value) -> Result<String, String> {
+        body.get("content")
+            .and_then(Value::as_array)
+            .and_then(|content| {
+                content
+                    .iter()
+                    .find(|item| item.get("type").and_then(Value::as_str) == Some("text"))
+                    .and_then(|item| item.get("text"))
+                    .and_then(Value::as_str)
+            })
+            .map(str::to_string)
+            .ok_or_else(|| "No text content found in Claude response".to_string())
+    }
+
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::Sse
+    }
+
+    fn parse_stream_event(&self, event: &Value) -> Result<StreamEvent, String> {
+        match event.get("type").and_then(Value::as_str) {
+            Some("content_block_delta") => Ok(event
+                .pointer("/delta/text")
+                .and_then(Value::as_str)
+                .map(|text| StreamEvent::Delta(text.to_string()))
+                .unwrap_or(StreamEvent::Skip)),
+            Some("message_stop") => Ok(StreamEvent::Done),
+            // Errors can arrive mid-stream, e.g. when the API is overloaded
+            Some("error") => Err(format!(
+                "Anthropic stream error: {}",
+                event.pointer("/error/message").and_then(Value::as_str).unwrap_or("unknown error")
+            )),
+            _ => Ok(StreamEvent::Skip),
+        }
+    }
+
+    fn models_request(&self, client: &Client) -> RequestBuilder {
+        self.authorized(client.get(format!("{}/models", self.base_url)))
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<ModelInfo> {
+        body.get("data")
+            .and_then(Value::as_array)
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|model| {
+                        let id = model.get("id")?.as_str()?.to_string();
+                        let name = model.get("display_name").and_then(Value::as_str).unwrap_or(&id).to_string();
+                        Some(ModelInfo { id, name })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::providers::tests::{body_of, built, caption_request, image, response, round_trip, serve};
+    use crate::providers::GenerationOptions;
+
+    #[test]
+    fn caption_request_sends_image_before_prompt() {
+        let provider = AnthropicProvider::new(None, "key");
+        let image = image();
+        let options = GenerationOptions {
+            system_prompt: Some("Be brief".to_string()),
+            temperature: Some(0.5),
+            ..GenerationOptions::default()
+        };
+        let (request, body) = built(provider.caption_request(&Client::new(), &caption_request(&image, &options, true)));
+
+        assert_eq!(request.url().as_str(), "https://api.anthropic.com/v1/messages");
+        assert_eq!(request.headers()["x-api-key"], "key");
+        assert_eq!(request.headers()["anthropic-version"], ANTHROPIC_VERSION);
+        assert_eq!(body["model"], "test-model");
+        assert_eq!(body["max_tokens"], 300);
+        assert_eq!(body["system"], "Be brief");
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["stream"], true);
+        assert_eq!(body.pointer("/messages/0/content/0/source/data").unwrap(), "aW1hZ2U=");
+        assert_eq!(body.pointer("/messages/0/content/1/text").unwrap(), "Describe the image");
+    }
+
+    #[test]
+    fn parse_caption_takes_the_text_block() {
+        let provider = AnthropicProvider::new(None, "key");
+        let body = json!({ "content": [{ "type": "thinking", "thinking": "..." }, { "type": "text", "text": "A cat" }] });
+        assert_eq!(provider.parse_caption(&body).unwrap(), "A cat");
+        assert!(provider.parse_caption(&json!({ "content": [] })).is_err());
+    }
+
+    #[test]
+    fn stream_events() {
+        let provider = AnthropicProvider::new(None, "key");
+        let delta = json!({ "type": "content_block_delta", "delta": { "type": "text_delta", "text": "A" } });
+        assert_eq!(provider.parse_stream_event(&delta), Ok(StreamEvent::Delta("A".to_string())));
+        assert_eq!(provider.parse_stream_event(&json!({ "type": "ping" })), Ok(StreamEvent::Skip));
+        assert_eq!(provider.parse_stream_event(&json!({ "type": "message_stop" })), Ok(StreamEvent::Done));
+
+        let error = json!({ "type": "error", "error": { "type": "overloaded_error", "message": "Overloaded" } });
+        assert_eq!(provider.parse_stream_event(&error), Err("Anthropic stream error: Overloaded".to_string()));
+    }
+
+    #[test]
+    fn parse_models_falls_back_to_the_id() {
+        let provider = AnthropicProvider::new(None, "key");
+        let body = json!({ "data": [{ "id": "claude-a", "display_name": "Claude A" }, { "id": "claude-b" }] });
+        let models = provider.parse_models(&body);
+        assert_eq!(models.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), ["Claude A", "claude-b"]);
+    }
+
+    #[tokio::test]
+    async fn streams_a_caption_from_a_server() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"A cat "}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"on a mat"}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let body: String = events.iter().map(|event| format!("event: x\ndata: {}\n\n", event)).collect();
+        let (base_url, requests) = serve(vec![response("200 OK", "text/event-stream", &body)]);
+
+        let provider = AnthropicProvider::new(Some(base_url), "key");
+        let (caption, deltas) = round_trip(&provider, true).await.unwrap();
+        assert_eq!(caption, "A cat on a mat");
+        assert_eq!(deltas, ["A cat ", "on a mat"]);
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /messages "));
+        assert!(request.to_lowercase().contains("x-api-key: key"));
+        assert_eq!(body_of(&request)["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn maps_overload_to_a_retryable_error() {
+        let body = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let (base_url, _requests) = serve(vec![response("529 Overloaded", "application/json", body)]);
+
+        let provider = AnthropicProvider::new(Some(base_url), "key");
+        let error = round_trip(&provider, false).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::ProviderUnavailable);
+        assert!(error.is_retryable());
+        assert!(error.message.ends_with("Overloaded"));
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use super::ProviderKind;
+use crate::error::AppError;
+use crate::preprocess::PreparedImage;
+
+// Rate limits and retry behaviour for one provider. Unset limits mean
+// unlimited; the defaults match the lowest paid tier of each cloud API.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProviderLimits {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+    // Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl ProviderLimits {
+    pub fn for_provider(provider: ProviderKind) -> Self {
+        let (requests_per_minute, tokens_per_minute) = match provider {
+            ProviderKind::Anthropic => (Some(50), Some(40_000)),
+            ProviderKind::OpenAi => (Some(500), Some(200_000)),
+            ProviderKind::Ollama | ProviderKind::LmStudio => (None, None),
+        };
+        ProviderLimits {
+            requests_per_minute,
+            tokens_per_minute,
+            max_retries: 3,
+            base_delay_ms: 1_000,
+            max_delay_ms: 60_000,
+        }
+    }
+
+    // "Full jitter" exponential backoff: a random delay up to the capped
+    // exponential, so clients that failed together don't retry together.
+    // A server-provided `retry-after` wins when it asks for longer, but never
+    // past `max_delay_ms`.
+    pub fn retry_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let ceiling = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.min(20))
+            .min(self.max_delay_ms)
+            .max(1);
+        let jittered = Duration::from_millis(random_u64() % (ceiling + 1));
+        match retry_after {
+            Some(after) => after.min(self.max_delay()).max(jittered),
+            None => jittered,
+        }
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_millis(self.max_delay_ms)
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(limit: u32) -> Self {
+        let capacity = f64::from(limit.max(1));
+        TokenBucket { capacity, per_second: capacity / 60.0, available: capacity, updated: Instant::now() }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    // How long until `amount` is available (capped at the bucket size, so one
+    // oversized request can still go through on a full bucket)
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.per_second)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+}
+
+struct ProviderState {
+    limits: ProviderLimits,
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+    // Set from `retry-after` on a 429, holding back every request to the provider
+    blocked_until: Option<Instant>,
+}
+
+impl ProviderState {
+    fn new(limits: ProviderLimits) -> Self {
+        ProviderState {
+            requests: limits.requests_per_minute.map(TokenBucket::per_minute),
+            tokens: limits.tokens_per_minute.map(TokenBucket::per_minute),
+            blocked_until: None,
+            limits,
+        }
+    }
+}
+
+// Per-provider token buckets shared by every request the app makes, so
+// single captions and batch jobs draw from the same budget
+#[derive(Default)]
+pub struct RateLimiter {
+    providers: Mutex<HashMap<ProviderKind, ProviderState>>,
+}
+
+impl RateLimiter {
+    fn with_state<T>(&self, provider: ProviderKind, f: impl FnOnce(&mut ProviderState) -> T) -> T {
+        let mut providers = self.providers.lock().unwrap_or_else(|e| e.into_inner());
+        let state = providers
+            .entry(provider)
+            .or_insert_with(|| ProviderState::new(ProviderLimits::for_provider(provider)));
+        f(state)
+    }
+
+    pub fn limits(&self, provider: ProviderKind) -> ProviderLimits {
+        self.with_state(provider, |state| state.limits.clone())
+    }
+
+    pub fn set_limits(&self, provider: ProviderKind, limits: ProviderLimits) -> Result<(), AppError> {
+        if limits.requests_per_minute == Some(0) || limits.tokens_per_minute == Some(0) {
+            return Err(AppError::invalid("Rate limits must be at least 1 per minute; leave them unset for no limit"));
+        }
+        if limits.base_delay_ms > limits.max_delay_ms {
+            return Err(AppError::invalid("Base retry delay can't be longer than the maximum delay"));
+        }
+        self.with_state(provider, |state| *state = ProviderState::new(limits));
+        Ok(())
+    }
+
+    // Wait until one request costing `tokens` fits in the provider's budget
+    pub async fn acquire(&self, provider: ProviderKind, tokens: u64) {
+        loop {
+            let wait = self.with_state(provider, |state| {
+                let now = Instant::now();
+                if let Some(until) = state.blocked_until.filter(|until| *until > now) {
+                    return until - now;
+                }
+                state.blocked_until = None;
+
+                let amount = tokens as f64;
+                let mut wait = Duration::ZERO;
+                if let Some(bucket) = &mut state.requests {
+                    bucket.refill(now);
+                    wait = wait.max(bucket.wait_for(1.0));
+                }
+                if let Some(bucket) = &mut state.tokens {
+                    bucket.refill(now);
+                    wait = wait.max(bucket.wait_for(amount));
+                }
+                if wait.is_zero() {
+                    if let Some(bucket) = &mut state.requests {
+                        bucket.take(1.0);
+                    }
+                    if let Some(bucket) = &mut state.tokens {
+                        bucket.take(amount);
+                    }
+                }
+                wait
+            });
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Hold back all requests to a provider that told us to slow down, for at
+    // most the provider's maximum retry delay
+    pub fn block_for(&self, provider: ProviderKind, duration: Duration) {
+        self.with_state(provider, |state| {
+            let Some(until) = Instant::now().checked_add(duration.min(state.limits.max_delay())) else {
+                return;
+            };
+            if state.blocked_until < Some(until) {
+                state.blocked_until = Some(until);
+            }
+        });
+    }
+}
+
+// Rough token cost of a caption request, used only for rate limiting. Image
+// costs follow each provider's published formula; text is ~4 chars a token.
+pub fn estimate_tokens(provider: ProviderKind, image: &PreparedImage, prompt: &str, max_tokens: u32) -> u64 {
+    let (width, height) = (u64::from(image.width), u64::from(image.height));
+    let image_tokens = match provider {
+        ProviderKind::Anthropic => width * height / 750,
+        // 85 base tokens plus 170 per 512px tile, after the image is fitted
+        // into 2048x2048 and then scaled so its short side is at most 768px
+        ProviderKind::OpenAi => {
+            let fit = (2048.0 / width.max(height).max(1) as f64).min(1.0);
+            let (width, height) = (width as f64 * fit, height as f64 * fit);
+            let scale = (768.0 / width.min(height).max(1.0)).min(1.0);
+            let tiles = |side: f64| (side * scale / 512.0).ceil() as u64;
+            85 + 170 * tiles(width) * tiles(height)
+        }
+        ProviderKind::Ollama | ProviderKind::LmStudio => 0,
+    };
+    image_tokens + prompt.len() as u64 / 4 + u64::from(max_tokens)
+}
+
+// `retry-after` as seconds or an HTTP date, or OpenAI's millisecond variant.
+// Values that aren't a usable duration (negative, `inf`, `1e30`) are ignored;
+// callers cap the rest at the provider's maximum delay.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+    let seconds = |secs: f64| Duration::try_from_secs_f64(secs).ok();
+    if let Some(after) = header("retry-after-ms").and_then(|value| value.parse::<f64>().ok()).and_then(|ms| seconds(ms / 1000.0)) {
+        return Some(after);
+    }
+    let value = header("retry-after")?;
+    match value.parse::<f64>() {
+        Ok(secs) => seconds(secs),
+        Err(_) => {
+            let at = httpdate::parse_http_date(value).ok()?;
+            Some(at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+        }
+    }
+}
+
+// Good enough randomness for jitter without pulling in a RNG crate
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(Instant::now().elapsed().as_nanos());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn retry_after_parses_seconds_and_milliseconds() {
+        assert_eq!(retry_after(&headers(&[("retry-after", "2")])), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&headers(&[("retry-after", "1.5")])), Some(Duration::from_millis(1500)));
+        assert_eq!(
+            retry_after(&headers(&[("retry-after-ms", "250"), ("retry-after", "9")])),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(retry_after(&headers(&[])), None);
+    }
+
+    #[test]
+    fn retry_after_ignores_unusable_values() {
+        for value in ["inf", "NaN", "1e30", "-5", "soon"] {
+            assert_eq!(retry_after(&headers(&[("retry-after", value)])), None, "{}", value);
+        }
+        assert_eq!(retry_after(&headers(&[("retry-after-ms", "inf")])), None);
+    }
+
+    #[test]
+    fn retry_after_accepts_http_dates() {
+        let past = headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")]);
+        assert_eq!(retry_after(&past), Some(Duration::ZERO));
+
+        let soon = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let after = retry_after(&headers(&[("retry-after", &soon)])).unwrap();
+        assert!(after > Duration::from_secs(25) && after <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn retry_delay_is_capped_at_max_delay() {
+        let limits = ProviderLimits::for_provider(ProviderKind::OpenAi);
+        let delay = limits.retry_delay(0, Some(Duration::from_secs(u64::MAX / 2)));
+        assert_eq!(delay, limits.max_delay());
+    }
+
+    #[test]
+    fn block_for_survives_huge_durations() {
+        let limiter = RateLimiter::default();
+        limiter.block_for(ProviderKind::Anthropic, Duration::MAX);
+        limiter.with_state(ProviderKind::Anthropic, |state| {
+            let until = state.blocked_until.unwrap();
+            assert!(until <= Instant::now() + state.limits.max_delay());
+        });
+    }
+}
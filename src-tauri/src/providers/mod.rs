@@ -0,0 +1,580 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::dictionary::DictionaryReport;
+use crate::filters::FilterReport;
+use crate::error::{AppError, ErrorCode};
+use crate::preprocess::{self, PreparedImage, PreprocessOptions, Transformation};
+
+mod anthropic;
+pub mod limits;
+pub mod ollama;
+mod openai;
+pub mod stream;
+
+use anthropic::AnthropicProvider;
+use limits::RateLimiter;
+use ollama::OllamaProvider;
+use openai::OpenAiCompatibleProvider;
+use stream::{CancelToken, LineBuffer, StreamEvent, StreamFormat};
+
+const DEFAULT_MAX_TOKENS: u32 = 300;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Anthropic,
+    #[serde(rename = "openai")]
+    OpenAi,
+    Ollama,
+    #[serde(rename = "lmstudio")]
+    LmStudio,
+}
+
+impl ProviderKind {
+    pub fn display_name(self) -> &'static str {
+        match self {
+            ProviderKind::Anthropic => "Anthropic",
+            ProviderKind::OpenAi => "OpenAI",
+            ProviderKind::Ollama => "Ollama",
+            ProviderKind::LmStudio => "LM Studio",
+        }
+    }
+}
+
+// Connection and sampling settings for one caption request. Everything is
+// optional; unset fields fall back to each provider's defaults.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct GenerationOptions {
+    pub api_key: Option<String>,
+    // Overrides the provider's endpoint, e.g. a LAN Ollama box or a mock server
+    pub base_url: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub system_prompt: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub preprocess: Option<PreprocessOptions>,
+    // Ollama only: how long the model stays loaded ("10m", 0, -1, ...)
+    pub keep_alive: Option<Value>,
+    // Ollama only: raw model options such as num_ctx, top_p or seed
+    pub model_options: Option<serde_json::Map<String, Value>>,
+}
+
+impl GenerationOptions {
+    pub fn max_tokens(&self) -> u32 {
+        self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS)
+    }
+}
+
+// One image to caption and how to caption it
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaptionJob {
+    pub provider: ProviderKind,
+    pub image_path: PathBuf,
+    pub model: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub options: GenerationOptions,
+}
+
+// Everything a provider needs to build one caption request
+pub struct CaptionRequest<'a> {
+    pub model: &'a str,
+    pub prompt: &'a str,
+    pub image: &'a PreparedImage,
+    pub options: &'a GenerationOptions,
+    // Ask for incremental output instead of one complete response
+    pub stream: bool,
+}
+
+// Incremental caption text forwarded to the frontend while streaming
+#[derive(Debug, Serialize, Clone)]
+pub struct CaptionDelta {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptionResult {
+    pub caption: String,
+    pub provider: ProviderKind,
+    pub model: String,
+    // What preprocessing did to the image before it was sent
+    pub transformations: Vec<Transformation>,
+    pub duration_ms: u64,
+    // Set when the tag dictionaries rewrote `caption`
+    pub dictionary: Option<DictionaryReport>,
+    // Set when the project's caption filters apply to the image
+    pub filtered: Option<FilterReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelInfo {
+    pub id: String,
+    pub name: String,
+}
+
+// A captioning backend. Providers only translate between our request and
+// their wire format; sending, status handling and timeouts are shared.
+pub trait Provider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    fn caption_request(&self, client: &Client, request: &CaptionRequest) -> RequestBuilder;
+
+    // Pull the caption text out of a complete (non-streaming) response
+    fn parse_caption(&self, body: &Value) -> Result<String, String>;
+
+    fn stream_format(&self) -> StreamFormat;
+
+    // Decode one JSON event of a streaming response
+    fn parse_stream_event(&self, event: &Value) -> Result<StreamEvent, String>;
+
+    fn models_request(&self, client: &Client) -> RequestBuilder;
+
+    fn parse_models(&self, body: &Value) -> Vec<ModelInfo>;
+}
+
+pub fn provider_for(kind: ProviderKind, options: &GenerationOptions) -> Result<Box<dyn Provider>, AppError> {
+    let base_url = options.base_url.as_deref().map(|url| url.trim().trim_end_matches('/').to_string());
+    let api_key = options.api_key.as_deref().map(str::trim).filter(|key| !key.is_empty());
+    let missing_key = |message: &str| AppError::provider(kind, ErrorCode::MissingApiKey, message);
+
+    Ok(match kind {
+        ProviderKind::Anthropic => Box::new(AnthropicProvider::new(
+            base_url,
+            api_key.ok_or_else(|| missing_key("Anthropic API key is required for Claude models"))?,
+        )),
+        ProviderKind::OpenAi => Box::new(OpenAiCompatibleProvider::openai(
+            base_url,
+            api_key.ok_or_else(|| missing_key("OpenAI API key is required for OpenAI models"))?,
+        )),
+        ProviderKind::LmStudio => Box::new(OpenAiCompatibleProvider::lmstudio(base_url)),
+        ProviderKind::Ollama => Box::new(OllamaProvider::new(ollama::base_url(base_url.as_deref()))),
+    })
+}
+
+// Shared HTTP client, so connections to a provider are reused across captions
+pub fn http_client() -> Client {
+    Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+}
+
+// Caption one image: preprocess it for the provider, send it and return the
+// text. With `on_delta`, the response is streamed and each piece of text is
+// passed on as it arrives. Cancelling `cancel` aborts at the next await point.
+// Requests wait for the provider's rate limits and retry transient failures.
+pub async fn generate_caption(
+    client: &Client,
+    limiter: &RateLimiter,
+    job: &CaptionJob,
+    on_delta: Option<&(dyn Fn(&str) + Send + Sync)>,
+    cancel: &CancelToken,
+) -> Result<CaptionResult, AppError> {
+    let provider = provider_for(job.provider, &job.options)?;
+    let started = Instant::now();
+
+    let image_path = job.image_path.clone();
+    let kind = job.provider;
+    let preprocess_options = job.options.preprocess.clone().unwrap_or_default();
+    let image = tauri::async_runtime::spawn_blocking(move || {
+        preprocess::prepare_image(&image_path, kind, &preprocess_options)
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, format!("Image preprocessing failed: {}", e)))??;
+    if cancel.is_cancelled() {
+        return Err(AppError::cancelled());
+    }
+
+    let request = CaptionRequest {
+        model: &job.model,
+        prompt: &job.prompt,
+        image: &image,
+        options: &job.options,
+        stream: on_delta.is_some(),
+    };
+    let timeout = job.options.timeout_secs.map(Duration::from_secs).unwrap_or(DEFAULT_TIMEOUT);
+    let cost = limits::estimate_tokens(kind, &image, &job.prompt, job.options.max_tokens());
+    let response = send_with_retry(provider.as_ref(), limiter, cost, cancel, || {
+        provider.caption_request(client, &request).timeout(timeout)
+    })
+    .await?;
+    let caption = match on_delta {
+        Some(on_delta) => read_stream(provider.as_ref(), response, on_delta, cancel).await?,
+        None => {
+            let body = tokio::select! {
+                body = read_json(provider.as_ref(), response) => body?,
+                _ = cancel.cancelled() => return Err(AppError::cancelled()),
+            };
+            provider
+                .parse_caption(&body)
+                .map_err(|e| AppError::provider(kind, ErrorCode::ProviderError, e))?
+        }
+    };
+
+    Ok(CaptionResult {
+        caption,
+        provider: kind,
+        model: job.model.clone(),
+        transformations: image.transformations,
+        duration_ms: started.elapsed().as_millis() as u64,
+        dictionary: None,
+        filtered: None,
+    })
+}
+
+pub async fn list_models(client: &Client, kind: ProviderKind, options: &GenerationOptions) -> Result<Vec<ModelInfo>, AppError> {
+    let provider = provider_for(kind, options)?;
+    let request = provider.models_request(client).timeout(Duration::from_secs(10));
+    let response = send(provider.as_ref(), request).await?;
+    let body = read_json(provider.as_ref(), response).await?;
+    Ok(provider.parse_models(&body))
+}
+
+// Send a request, turning transport failures and error statuses into errors
+// that say whether trying again later could help
+pub(crate) async fn send(provider: &dyn Provider, request: RequestBuilder) -> Result<Response, AppError> {
+    let response = request.send().await.map_err(|e| transport_error(provider.kind(), e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = limits::retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        return Err(status_error(provider.kind(), status, &body, retry_after));
+    }
+    Ok(response)
+}
+
+pub(crate) fn transport_error(kind: ProviderKind, e: reqwest::Error) -> AppError {
+    let name = kind.display_name();
+    let error = if e.is_timeout() {
+        AppError::provider(kind, ErrorCode::Timeout, format!("{} request timed out: {}", name, e))
+    } else if e.is_connect() {
+        AppError::provider(kind, ErrorCode::ProviderUnavailable, format!("Could not connect to {}: {}", name, e))
+    } else {
+        AppError::provider(kind, ErrorCode::ProviderError, format!("{} request failed: {}", name, e))
+    };
+    // Timeouts and dropped connections are transient; a malformed request or
+    // bad URL fails the same way every time
+    if e.is_timeout() || e.is_connect() || (e.is_request() && !e.is_builder()) {
+        error.retryable(None)
+    } else {
+        error
+    }
+}
+
+// Rate limits, overload (Anthropic's 529) and server-side hiccups are worth
+// retrying; auth failures, bad requests and missing models are not
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 409 | 425 | 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+// Send with rate limiting and retries. `build` makes a fresh request for each
+// attempt, since a sent request can't be reused.
+async fn send_with_retry(
+    provider: &dyn Provider,
+    limiter: &RateLimiter,
+    cost: u64,
+    cancel: &CancelToken,
+    build: impl Fn() -> RequestBuilder,
+) -> Result<Response, AppError> {
+    let kind = provider.kind();
+    let policy = limiter.limits(kind);
+    let mut attempt = 0;
+    loop {
+        let result = tokio::select! {
+            result = async {
+                limiter.acquire(kind, cost).await;
+                send(provider, build()).await
+            } => result,
+            _ = cancel.cancelled() => return Err(AppError::cancelled()),
+        };
+        let mut error = match result {
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+        if !error.is_retryable() {
+            return Err(error);
+        }
+        if attempt >= policy.max_retries {
+            if attempt > 0 {
+                error.message = format!("{} (gave up after {} attempts)", error.message, attempt + 1);
+            }
+            return Err(error);
+        }
+
+        if let Some(after) = error.retry_after() {
+            limiter.block_for(kind, after);
+        }
+        let delay = policy.retry_delay(attempt, error.retry_after());
+        attempt += 1;
+        println!(
+            "{}; retrying in {:.1}s (attempt {} of {})",
+            error.message,
+            delay.as_secs_f32(),
+            attempt + 1,
+            policy.max_retries + 1
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = cancel.cancelled() => return Err(AppError::cancelled()),
+        }
+    }
+}
+
+async fn read_json(provider: &dyn Provider, response: Response) -> Result<Value, AppError> {
+    let kind = provider.kind();
+    let body = response.text().await.map_err(|e| transport_error(kind, e))?;
+    serde_json::from_str(&body).map_err(|e| {
+        AppError::provider(kind, ErrorCode::ProviderError, format!("Invalid response from {}: {}", kind.display_name(), e))
+    })
+}
+
+// Decode a streaming response line by line, forwarding text as it arrives
+async fn read_stream(
+    provider: &dyn Provider,
+    mut response: Response,
+    on_delta: &(dyn Fn(&str) + Send + Sync),
+    cancel: &CancelToken,
+) -> Result<String, AppError> {
+    let kind = provider.kind();
+    let format = provider.stream_format();
+    let mut lines = LineBuffer::default();
+    let mut caption = String::new();
+
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk.map_err(|e| {
+                AppError::provider(kind, ErrorCode::ProviderError, format!("{} stream failed: {}", kind.display_name(), e))
+            })?,
+            _ = cancel.cancelled() => return Err(AppError::cancelled()),
+        };
+        let ended = chunk.is_none();
+        let batch = match chunk {
+            Some(bytes) => lines.push(&bytes),
+            None => lines.finish().into_iter().collect(),
+        };
+
+        for line in batch {
+            let Some(payload) = stream::line_payload(format, &line) else { continue };
+            // OpenAI-style streams end with a bare `[DONE]` sentinel
+            if payload == "[DONE]" {
+                return Ok(caption);
+            }
+            let Some(event) = stream::parse_json(payload) else { continue };
+            let event = provider
+                .parse_stream_event(&event)
+                .map_err(|e| AppError::provider(kind, ErrorCode::ProviderError, e))?;
+            match event {
+                StreamEvent::Delta(text) => {
+                    on_delta(&text);
+                    caption.push_str(&text);
+                }
+                StreamEvent::Done => return Ok(caption),
+                StreamEvent::Skip => {}
+            }
+        }
+
+        if ended {
+            return Ok(caption);
+        }
+    }
+}
+
+pub(crate) fn status_error(kind: ProviderKind, status: StatusCode, body: &str, retry_after: Option<Duration>) -> AppError {
+    let name = kind.display_name();
+    // Providers wrap the useful part as `{"error": {"message": ...}}` or `{"error": "..."}`
+    let detail = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| {
+            let error = value.get("error")?;
+            error
+                .get("message")
+                .and_then(Value::as_str)
+                .or_else(|| error.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| body.trim().to_string());
+
+    let (code, message) = match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            (ErrorCode::AuthFailed, format!("{} authentication failed ({}): {}", name, status, detail))
+        }
+        StatusCode::TOO_MANY_REQUESTS => {
+            (ErrorCode::RateLimited, format!("{} rate limit exceeded ({}): {}", name, status, detail))
+        }
+        StatusCode::BAD_REQUEST | StatusCode::NOT_FOUND | StatusCode::PAYLOAD_TOO_LARGE | StatusCode::UNPROCESSABLE_ENTITY => {
+            (ErrorCode::ProviderRejected, format!("{} rejected the request ({}): {}", name, status, detail))
+        }
+        _ if status.is_server_error() || status.as_u16() == 529 => {
+            (ErrorCode::ProviderUnavailable, format!("{} is unavailable ({}): {}", name, status, detail))
+        }
+        _ => (ErrorCode::ProviderError, format!("{} request failed ({}): {}", name, status, detail)),
+    };
+    let error = AppError::provider(kind, code, message).with_status(status.as_u16());
+    if is_retryable(status) {
+        error.retryable(retry_after)
+    } else {
+        error
+    }
+}
+
+// `data:` URL for the prepared image, as OpenAI-style APIs expect
+pub fn data_url(image: &PreparedImage) -> String {
+    format!("data:{};base64,{}", image.media_type, image.data)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+    use super::*;
+    use super::stream::cancel_pair;
+
+    // A local HTTP server that answers each connection with the next canned
+    // response. The raw requests it received come out of the receiver.
+    pub fn serve(responses: Vec<String>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let (requests, received) = mpsc::channel();
+        thread::spawn(move || {
+            for response in responses {
+                let Ok((stream, _)) = listener.accept() else { return };
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                    request.push_str(&line);
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).ok();
+                request.push_str("\r\n");
+                request.push_str(&String::from_utf8_lossy(&body));
+                requests.send(request).ok();
+                reader.get_mut().write_all(response.as_bytes()).ok();
+            }
+        });
+        (base_url, received)
+    }
+
+    pub fn response(status: &str, content_type: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    // The JSON body of a request captured by `serve`
+    pub fn body_of(request: &str) -> Value {
+        serde_json::from_str(request.split_once("\r\n\r\n").unwrap().1).unwrap()
+    }
+
+    // Build a request without sending it, returning it with its JSON body
+    pub fn built(request: RequestBuilder) -> (reqwest::Request, Value) {
+        let request = request.build().unwrap();
+        let body = request.body().and_then(|body| body.as_bytes()).map(|bytes| serde_json::from_slice(bytes).unwrap());
+        (request, body.unwrap_or(Value::Null))
+    }
+
+    pub fn image() -> PreparedImage {
+        PreparedImage {
+            data: "aW1hZ2U=".to_string(),
+            media_type: "image/png".to_string(),
+            width: 1,
+            height: 1,
+            original_width: 1,
+            original_height: 1,
+            original_format: "png".to_string(),
+            byte_size: 5,
+            transformations: Vec::new(),
+        }
+    }
+
+    pub fn caption_request<'a>(image: &'a PreparedImage, options: &'a GenerationOptions, stream: bool) -> CaptionRequest<'a> {
+        CaptionRequest { model: "test-model", prompt: "Describe the image", image, options, stream }
+    }
+
+    // Send a caption request and read the answer back the way
+    // `generate_caption` does, returning the caption and any streamed pieces
+    pub async fn round_trip(provider: &dyn Provider, stream: bool) -> Result<(String, Vec<String>), AppError> {
+        let client = Client::new();
+        let image = image();
+        let options = GenerationOptions::default();
+        let request = provider.caption_request(&client, &caption_request(&image, &options, stream));
+        let response = send(provider, request).await?;
+        if !stream {
+            let body = read_json(provider, response).await?;
+            let caption = provider
+                .parse_caption(&body)
+                .map_err(|e| AppError::provider(provider.kind(), ErrorCode::ProviderError, e))?;
+            return Ok((caption, Vec::new()));
+        }
+
+        let deltas = Mutex::new(Vec::new());
+        let (_handle, cancel) = cancel_pair();
+        let on_delta = |text: &str| deltas.lock().unwrap().push(text.to_string());
+        let caption = read_stream(provider, response, &on_delta, &cancel).await?;
+        Ok((caption, deltas.into_inner().unwrap()))
+    }
+
+    #[test]
+    fn status_errors_say_whether_to_retry() {
+        let kind = ProviderKind::OpenAi;
+        let auth = status_error(kind, StatusCode::UNAUTHORIZED, r#"{"error": {"message": "bad key"}}"#, None);
+        assert_eq!(auth.code, ErrorCode::AuthFailed);
+        assert!(auth.message.ends_with("bad key"));
+        assert!(!auth.is_retryable());
+
+        let limited = status_error(kind, StatusCode::TOO_MANY_REQUESTS, "slow down", Some(Duration::from_secs(3)));
+        assert_eq!(limited.code, ErrorCode::RateLimited);
+        assert_eq!(limited.retry_after(), Some(Duration::from_secs(3)));
+
+        let missing = status_error(kind, StatusCode::NOT_FOUND, r#"{"error": "model not found"}"#, None);
+        assert_eq!(missing.code, ErrorCode::ProviderRejected);
+        assert!(missing.message.ends_with("model not found"));
+
+        let overloaded = status_error(ProviderKind::Anthropic, StatusCode::from_u16(529).unwrap(), "", None);
+        assert_eq!(overloaded.code, ErrorCode::ProviderUnavailable);
+        assert!(overloaded.is_retryable());
+        assert_eq!(overloaded.provider.unwrap().status, Some(529));
+    }
+
+    #[test]
+    fn provider_for_requires_cloud_keys() {
+        let options = GenerationOptions { api_key: Some("  ".to_string()), ..GenerationOptions::default() };
+        let error = provider_for(ProviderKind::Anthropic, &options).err().unwrap();
+        assert_eq!(error.code, ErrorCode::MissingApiKey);
+        assert!(provider_for(ProviderKind::LmStudio, &options).is_ok());
+    }
+
+    #[tokio::test]
+    async fn unreachable_servers_are_retryable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let options = GenerationOptions { base_url: Some(base_url), ..GenerationOptions::default() };
+        let provider = provider_for(ProviderKind::Ollama, &options).unwrap();
+        let error = round_trip(provider.as_ref(), false).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::ProviderUnavailable);
+        assert!(error.is_retryable());
+    }
+}
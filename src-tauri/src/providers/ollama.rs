@@ -0,0 +1,378 @@
+use reqwest::{Client, Method, RequestBuilder};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::stream::{self, CancelToken, LineBuffer, StreamEvent, StreamFormat};
+use super::{status_error, transport_error, CaptionRequest, ModelInfo, Provider, ProviderKind};
+use crate::error::{AppError, ErrorCode};
+
+pub const DEFAULT_BASE_URL: &str = "http://127.0.0.1:11434";
+
+pub struct OllamaProvider {
+    base_url: String,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: String) -> Self {
+        OllamaProvider { base_url }
+    }
+}
+
+impl Provider for OllamaProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ollama
+    }
+
+    fn caption_request(&self, client: &Client, request: &CaptionRequest) -> RequestBuilder {
+        // Model options (num_ctx, top_p, seed, ...) pass straight through;
+        // the dedicated fields win over anything set there
+        let mut options = json!(request.options.model_options.clone().unwrap_or_default());
+        options["num_predict"] = json!(request.options.max_tokens());
+        if let Some(temperature) = request.options.temperature {
+            options["temperature"] = json!(temperature);
+        }
+
+        let mut messages = Vec::new();
+        if let Some(system) = &request.options.system_prompt {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.push(json!({
+            "role": "user",
+            "content": request.prompt,
+            "images": [request.image.data],
+        }));
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": request.stream,
+            "options": options,
+        });
+        if let Some(keep_alive) = &request.options.keep_alive {
+            body["keep_alive"] = keep_alive.clone();
+        }
+
+        client.post(format!("{}/api/chat", self.base_url)).json(&body)
+    }
+
+    fn parse_caption(&self, body: &Value) -> Result<String, String> {
+        body.pointer("/message/content")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| "No caption found in Ollama response".to_string())
+    }
+
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::Ndjson
+    }
+
+    fn parse_stream_event(&self, event: &Value) -> Result<StreamEvent, String> {
+        if let Some(error) = event.get("error").and_then(Value::as_str) {
+            return Err(format!("Ollama stream error: {}", error));
+        }
+        match event.pointer("/message/content").and_then(Value::as_str) {
+            Some(text) if !text.is_empty() => Ok(StreamEvent::Delta(text.to_string())),
+            _ if event.get("done").and_then(Value::as_bool) == Some(true) => Ok(StreamEvent::Done),
+            _ => Ok(StreamEvent::Skip),
+        }
+    }
+
+    fn models_request(&self, client: &Client) -> RequestBuilder {
+        client.get(format!("{}/api/tags", self.base_url))
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<ModelInfo> {
+        body.get("models")
+            .and_then(Value::as_array)
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|model| {
+                        let name = model.get("name")?.as_str()?.to_string();
+                        Some(ModelInfo { id: name.clone(), name })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// What `/api/show` tells us about an installed model
+#[derive(Debug, Serialize)]
+pub struct OllamaModelDetails {
+    pub name: String,
+    pub family: Option<String>,
+    pub parameter_size: Option<String>,
+    pub quantization: Option<String>,
+    // As reported by Ollama, e.g. ["completion", "vision"]
+    pub capabilities: Vec<String>,
+    pub vision: bool,
+    // Maximum context the model was trained for
+    pub context_length: Option<u64>,
+    // Context configured in the Modelfile (`num_ctx`), if overridden
+    pub num_ctx: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PullProgress {
+    pub model: String,
+    pub status: String,
+    pub digest: Option<String>,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+    pub done: bool,
+}
+
+// Normalise a configured Ollama address; users often paste it with `/api`
+pub fn base_url(base_url: Option<&str>) -> String {
+    base_url
+        .map(|url| url.trim().trim_end_matches('/').trim_end_matches("/api").to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
+
+// HTTP method for a raw `/api/<endpoint>` call
+pub fn method_for(endpoint: &str) -> Method {
+    match endpoint {
+        "tags" | "ps" | "version" => Method::GET,
+        "delete" => Method::DELETE,
+        _ => Method::POST,
+    }
+}
+
+pub async fn show_model(client: &Client, base_url: &str, model: &str) -> Result<OllamaModelDetails, AppError> {
+    let kind = ProviderKind::Ollama;
+    let response = client
+        .post(format!("{}/api/show", base_url))
+        .json(&json!({ "model": model }))
+        .send()
+        .await
+        .map_err(|e| transport_error(kind, e))?;
+    let status = response.status();
+    let body = response.text().await.map_err(|e| transport_error(kind, e))?;
+    if !status.is_success() {
+        return Err(status_error(kind, status, &body, None));
+    }
+    let body: Value = serde_json::from_str(&body)
+        .map_err(|e| AppError::provider(kind, ErrorCode::ProviderError, format!("Invalid response from Ollama: {}", e)))?;
+
+    let details = body.get("details");
+    let detail = |key: &str| details.and_then(|d| d.get(key)).and_then(Value::as_str).map(str::to_string);
+    let capabilities: Vec<String> = body
+        .get("capabilities")
+        .and_then(Value::as_array)
+        .map(|caps| caps.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        .unwrap_or_default();
+
+    // Older Ollama versions don't report capabilities; fall back to spotting a
+    // vision projector in the model metadata
+    let model_info = body.get("model_info").and_then(Value::as_object);
+    let has_projector = body.get("projector_info").is_some()
+        || details
+            .and_then(|d| d.get("families"))
+            .and_then(Value::as_array)
+            .map(|families| families.iter().any(|f| matches!(f.as_str(), Some("clip") | Some("mllama"))))
+            .unwrap_or(false)
+        || model_info.map(|info| info.keys().any(|key| key.contains(".vision."))).unwrap_or(false);
+    let vision = capabilities.iter().any(|c| c == "vision") || has_projector;
+
+    let context_length = model_info.and_then(|info| {
+        let architecture = info.get("general.architecture")?.as_str()?;
+        info.get(&format!("{}.context_length", architecture))?.as_u64()
+    });
+    // `parameters` is the Modelfile's PARAMETER block as plain text
+    let num_ctx = body.get("parameters").and_then(Value::as_str).and_then(|parameters| {
+        parameters.lines().find_map(|line| {
+            let mut parts = line.split_whitespace();
+            (parts.next()? == "num_ctx").then(|| parts.next()?.parse().ok())?
+        })
+    });
+
+    Ok(OllamaModelDetails {
+        name: model.to_string(),
+        family: detail("family"),
+        parameter_size: detail("parameter_size"),
+        quantization: detail("quantization_level"),
+        capabilities,
+        vision,
+        context_length,
+        num_ctx,
+    })
+}
+
+// Download a model, reporting each progress line Ollama streams back
+pub async fn pull_model(
+    client: &Client,
+    base_url: &str,
+    model: &str,
+    on_progress: &(dyn Fn(PullProgress) + Send + Sync),
+    cancel: &CancelToken,
+) -> Result<(), AppError> {
+    let kind = ProviderKind::Ollama;
+    let mut response = client
+        .post(format!("{}/api/pull", base_url))
+        .json(&json!({ "model": model, "stream": true }))
+        .send()
+        .await
+        .map_err(|e| transport_error(kind, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(status_error(kind, status, &body, None));
+    }
+
+    let mut lines = LineBuffer::default();
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk.map_err(|e| transport_error(kind, e))?,
+            _ = cancel.cancelled() => {
+                return Err(AppError::provider(kind, ErrorCode::Cancelled, format!("Pull of {} cancelled", model)))
+            }
+        };
+        let ended = chunk.is_none();
+        let batch = match chunk {
+            Some(bytes) => lines.push(&bytes),
+            None => lines.finish().into_iter().collect(),
+        };
+
+        for line in batch {
+            let Some(event) = stream::line_payload(StreamFormat::Ndjson, &line).and_then(stream::parse_json) else {
+                continue;
+            };
+            if let Some(error) = event.get("error").and_then(Value::as_str) {
+                return Err(AppError::provider(kind, ErrorCode::ProviderError, format!("Ollama pull failed: {}", error)));
+            }
+            let status = event.get("status").and_then(Value::as_str).unwrap_or_default().to_string();
+            let done = status == "success";
+            on_progress(PullProgress {
+                model: model.to_string(),
+                status,
+                digest: event.get("digest").and_then(Value::as_str).map(str::to_string),
+                total: event.get("total").and_then(Value::as_u64),
+                completed: event.get("completed").and_then(Value::as_u64),
+                done,
+            });
+            if done {
+                return Ok(());
+            }
+        }
+
+        if ended {
+            return Err(AppError::provider(
+                kind,
+                ErrorCode::ProviderError,
+                format!("Ollama pull of {} ended before completing", model),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+    use crate::providers::stream::cancel_pair;
+    use crate::providers::tests::{body_of, built, caption_request, image, response, round_trip, serve};
+    use crate::providers::GenerationOptions;
+
+    #[test]
+    fn caption_request_merges_model_options() {
+        let provider = OllamaProvider::new(DEFAULT_BASE_URL.to_string());
+        let image = image();
+        let model_options = json!({ "num_ctx": 8192, "num_predict": 10 });
+        let options = GenerationOptions {
+            max_tokens: Some(120),
+            keep_alive: Some(json!("10m")),
+            model_options: model_options.as_object().cloned(),
+            ..GenerationOptions::default()
+        };
+        let (request, body) = built(provider.caption_request(&Client::new(), &caption_request(&image, &options, false)));
+
+        assert_eq!(request.url().as_str(), "http://127.0.0.1:11434/api/chat");
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["keep_alive"], "10m");
+        assert_eq!(body.pointer("/options/num_ctx").unwrap(), 8192);
+        assert_eq!(body.pointer("/options/num_predict").unwrap(), 120);
+        assert_eq!(body.pointer("/messages/0/images/0").unwrap(), "aW1hZ2U=");
+    }
+
+    #[test]
+    fn stream_events() {
+        let provider = OllamaProvider::new(DEFAULT_BASE_URL.to_string());
+        let delta = json!({ "message": { "role": "assistant", "content": "A" }, "done": false });
+        assert_eq!(provider.parse_stream_event(&delta), Ok(StreamEvent::Delta("A".to_string())));
+        let done = json!({ "message": { "role": "assistant", "content": "" }, "done": true });
+        assert_eq!(provider.parse_stream_event(&done), Ok(StreamEvent::Done));
+        let error = json!({ "error": "model not found" });
+        assert_eq!(provider.parse_stream_event(&error), Err("Ollama stream error: model not found".to_string()));
+    }
+
+    #[test]
+    fn base_url_drops_the_api_suffix() {
+        assert_eq!(base_url(Some(" http://box:11434/api/ ")), "http://box:11434");
+        assert_eq!(base_url(Some("")), DEFAULT_BASE_URL);
+        assert_eq!(base_url(None), DEFAULT_BASE_URL);
+        assert_eq!(method_for("tags"), Method::GET);
+        assert_eq!(method_for("delete"), Method::DELETE);
+        assert_eq!(method_for("generate"), Method::POST);
+    }
+
+    #[tokio::test]
+    async fn streams_a_caption_from_a_server() {
+        let body = concat!(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"A cat\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\" on a mat\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}",
+        );
+        let (base_url, requests) = serve(vec![response("200 OK", "application/x-ndjson", body)]);
+
+        let provider = OllamaProvider::new(base_url);
+        let (caption, deltas) = round_trip(&provider, true).await.unwrap();
+        assert_eq!(caption, "A cat on a mat");
+        assert_eq!(deltas, ["A cat", " on a mat"]);
+        assert_eq!(body_of(&requests.recv().unwrap())["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn show_model_detects_vision() {
+        let body = json!({
+            "details": { "family": "gemma3", "families": ["gemma3"], "parameter_size": "4.3B", "quantization_level": "Q4_K_M" },
+            "model_info": { "general.architecture": "gemma3", "gemma3.context_length": 131072, "gemma3.vision.block_count": 27 },
+            "parameters": "stop \"<end_of_turn>\"\nnum_ctx 8192",
+        });
+        let (base_url, requests) = serve(vec![response("200 OK", "application/json", &body.to_string())]);
+
+        let details = show_model(&Client::new(), &base_url, "gemma3:4b").await.unwrap();
+        assert!(details.vision);
+        assert_eq!(details.family.as_deref(), Some("gemma3"));
+        assert_eq!(details.context_length, Some(131072));
+        assert_eq!(details.num_ctx, Some(8192));
+        assert_eq!(body_of(&requests.recv().unwrap())["model"], "gemma3:4b");
+    }
+
+    #[tokio::test]
+    async fn pull_model_reports_progress() {
+        let body = concat!(
+            "{\"status\":\"pulling manifest\"}\n",
+            "{\"status\":\"pulling abc\",\"digest\":\"sha256:abc\",\"total\":100,\"completed\":50}\n",
+            "{\"status\":\"success\"}\n",
+        );
+        let (base_url, _requests) = serve(vec![
+            response("200 OK", "application/x-ndjson", body),
+            response("200 OK", "application/x-ndjson", "{\"error\":\"pull model manifest: file does not exist\"}\n"),
+        ]);
+
+        let progress = Mutex::new(Vec::new());
+        let on_progress = |update: PullProgress| progress.lock().unwrap().push(update);
+        let (_handle, cancel) = cancel_pair();
+        pull_model(&Client::new(), &base_url, "llava", &on_progress, &cancel).await.unwrap();
+        let progress = progress.into_inner().unwrap();
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[1].completed, Some(50));
+        assert!(progress[2].done);
+
+        let error = pull_model(&Client::new(), &base_url, "nope", &|_| {}, &cancel).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::ProviderError);
+        assert!(error.message.contains("file does not exist"));
+    }
+}
@@ -0,0 +1,213 @@
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+use super::stream::{StreamEvent, StreamFormat};
+use super::{data_url, CaptionRequest, ModelInfo, Provider, ProviderKind};
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const LMSTUDIO_BASE_URL: &str = "http://localhost:1234/v1";
+
+// OpenAI's chat completions API, also spoken by LM Studio's local server
+pub struct OpenAiCompatibleProvider {
+    kind: ProviderKind,
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn openai(base_url: Option<String>, api_key: &str) -> Self {
+        OpenAiCompatibleProvider {
+            kind: ProviderKind::OpenAi,
+            base_url: base_url.unwrap_or_else(|| OPENAI_BASE_URL.to_string()),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    pub fn lmstudio(base_url: Option<String>) -> Self {
+        OpenAiCompatibleProvider {
+            kind: ProviderKind::LmStudio,
+            base_url: base_url.unwrap_or_else(|| LMSTUDIO_BASE_URL.to_string()),
+            // LM Studio ignores the key, but expects the header to be present
+            api_key: "lm-studio".to_string(),
+        }
+    }
+}
+
+impl Provider for OpenAiCompatibleProvider {
+    fn kind(&self) -> ProviderKind {
+        self.kind
+    }
+
+    fn caption_request(&self, client: &Client, request: &CaptionRequest) -> RequestBuilder {
+        let mut messages = Vec::new();
+        if let Some(system) = &request.options.system_prompt {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.push(json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": request.prompt },
+                { "type": "image_url", "image_url": { "url": data_url(request.image) } }
+            ]
+        }));
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": request.options.max_tokens(),
+        });
+        if let Some(temperature) = request.options.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if request.stream {
+            body["stream"] = json!(true);
+        }
+
+        client
+            .post(format!("{}/chat/completions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(&body)
+    }
+
+    fn parse_caption(&self, body: &Value) -> Result<String, String> {
+        body.pointer("/choices/0/message/content")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| format!("No caption found in {} response", self.kind.display_name()))
+    }
+
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::Sse
+    }
+
+    fn parse_stream_event(&self, event: &Value) -> Result<StreamEvent, String> {
+        if let Some(error) = event.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or("unknown error");
+            return Err(format!("{} stream error: {}", self.kind.display_name(), message));
+        }
+        Ok(event
+            .pointer("/choices/0/delta/content")
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+            .map(|text| StreamEvent::Delta(text.to_string()))
+            .unwrap_or(StreamEvent::Skip))
+    }
+
+    fn models_request(&self, client: &Client) -> RequestBuilder {
+        client.get(format!("{}/models", self.base_url)).bearer_auth(&self.api_key)
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<ModelInfo> {
+        body.get("data")
+            .and_then(Value::as_array)
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|model| {
+                        let id = model.get("id")?.as_str()?.to_string();
+                        let name = model.get("name").and_then(Value::as_str).unwrap_or(&id).to_string();
+                        Some(ModelInfo { id, name })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::providers::tests::{body_of, built, caption_request, image, response, round_trip, serve};
+    use crate::providers::GenerationOptions;
+
+    #[test]
+    fn caption_request_puts_the_system_prompt_first() {
+        let provider = OpenAiCompatibleProvider::openai(None, "key");
+        let image = image();
+        let options = GenerationOptions { system_prompt: Some("Be brief".to_string()), ..GenerationOptions::default() };
+        let (request, body) = built(provider.caption_request(&Client::new(), &caption_request(&image, &options, false)));
+
+        assert_eq!(request.url().as_str(), "https://api.openai.com/v1/chat/completions");
+        assert_eq!(request.headers()["authorization"], "Bearer key");
+        assert_eq!(body.pointer("/messages/0/role").unwrap(), "system");
+        assert_eq!(body.pointer("/messages/1/content/1/image_url/url").unwrap(), "data:image/png;base64,aW1hZ2U=");
+        assert!(body.get("stream").is_none());
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[test]
+    fn lmstudio_uses_the_local_server() {
+        let provider = OpenAiCompatibleProvider::lmstudio(None);
+        let (request, _) = built(provider.models_request(&Client::new()));
+        assert_eq!(request.url().as_str(), "http://localhost:1234/v1/models");
+        assert_eq!(request.headers()["authorization"], "Bearer lm-studio");
+        assert_eq!(provider.kind(), ProviderKind::LmStudio);
+    }
+
+    #[test]
+    fn stream_events() {
+        let provider = OpenAiCompatibleProvider::openai(None, "key");
+        let delta = json!({ "choices": [{ "delta": { "content": "A" } }] });
+        assert_eq!(provider.parse_stream_event(&delta), Ok(StreamEvent::Delta("A".to_string())));
+        let role = json!({ "choices": [{ "delta": { "role": "assistant", "content": "" } }] });
+        assert_eq!(provider.parse_stream_event(&role), Ok(StreamEvent::Skip));
+
+        let error = json!({ "error": { "message": "Model unloaded" } });
+        assert_eq!(provider.parse_stream_event(&error), Err("OpenAI stream error: Model unloaded".to_string()));
+    }
+
+    #[test]
+    fn parse_caption_reports_missing_content() {
+        let provider = OpenAiCompatibleProvider::lmstudio(None);
+        let body = json!({ "choices": [{ "message": { "content": "A cat" } }] });
+        assert_eq!(provider.parse_caption(&body).unwrap(), "A cat");
+        assert_eq!(provider.parse_caption(&json!({})), Err("No caption found in LM Studio response".to_string()));
+    }
+
+    #[tokio::test]
+    async fn reads_a_caption_from_a_server() {
+        let body = r#"{"choices":[{"message":{"role":"assistant","content":"A cat on a mat"}}]}"#;
+        let (base_url, requests) = serve(vec![response("200 OK", "application/json", body)]);
+
+        let provider = OpenAiCompatibleProvider::openai(Some(base_url), "key");
+        let (caption, _) = round_trip(&provider, false).await.unwrap();
+        assert_eq!(caption, "A cat on a mat");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /chat/completions "));
+        assert_eq!(body_of(&request)["model"], "test-model");
+    }
+
+    #[tokio::test]
+    async fn streams_until_done() {
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"A cat\"}}]}\n\n",
+            ": keep-alive\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" on a mat\"}}]}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" ignored\"}}]}\n\n",
+        );
+        let (base_url, _requests) = serve(vec![response("200 OK", "text/event-stream", body)]);
+
+        let provider = OpenAiCompatibleProvider::lmstudio(Some(base_url));
+        let (caption, deltas) = round_trip(&provider, true).await.unwrap();
+        assert_eq!(caption, "A cat on a mat");
+        assert_eq!(deltas.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_carry_retry_after() {
+        let body = r#"{"error":{"message":"Rate limit reached","type":"requests"}}"#;
+        let limited = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3\r\nContent-Type: application/json\r\n";
+        let raw = format!("{}Content-Length: {}\r\nConnection: close\r\n\r\n{}", limited, body.len(), body);
+        let (base_url, _requests) = serve(vec![raw]);
+
+        let provider = OpenAiCompatibleProvider::openai(Some(base_url), "key");
+        let error = round_trip(&provider, false).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(3)));
+        assert!(error.message.ends_with("Rate limit reached"));
+    }
+}
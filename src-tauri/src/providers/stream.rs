@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::error::{AppError, ErrorCode};
+
+pub const CANCELLED: &str = "Caption generation cancelled";
+
+// How a provider frames its streaming responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamFormat {
+    // Server-sent events with JSON `data:` lines (Anthropic, OpenAI, LM Studio)
+    Sse,
+    // One JSON object per line (Ollama)
+    Ndjson,
+}
+
+// One decoded event from a streaming response
+#[derive(Debug, PartialEq)]
+pub enum StreamEvent {
+    Delta(String),
+    Done,
+    // Keep-alives, metadata and other events without caption text
+    Skip,
+}
+
+// Reassembles complete lines from a byte stream that may split them (or a
+// multi-byte character) across chunks
+#[derive(Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string());
+        }
+        lines
+    }
+
+    // Whatever is left once the stream ends without a final newline
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = String::from_utf8_lossy(&std::mem::take(&mut self.pending)).trim().to_string();
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+// The JSON payload carried by one line of a stream, if any
+pub fn line_payload(format: StreamFormat, line: &str) -> Option<&str> {
+    let payload = match format {
+        // `event:`, `id:` and `:` comment lines carry nothing we need
+        StreamFormat::Sse => line.strip_prefix("data:")?.trim(),
+        StreamFormat::Ndjson => line.trim(),
+    };
+    if payload.is_empty() {
+        None
+    } else {
+        Some(payload)
+    }
+}
+
+pub fn parse_json(payload: &str) -> Option<Value> {
+    match serde_json::from_str(payload) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            println!("Skipping malformed stream event: {}", e);
+            None
+        }
+    }
+}
+
+// Cooperative cancellation for in-flight requests
+#[derive(Clone)]
+pub struct CancelToken {
+    cancelled: watch::Receiver<bool>,
+}
+
+pub struct CancelHandle {
+    cancelled: watch::Sender<bool>,
+}
+
+pub fn cancel_pair() -> (CancelHandle, CancelToken) {
+    let (tx, rx) = watch::channel(false);
+    (CancelHandle { cancelled: tx }, CancelToken { cancelled: rx })
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+}
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    // Resolves once cancelled; never resolves if the handle is dropped first
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.clone();
+        if cancelled.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// In-flight caption requests by frontend-assigned id, so they can be cancelled
+#[derive(Default)]
+pub struct ActiveRequests {
+    handles: Mutex<HashMap<String, CancelHandle>>,
+}
+
+impl ActiveRequests {
+    // Ids must be unique among in-flight requests: a second request under the
+    // same id would take over the first one's cancel handle
+    pub fn register(&self, id: &str) -> Result<CancelToken, AppError> {
+        let mut handles = self.handles.lock().unwrap_or_else(PoisonError::into_inner);
+        if handles.contains_key(id) {
+            return Err(AppError::new(ErrorCode::Conflict, format!("Request {} is already in progress", id)));
+        }
+        let (handle, token) = cancel_pair();
+        handles.insert(id.to_string(), handle);
+        Ok(token)
+    }
+
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.handles.lock().ok().and_then(|mut handles| handles.remove(id)) {
+            Some(handle) => {
+                handle.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn finish(&self, id: &str) {
+        if let Ok(mut handles) = self.handles.lock() {
+            handles.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_buffer_joins_split_lines() {
+        let mut lines = LineBuffer::default();
+        assert!(lines.push(b"data: {\"a\"").is_empty());
+        assert_eq!(lines.push(b":1}\r\ndata: x\n\npart"), ["data: {\"a\":1}", "data: x", ""]);
+        assert_eq!(lines.finish().as_deref(), Some("part"));
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[test]
+    fn line_buffer_keeps_split_characters_whole() {
+        let text = "caf\u{e9} \u{1f431}\n".as_bytes();
+        let mut lines = LineBuffer::default();
+        let mut decoded = Vec::new();
+        for byte in text {
+            decoded.extend(lines.push(&[*byte]));
+        }
+        assert_eq!(decoded, ["caf\u{e9} \u{1f431}"]);
+    }
+
+    #[test]
+    fn line_payloads() {
+        assert_eq!(line_payload(StreamFormat::Sse, "data: {\"a\":1}"), Some("{\"a\":1}"));
+        assert_eq!(line_payload(StreamFormat::Sse, "data:[DONE]"), Some("[DONE]"));
+        assert_eq!(line_payload(StreamFormat::Sse, "event: message_stop"), None);
+        assert_eq!(line_payload(StreamFormat::Sse, ": keep-alive"), None);
+        assert_eq!(line_payload(StreamFormat::Sse, "data: "), None);
+        assert_eq!(line_payload(StreamFormat::Ndjson, " {\"done\":true} "), Some("{\"done\":true}"));
+        assert_eq!(line_payload(StreamFormat::Ndjson, ""), None);
+        assert!(parse_json("{\"done\":true}").is_some());
+        assert!(parse_json("{\"done\":").is_none());
+    }
+
+    #[test]
+    fn active_requests_cancel_by_id() {
+        let requests = ActiveRequests::default();
+        let token = requests.register("a").unwrap();
+        assert!(!token.is_cancelled());
+        // A duplicate id is refused and leaves the first request cancellable
+        assert_eq!(requests.register("a").err().map(|e| e.code), Some(ErrorCode::Conflict));
+        assert!(requests.cancel("a"));
+        assert!(token.is_cancelled());
+        assert!(!requests.cancel("a"));
+
+        let token = requests.register("b").unwrap();
+        requests.finish("b");
+        assert!(!requests.cancel("b"));
+        assert!(!token.is_cancelled());
+    }
+}
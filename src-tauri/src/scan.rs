@@ -0,0 +1,485 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::captions::{self, CaptionCounts, CaptionNaming, CaptionState, CaptionStatus};
+use crate::error::{AppError, ErrorCode};
+use crate::formats::{self, Detection};
+use crate::index::{self, DatasetIndex, IndexCache, IndexedFile};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub path: String,
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    // Path relative to the scanned root, always '/'-separated
+    pub relative_path: String,
+    // 0 for entries directly inside the root
+    pub depth: usize,
+    // Relative path of the folder containing this entry ("" for the root)
+    pub folder: String,
+    // Whether the file's contents are an image we can decode
+    pub is_image: bool,
+    // Format detected from the file header, for images
+    pub format: Option<String>,
+    // Last modification time in milliseconds since the Unix epoch
+    pub modified: Option<u64>,
+    // Pixel dimensions and content fingerprint, for images
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub hash: Option<String>,
+    // Caption file details, for images
+    pub caption: Option<CaptionStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderInfo {
+    pub path: String,
+    pub relative_path: String,
+    pub depth: usize,
+    pub image_count: usize,
+    pub caption_count: usize,
+    pub stale_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatIssueKind {
+    // Decodable, but the extension names a different format (e.g. a PNG saved as .jpg)
+    Mislabeled,
+    // A real image format without a decoder in this build
+    Unsupported,
+    // Image extension, but the header doesn't match any known format
+    Unrecognized,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FormatIssue {
+    pub path: String,
+    pub relative_path: String,
+    pub kind: FormatIssueKind,
+    // Format implied by the file extension
+    pub extension_format: Option<String>,
+    // Format detected from the file header
+    pub detected_format: Option<String>,
+}
+
+// Images that map to the same caption file under the current naming policy
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptionCollision {
+    pub caption_path: String,
+    pub image_paths: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectoryContents {
+    pub files: Vec<FileInfo>,
+    pub folders: Vec<FolderInfo>,
+    pub image_count: usize,
+    pub caption_count: usize,
+    pub stale_count: usize,
+    pub format_issues: Vec<FormatIssue>,
+    pub caption_collisions: Vec<CaptionCollision>,
+}
+
+// Build a '/'-separated path relative to the scan root
+pub fn relative_path(root: &Path, path: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) => rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+// Scan a dataset root. In flat mode only the root itself is listed (directories
+// included, as before); in recursive mode every subfolder is walked and only
+// files are returned, with per-folder counts reported in `folders`.
+// With an index, unchanged files are served from the cache instead of being
+// re-read, and whatever is read fresh is written back for next time.
+pub fn scan_directory(
+    root: &Path,
+    recursive: bool,
+    index: Option<&DatasetIndex>,
+    naming: &CaptionNaming,
+) -> Result<DirectoryContents, AppError> {
+    if !root.exists() || !root.is_dir() {
+        return Err(AppError::new(ErrorCode::NotFound, format!("Directory not found: {}", root.display())).with_path(root));
+    }
+
+    let (mut files, mut folders, mut format_issues, mut caption_collisions) = match index {
+        Some(index) => index.with_cache(|cache| run_scan(root, recursive, Some(cache), naming))??,
+        None => run_scan(root, recursive, None, naming)?,
+    };
+
+    if recursive {
+        // Group files by folder, then by name
+        files.sort_by(|a, b| {
+            a.folder.to_lowercase().cmp(&b.folder.to_lowercase())
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+    } else {
+        // Sort files: directories first, then by name
+        files.sort_by(|a, b| {
+            if a.is_dir && !b.is_dir {
+                std::cmp::Ordering::Less
+            } else if !a.is_dir && b.is_dir {
+                std::cmp::Ordering::Greater
+            } else {
+                a.name.to_lowercase().cmp(&b.name.to_lowercase())
+            }
+        });
+    }
+    folders.sort_by_key(|f| f.relative_path.to_lowercase());
+    format_issues.sort_by_key(|i| i.relative_path.to_lowercase());
+    caption_collisions.sort_by_key(|c| c.caption_path.to_lowercase());
+
+    let image_count = folders.iter().map(|f| f.image_count).sum();
+    let caption_count = folders.iter().map(|f| f.caption_count).sum();
+    let stale_count = folders.iter().map(|f| f.stale_count).sum();
+
+    Ok(DirectoryContents {
+        files,
+        folders,
+        image_count,
+        caption_count,
+        stale_count,
+        format_issues,
+        caption_collisions,
+    })
+}
+
+type ScanOutput = (Vec<FileInfo>, Vec<FolderInfo>, Vec<FormatIssue>, Vec<CaptionCollision>);
+
+fn run_scan(root: &Path, recursive: bool, cache: Option<&IndexCache>, naming: &CaptionNaming) -> Result<ScanOutput, AppError> {
+    let mut scanner = Scanner {
+        root,
+        recursive,
+        cache,
+        naming,
+        files: Vec::new(),
+        folders: Vec::new(),
+        format_issues: Vec::new(),
+        caption_collisions: Vec::new(),
+    };
+    scanner
+        .scan_folder(root, 0)
+        .map_err(|e| AppError::io("Failed to read directory", root, e))?;
+    Ok((scanner.files, scanner.folders, scanner.format_issues, scanner.caption_collisions))
+}
+
+struct Scanner<'a> {
+    root: &'a Path,
+    recursive: bool,
+    cache: Option<&'a IndexCache<'a>>,
+    naming: &'a CaptionNaming,
+    files: Vec<FileInfo>,
+    folders: Vec<FolderInfo>,
+    format_issues: Vec<FormatIssue>,
+    caption_collisions: Vec<CaptionCollision>,
+}
+
+impl Scanner<'_> {
+    fn scan_folder(&mut self, dir: &Path, depth: usize) -> std::io::Result<()> {
+        let root = self.root;
+        let recursive = self.recursive;
+        let entries = fs::read_dir(dir)?;
+        let folder = relative_path(root, dir);
+        let folder_key = dir.to_string_lossy().to_string();
+
+        let mut image_count = 0;
+        let mut caption_count = 0;
+        let mut stale_count = 0;
+        let mut subdirs = Vec::new();
+        let mut seen = HashSet::new();
+        let mut caption_owners: HashMap<String, Vec<String>> = HashMap::new();
+
+        for entry in entries.flatten() {
+            let path_buf = entry.path();
+            let name = match path_buf.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+
+            // Use the entry's own file type so symlinked directories aren't followed
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            let is_dir = file_type.is_dir() || (!recursive && path_buf.is_dir());
+
+            if is_dir && recursive {
+                // Skip hidden folders like .git or .cache
+                if !name.starts_with('.') {
+                    subdirs.push(path_buf);
+                }
+                continue;
+            }
+
+            let path_str = path_buf.to_string_lossy().to_string();
+            let metadata = entry.metadata().ok();
+            let size = if is_dir {
+                0
+            } else {
+                metadata.as_ref().map(|m| m.len()).unwrap_or(0)
+            };
+            let modified = metadata.as_ref().and_then(captions::modified_millis);
+
+            let relative = relative_path(root, &path_buf);
+
+            // Identify images by their header rather than trusting the extension
+            let indexed = if is_dir {
+                None
+            } else {
+                seen.insert(path_str.clone());
+                Some(self.probe_file(&path_buf, &path_str, &folder_key, size, modified))
+            };
+            let detection = match &indexed {
+                Some(entry) => Detection::from_parts(&entry.kind, entry.format.as_deref(), entry.claimed_format.as_deref()),
+                None => Detection::NotImage,
+            };
+            let issue = match &detection {
+                Detection::Image { format, claimed: Some(claimed) } if claimed != format => {
+                    Some((FormatIssueKind::Mislabeled, Some(*claimed), Some(*format)))
+                }
+                Detection::Unsupported { format, claimed } => {
+                    Some((FormatIssueKind::Unsupported, *claimed, Some(*format)))
+                }
+                Detection::Unrecognized { claimed } => {
+                    Some((FormatIssueKind::Unrecognized, Some(*claimed), None))
+                }
+                _ => None,
+            };
+            if let Some((kind, claimed, detected)) = issue {
+                self.format_issues.push(FormatIssue {
+                    path: path_str.clone(),
+                    relative_path: relative.clone(),
+                    kind,
+                    extension_format: claimed.map(|f| formats::format_name(f).to_string()),
+                    detected_format: detected.map(|f| formats::format_name(f).to_string()),
+                });
+            }
+
+            let format = match detection {
+                Detection::Image { format, .. } => Some(format),
+                _ => None,
+            };
+            let caption = match format {
+                Some(_) => captions::caption_status(self.naming, &path_buf, modified, |caption_path, caption_meta| {
+                    self.caption_counts(caption_path, caption_meta, &folder_key)
+                }),
+                None => None,
+            };
+            if format.is_some() {
+                image_count += 1;
+            }
+            if let Some(caption) = &caption {
+                caption_owners.entry(caption.path.clone()).or_default().push(path_str.clone());
+            }
+            match caption.as_ref().map(|c| c.state) {
+                Some(CaptionState::Captioned) => caption_count += 1,
+                Some(CaptionState::Stale) => {
+                    caption_count += 1;
+                    stale_count += 1;
+                }
+                _ => {}
+            }
+
+            let (width, height, hash) = match (format, indexed) {
+                (Some(_), Some(entry)) => (entry.width, entry.height, entry.hash),
+                _ => (None, None, None),
+            };
+
+            self.files.push(FileInfo {
+                path: path_str,
+                relative_path: relative,
+                name,
+                is_dir,
+                size,
+                depth,
+                folder: folder.clone(),
+                is_image: format.is_some(),
+                format: format.map(|f| formats::format_name(f).to_string()),
+                modified,
+                width,
+                height,
+                hash,
+                caption,
+            });
+        }
+
+        for (caption_path, mut image_paths) in caption_owners {
+            if image_paths.len() > 1 {
+                image_paths.sort();
+                self.caption_collisions.push(CaptionCollision { caption_path, image_paths });
+            }
+        }
+
+        if let Some(cache) = self.cache {
+            cache.prune_folder(&folder_key, &seen);
+        }
+
+        self.folders.push(FolderInfo {
+            path: folder_key,
+            relative_path: folder,
+            depth,
+            image_count,
+            caption_count,
+            stale_count,
+        });
+
+        for subdir in subdirs {
+            // A single unreadable subfolder shouldn't abort the whole scan
+            if let Err(e) = self.scan_folder(&subdir, depth + 1) {
+                println!("Skipping unreadable folder {}: {}", subdir.display(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Detection, dimensions and fingerprint for a file, from the index when the
+    // file is unchanged since it was last scanned
+    fn probe_file(&self, path: &Path, key: &str, folder_key: &str, size: u64, modified: Option<u64>) -> IndexedFile {
+        let modified = modified.unwrap_or(0);
+        if let Some(entry) = self.cache.and_then(|cache| cache.file(key, size, modified)) {
+            return entry;
+        }
+
+        let detection = formats::detect(path);
+        let (dimensions, hash) = match detection {
+            Detection::Image { .. } => {
+                let dimensions = image::io::Reader::open(path)
+                    .and_then(|r| r.with_guessed_format())
+                    .ok()
+                    .and_then(|r| r.into_dimensions().ok());
+                (dimensions, index::fingerprint(path).ok())
+            }
+            _ => (None, None),
+        };
+
+        let entry = IndexedFile {
+            size,
+            modified,
+            kind: detection.kind().to_string(),
+            format: detection.format().map(|f| formats::format_name(f).to_string()),
+            claimed_format: detection.claimed().map(|f| formats::format_name(f).to_string()),
+            width: dimensions.map(|(w, _)| w),
+            height: dimensions.map(|(_, h)| h),
+            hash,
+        };
+        if let Some(cache) = self.cache {
+            cache.put_file(key, folder_key, &entry);
+        }
+        entry
+    }
+
+    fn caption_counts(&self, caption_path: &Path, metadata: &fs::Metadata, folder_key: &str) -> CaptionCounts {
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => return captions::read_counts(caption_path),
+        };
+
+        let key = caption_path.to_string_lossy();
+        let size = metadata.len();
+        let modified = captions::modified_millis(metadata).unwrap_or(0);
+        if let Some(counts) = cache.caption(&key, size, modified) {
+            return counts;
+        }
+
+        let counts = captions::read_counts(caption_path);
+        cache.put_caption(&key, folder_key, size, modified, &counts);
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    // root: a.png, a.jpg and a.txt (shared caption), notes.md
+    //   sub: b.png, b.txt
+    //     deeper: c.png
+    //   .hidden: d.png
+    fn dataset(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("tagmeister-scan-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        let image = image::RgbImage::new(2, 2);
+        for path in ["a.png", "a.jpg", "sub/b.png", "sub/deeper/c.png", ".hidden/d.png"] {
+            image.save(root.join(path)).unwrap();
+        }
+        fs::write(root.join("a.txt"), "cat, dog").unwrap();
+        fs::write(root.join("sub/b.txt"), "a bird").unwrap();
+        fs::write(root.join("notes.md"), "not an image").unwrap();
+        root
+    }
+
+    #[test]
+    fn recursive_scans_count_every_folder() {
+        let root = dataset("recursive");
+        let contents = scan_directory(&root, true, None, &CaptionNaming::default()).unwrap();
+
+        let folders: Vec<(&str, usize, usize, usize)> = contents
+            .folders
+            .iter()
+            .map(|f| (f.relative_path.as_str(), f.depth, f.image_count, f.caption_count))
+            .collect();
+        assert_eq!(folders, [("", 0, 2, 2), ("sub", 1, 1, 1), ("sub/deeper", 2, 1, 0)]);
+        assert_eq!((contents.image_count, contents.caption_count), (4, 3));
+
+        assert!(contents.files.iter().all(|file| !file.is_dir && !file.relative_path.starts_with(".hidden")));
+        let deep = contents.files.iter().find(|file| file.name == "c.png").unwrap();
+        assert_eq!((deep.relative_path.as_str(), deep.folder.as_str(), deep.depth), ("sub/deeper/c.png", "sub/deeper", 2));
+        assert_eq!((deep.width, deep.height), (Some(2), Some(2)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn flat_scans_list_only_the_root() {
+        let root = dataset("flat");
+        let contents = scan_directory(&root, false, None, &CaptionNaming::default()).unwrap();
+        assert_eq!(contents.folders.len(), 1);
+        assert_eq!(contents.image_count, 2);
+
+        let names: Vec<&str> = contents.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, [".hidden", "sub", "a.jpg", "a.png", "a.txt", "notes.md"]);
+        assert!(contents.files[0].is_dir && !contents.files[2].is_dir);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn images_sharing_a_caption_are_reported() {
+        let root = dataset("collisions");
+        let contents = scan_directory(&root, true, None, &CaptionNaming::default()).unwrap();
+        assert_eq!(contents.caption_collisions.len(), 1);
+        let collision = &contents.caption_collisions[0];
+        assert_eq!(collision.caption_path, root.join("a.txt").to_string_lossy());
+        let expected: Vec<String> = ["a.jpg", "a.png"].iter().map(|name| root.join(name).to_string_lossy().to_string()).collect();
+        assert_eq!(collision.image_paths, expected);
+
+        // Captions named after the full image name don't collide
+        let naming = CaptionNaming { keep_image_extension: true, ..CaptionNaming::default() };
+        let contents = scan_directory(&root, true, None, &naming).unwrap();
+        assert!(contents.caption_collisions.is_empty());
+        assert_eq!(contents.caption_count, 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn indexed_scans_match_fresh_ones() {
+        let root = dataset("indexed");
+        let index = DatasetIndex::in_memory().unwrap();
+        let naming = CaptionNaming::default();
+        for _ in 0..2 {
+            let contents = scan_directory(&root, true, Some(&index), &naming).unwrap();
+            assert_eq!((contents.image_count, contents.caption_count, contents.files.len()), (4, 3, 7));
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::captions::{self, CaptionNaming};
+use crate::error::{AppError, ErrorCode};
+use crate::scan;
+use crate::tags::{self, TagFormat};
+
+// Tokens CLIP's text encoder sees per prompt, not counting the start/end tokens
+pub const CLIP_TOKEN_LIMIT: usize = 75;
+
+// Histogram bucket widths for caption lengths
+const WORD_BUCKET: usize = 10;
+const TOKEN_BUCKET: usize = 25;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TagFrequency {
+    pub tag: String,
+    // Captions the tag appears in
+    pub count: usize,
+    // Fraction of captions the tag appears in
+    pub share: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FolderTagStats {
+    // Relative to the dataset root, '/'-separated ("" for the root)
+    pub folder: String,
+    pub caption_count: usize,
+    pub tags: Vec<TagFrequency>,
+}
+
+// Two tags that appear in the same caption
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TagPair {
+    pub a: String,
+    pub b: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LengthBucket {
+    // Inclusive lower and exclusive upper bound
+    pub from: usize,
+    pub to: usize,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LengthDistribution {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: usize,
+    pub p95: usize,
+    pub buckets: Vec<LengthBucket>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CaptionLength {
+    pub path: String,
+    pub words: usize,
+    pub tags: usize,
+    pub clip_tokens: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TagStats {
+    pub root: String,
+    pub caption_count: usize,
+    // Images without a caption file, or with an empty one
+    pub uncaptioned_count: usize,
+    // Sorted by count, most frequent first
+    pub tags: Vec<TagFrequency>,
+    pub folders: Vec<FolderTagStats>,
+    // The most frequent co-occurring pairs
+    pub pairs: Vec<TagPair>,
+    // Tags used by exactly one caption, often typos
+    pub singletons: Vec<String>,
+    pub word_lengths: LengthDistribution,
+    pub token_lengths: LengthDistribution,
+    // Captions whose estimated CLIP tokens exceed `CLIP_TOKEN_LIMIT`
+    pub over_token_limit: usize,
+    pub captions: Vec<CaptionLength>,
+    // Captions that couldn't be read
+    pub errors: Vec<AppError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+// Which table a CSV export contains; JSON always has everything
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportSection {
+    // One row per tag, with a count column per subfolder
+    #[default]
+    Tags,
+    Pairs,
+    Captions,
+}
+
+// Count tags across every caption under `root`. Tags are compared the way
+// dedupe compares them and reported under their first spelling.
+pub fn collect(naming: &CaptionNaming, root: &Path, recursive: bool, max_pairs: usize) -> Result<TagStats, AppError> {
+    if !root.is_dir() {
+        return Err(AppError::new(ErrorCode::NotFound, format!("Directory not found: {}", root.display())).with_path(root));
+    }
+    let images = captions::images_in(root, recursive).map_err(|e| AppError::io("Failed to read directory", root, e))?;
+    let format = TagFormat::default();
+
+    // Tag names by index, and how many captions use each overall and per folder
+    let mut names: Vec<String> = Vec::new();
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let mut folders: HashMap<String, (usize, HashMap<usize, usize>)> = HashMap::new();
+    let mut pairs: HashMap<(usize, usize), usize> = HashMap::new();
+
+    let mut stats = TagStats {
+        root: root.to_string_lossy().to_string(),
+        caption_count: 0,
+        uncaptioned_count: 0,
+        tags: Vec::new(),
+        folders: Vec::new(),
+        pairs: Vec::new(),
+        singletons: Vec::new(),
+        word_lengths: LengthDistribution::default(),
+        token_lengths: LengthDistribution::default(),
+        over_token_limit: 0,
+        captions: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for loaded in captions::load_many(naming, &images) {
+        if let Some(error) = loaded.error {
+            stats.errors.push(error);
+            continue;
+        }
+        let text = loaded.text.unwrap_or_default();
+        if text.trim().is_empty() {
+            stats.uncaptioned_count += 1;
+            continue;
+        }
+        stats.caption_count += 1;
+
+        let caption_tags = tags::normalize(tags::parse(&text), &format);
+        let mut ids: Vec<usize> = caption_tags
+            .iter()
+            .map(|tag| {
+                let key = tags::tag_key(&tag.name);
+                *index_of.entry(key).or_insert_with(|| {
+                    names.push(tag.name.clone());
+                    counts.push(0);
+                    names.len() - 1
+                })
+            })
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let folder = Path::new(&loaded.path)
+            .parent()
+            .map(|parent| scan::relative_path(root, parent))
+            .unwrap_or_default();
+        let (folder_captions, folder_counts) = folders.entry(folder).or_default();
+        *folder_captions += 1;
+        for (i, &id) in ids.iter().enumerate() {
+            counts[id] += 1;
+            *folder_counts.entry(id).or_default() += 1;
+            for &other in &ids[i + 1..] {
+                *pairs.entry((id, other)).or_default() += 1;
+            }
+        }
+
+        let clip_tokens = estimate_clip_tokens(&text);
+        if clip_tokens > CLIP_TOKEN_LIMIT {
+            stats.over_token_limit += 1;
+        }
+        stats.captions.push(CaptionLength {
+            path: loaded.path,
+            words: captions::count_words(&text),
+            tags: ids.len(),
+            clip_tokens,
+        });
+    }
+
+    let frequencies = |counts: &mut dyn Iterator<Item = (usize, usize)>, total: usize| {
+        let mut list: Vec<TagFrequency> = counts
+            .map(|(id, count)| TagFrequency {
+                tag: names[id].clone(),
+                count,
+                share: count as f64 / total.max(1) as f64,
+            })
+            .collect();
+        list.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        list
+    };
+
+    stats.tags = frequencies(&mut counts.iter().copied().enumerate(), stats.caption_count);
+    stats.singletons = stats.tags.iter().filter(|tag| tag.count == 1).map(|tag| tag.tag.clone()).collect();
+    stats.singletons.sort();
+
+    stats.folders = folders
+        .into_iter()
+        .map(|(folder, (caption_count, folder_counts))| FolderTagStats {
+            folder,
+            caption_count,
+            tags: frequencies(&mut folder_counts.into_iter(), caption_count),
+        })
+        .collect();
+    stats.folders.sort_by(|a, b| a.folder.cmp(&b.folder));
+
+    let mut pairs: Vec<TagPair> = pairs
+        .into_iter()
+        .map(|((a, b), count)| TagPair { a: names[a].clone(), b: names[b].clone(), count })
+        .collect();
+    pairs.sort_by(|x, y| y.count.cmp(&x.count).then_with(|| x.a.cmp(&y.a)).then_with(|| x.b.cmp(&y.b)));
+    pairs.truncate(max_pairs);
+    stats.pairs = pairs;
+
+    let words: Vec<usize> = stats.captions.iter().map(|caption| caption.words).collect();
+    let tokens: Vec<usize> = stats.captions.iter().map(|caption| caption.clip_tokens).collect();
+    stats.word_lengths = distribution(words, WORD_BUCKET);
+    stats.token_lengths = distribution(tokens, TOKEN_BUCKET);
+    stats.captions.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(stats)
+}
+
+// Rough CLIP BPE token count: common words are one token, long or rare words
+// split into pieces of ~5 characters, and every punctuation mark or digit is
+// its own token
+pub fn estimate_clip_tokens(text: &str) -> usize {
+    let mut tokens = 0;
+    let mut word_len = 0;
+    let flush = |word_len: &mut usize, tokens: &mut usize| {
+        if *word_len > 0 {
+            *tokens += word_len.div_ceil(5).max(1);
+            *word_len = 0;
+        }
+    };
+    for c in text.chars() {
+        if c.is_alphabetic() {
+            word_len += 1;
+        } else {
+            flush(&mut word_len, &mut tokens);
+            if !c.is_whitespace() {
+                tokens += 1;
+            }
+        }
+    }
+    flush(&mut word_len, &mut tokens);
+    tokens
+}
+
+fn distribution(mut values: Vec<usize>, bucket: usize) -> LengthDistribution {
+    if values.is_empty() {
+        return LengthDistribution::default();
+    }
+    values.sort_unstable();
+    let percentile = |p: usize| values[((values.len() - 1) * p) / 100];
+    let max = *values.last().unwrap_or(&0);
+
+    let mut buckets: Vec<LengthBucket> = (0..=max / bucket)
+        .map(|i| LengthBucket { from: i * bucket, to: (i + 1) * bucket, count: 0 })
+        .collect();
+    for value in &values {
+        buckets[value / bucket].count += 1;
+    }
+
+    LengthDistribution {
+        min: values[0],
+        max,
+        mean: values.iter().sum::<usize>() as f64 / values.len() as f64,
+        median: percentile(50),
+        p95: percentile(95),
+        buckets,
+    }
+}
+
+// Write stats to `path` as JSON (everything) or CSV (one section)
+pub fn export(stats: &TagStats, format: ExportFormat, section: ExportSection, path: &Path) -> Result<(), AppError> {
+    let contents = match format {
+        ExportFormat::Json => serde_json::to_string_pretty(stats)
+            .map_err(|e| AppError::new(ErrorCode::Internal, format!("Failed to encode tag statistics: {}", e)))?,
+        ExportFormat::Csv => to_csv(stats, section),
+    };
+    fs::write(path, contents).map_err(|e| AppError::io("Failed to export tag statistics to", path, e))
+}
+
+fn to_csv(stats: &TagStats, section: ExportSection) -> String {
+    let mut out = String::new();
+    match section {
+        ExportSection::Tags => {
+            let mut header = vec!["tag".to_string(), "count".to_string(), "share".to_string()];
+            header.extend(stats.folders.iter().map(|folder| csv_field(if folder.folder.is_empty() { "." } else { &folder.folder })));
+            let _ = writeln!(out, "{}", header.join(","));
+
+            let folder_counts: Vec<HashMap<&str, usize>> = stats
+                .folders
+                .iter()
+                .map(|folder| folder.tags.iter().map(|tag| (tag.tag.as_str(), tag.count)).collect())
+                .collect();
+            for tag in &stats.tags {
+                let mut row = vec![csv_field(&tag.tag), tag.count.to_string(), format!("{:.4}", tag.share)];
+                row.extend(folder_counts.iter().map(|counts| counts.get(tag.tag.as_str()).copied().unwrap_or(0).to_string()));
+                let _ = writeln!(out, "{}", row.join(","));
+            }
+        }
+        ExportSection::Pairs => {
+            let _ = writeln!(out, "tag_a,tag_b,count");
+            for pair in &stats.pairs {
+                let _ = writeln!(out, "{},{},{}", csv_field(&pair.a), csv_field(&pair.b), pair.count);
+            }
+        }
+        ExportSection::Captions => {
+            let _ = writeln!(out, "path,words,tags,clip_tokens");
+            for caption in &stats.captions {
+                let _ = writeln!(out, "{},{},{},{}", csv_field(&caption.path), caption.words, caption.tags, caption.clip_tokens);
+            }
+        }
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    // a: "1girl, smile"  b: "1girl, long hair"  c: no caption  d: empty
+    // sub/e: "1girl, smile, say "hi""
+    fn dataset(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("tagmeister-stats-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let image = image::RgbImage::new(2, 2);
+        for path in ["a.png", "b.png", "c.png", "d.png", "sub/e.png"] {
+            image.save(root.join(path)).unwrap();
+        }
+        fs::write(root.join("a.txt"), "1girl, smile").unwrap();
+        fs::write(root.join("b.txt"), "1girl, long hair").unwrap();
+        fs::write(root.join("d.txt"), " \n").unwrap();
+        fs::write(root.join("sub/e.txt"), "1girl, Smile, say \"hi\"").unwrap();
+        root
+    }
+
+    #[test]
+    fn tags_are_counted_overall_and_per_folder() {
+        let root = dataset("counts");
+        let stats = collect(&CaptionNaming::default(), &root, true, 10).unwrap();
+        assert_eq!((stats.caption_count, stats.uncaptioned_count), (3, 2));
+
+        let tags: Vec<(&str, usize)> = stats.tags.iter().map(|tag| (tag.tag.as_str(), tag.count)).collect();
+        assert_eq!(tags, [("1girl", 3), ("smile", 2), ("long hair", 1), ("say \"hi\"", 1)]);
+        assert_eq!(stats.singletons, ["long hair", "say \"hi\""]);
+
+        let folders: Vec<(&str, usize)> = stats.folders.iter().map(|folder| (folder.folder.as_str(), folder.caption_count)).collect();
+        assert_eq!(folders, [("", 2), ("sub", 1)]);
+        assert_eq!((stats.pairs[0].a.as_str(), stats.pairs[0].b.as_str(), stats.pairs[0].count), ("1girl", "smile", 2));
+
+        let flat = collect(&CaptionNaming::default(), &root, false, 10).unwrap();
+        assert_eq!((flat.caption_count, flat.uncaptioned_count), (2, 2));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn csv_exports_quote_and_split_by_folder() {
+        let root = dataset("csv");
+        let stats = collect(&CaptionNaming::default(), &root, true, 1).unwrap();
+
+        let tags = to_csv(&stats, ExportSection::Tags);
+        let lines: Vec<&str> = tags.lines().collect();
+        assert_eq!(lines[0], "tag,count,share,.,sub");
+        assert_eq!(lines[1], "1girl,3,1.0000,2,1");
+        assert_eq!(lines[2], "smile,2,0.6667,1,1");
+        assert_eq!(lines[4], "\"say \"\"hi\"\"\",1,0.3333,0,1");
+
+        assert_eq!(to_csv(&stats, ExportSection::Pairs), "tag_a,tag_b,count\n1girl,smile,2\n");
+        let captions = to_csv(&stats, ExportSection::Captions);
+        assert_eq!(captions.lines().count(), 4);
+        assert!(captions.starts_with("path,words,tags,clip_tokens\n"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lengths_are_bucketed() {
+        assert_eq!(estimate_clip_tokens("1girl, smile"), 4);
+        assert_eq!(estimate_clip_tokens("extraordinarily"), 3);
+
+        let lengths = distribution(vec![3, 12, 5, 30], 10);
+        assert_eq!((lengths.min, lengths.max, lengths.median), (3, 30, 5));
+        let counts: Vec<usize> = lengths.buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, [2, 1, 0, 1]);
+    }
+}
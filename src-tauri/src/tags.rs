@@ -0,0 +1,301 @@
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+
+// Emphasis applied per `(...)` layer (and removed per `[...]` layer), as in
+// the A1111 prompt syntax
+const EMPHASIS: f32 = 1.1;
+
+// Danbooru kaomoji tags whose underscores are part of the face, not spaces
+const KAOMOJI: &[&str] = &[
+    "0_0", "(o)_(o)", "+_+", "+_-", "._.", "<o>_<o>", "<|>_<|>", "=_=", ">_<", "3_3", "6_9", ">_o", "@_@", "^_^",
+    "o_o", "u_u", "x_x", "|_|", "||_||",
+];
+
+// One tag of a booru-style caption. Names are stored with spacing collapsed
+// and parentheses unescaped; `weight` is None for unweighted tags.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub weight: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnderscoreStyle {
+    #[default]
+    Keep,
+    // `long_hair` -> `long hair` (the usual style for SD training captions)
+    Spaces,
+    // `long hair` -> `long_hair` (Danbooru's own spelling)
+    Underscores,
+}
+
+// How tags are normalised and written back out
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TagFormat {
+    #[serde(default)]
+    pub underscores: UnderscoreStyle,
+    // Write literal parentheses as `\(` and `\)` so prompt parsers don't read
+    // them as emphasis
+    #[serde(default)]
+    pub escape_parentheses: bool,
+    // Drop repeats of a tag (compared case-insensitively), keeping the first
+    #[serde(default = "default_true")]
+    pub dedupe: bool,
+    #[serde(default = "default_separator")]
+    pub separator: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_separator() -> String {
+    ", ".to_string()
+}
+
+impl Default for TagFormat {
+    fn default() -> Self {
+        TagFormat {
+            underscores: UnderscoreStyle::Keep,
+            escape_parentheses: false,
+            dedupe: true,
+            separator: default_separator(),
+        }
+    }
+}
+
+// Split a caption into tags. Commas inside `(...)` groups don't split, so
+// `(red hair, blue eyes:1.2)` gives two tags that share the weight.
+pub fn parse(caption: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for piece in split_top_level(caption) {
+        parse_piece(piece, 1.0, &mut tags);
+    }
+    tags
+}
+
+// Apply the format's spelling rules and dedupe
+pub fn normalize(tags: Vec<Tag>, format: &TagFormat) -> Vec<Tag> {
+    let mut seen = HashSet::new();
+    tags.into_iter()
+        .map(|tag| Tag { name: normalize_name(&tag.name, format.underscores), weight: tag.weight })
+        .filter(|tag| !tag.name.is_empty())
+        .filter(|tag| !format.dedupe || seen.insert(tag_key(&tag.name)))
+        .collect()
+}
+
+pub fn serialize(tags: &[Tag], format: &TagFormat) -> String {
+    tags.iter()
+        .map(|tag| {
+            let name = if format.escape_parentheses { escape(&tag.name) } else { tag.name.clone() };
+            match tag.weight {
+                Some(weight) => format!("({}:{})", name, format_weight(weight)),
+                None => name,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&format.separator)
+}
+
+// Parse, normalise and serialise in one go
+pub fn normalize_caption(caption: &str, format: &TagFormat) -> String {
+    serialize(&normalize(parse(caption), format), format)
+}
+
+// Concatenate several tag captions (e.g. prefix, generated tags, suffix) into
+// one, normalised and deduplicated across all of them
+pub fn join(parts: &[&str], format: &TagFormat) -> String {
+    let tags = parts.iter().flat_map(|part| parse(part)).collect();
+    serialize(&normalize(tags, format), format)
+}
+
+// Kaomoji tags keep their underscores whatever the underscore style
+pub fn is_kaomoji(name: &str) -> bool {
+    KAOMOJI.contains(&name)
+}
+
+// Key tags are compared by: case, spacing and underscores don't matter
+pub fn tag_key(name: &str) -> String {
+    if KAOMOJI.contains(&name) {
+        return name.to_string();
+    }
+    name.to_lowercase().replace('_', " ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn normalize_name(name: &str, underscores: UnderscoreStyle) -> String {
+    let collapsed = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if KAOMOJI.contains(&collapsed.as_str()) {
+        return collapsed;
+    }
+    match underscores {
+        UnderscoreStyle::Keep => collapsed,
+        UnderscoreStyle::Spaces => collapsed.replace('_', " ").split_whitespace().collect::<Vec<_>>().join(" "),
+        UnderscoreStyle::Underscores => collapsed.replace(' ', "_"),
+    }
+}
+
+fn parse_piece(piece: &str, weight: f32, tags: &mut Vec<Tag>) {
+    let piece = piece.trim();
+    if piece.is_empty() {
+        return;
+    }
+
+    if let Some(inner) = unwrap_group(piece, '(', ')') {
+        // `(tag:1.2)` sets the weight outright; a bare `(tag)` adds emphasis
+        let (inner, weight) = match split_weight(inner) {
+            Some((inner, explicit)) => (inner, weight * explicit),
+            None => (inner, weight * EMPHASIS),
+        };
+        for part in split_top_level(inner) {
+            parse_piece(part, weight, tags);
+        }
+        return;
+    }
+    if let Some(inner) = unwrap_group(piece, '[', ']') {
+        for part in split_top_level(inner) {
+            parse_piece(part, weight / EMPHASIS, tags);
+        }
+        return;
+    }
+
+    // Rounded so repeated emphasis doesn't leave float noise like 1.2100001
+    let weight = (weight * 100.0).round() / 100.0;
+    tags.push(Tag {
+        name: unescape(piece),
+        weight: if weight == 1.0 { None } else { Some(weight) },
+    });
+}
+
+// The contents of `piece` if it is entirely one `open ... close` group (and
+// not, say, `(a) and (b)` or `pokemon \(creature\)`)
+fn unwrap_group(piece: &str, open: char, close: char) -> Option<&str> {
+    if !piece.starts_with(open) || !piece.ends_with(close) || piece.len() < 2 || piece.ends_with(&format!("\\{}", close)) {
+        return None;
+    }
+    let mut depth = 0;
+    let mut escaped = false;
+    for (index, c) in piece.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                // Closed before the end, so the group doesn't span the piece
+                if depth == 0 && index != piece.len() - close.len_utf8() {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    (depth == 0).then(|| &piece[open.len_utf8()..piece.len() - close.len_utf8()])
+}
+
+// `name:1.2` -> ("name", 1.2), only when the part after the last colon is a number
+fn split_weight(inner: &str) -> Option<(&str, f32)> {
+    let (name, weight) = inner.rsplit_once(':')?;
+    let weight = weight.trim().parse::<f32>().ok()?;
+    weight.is_finite().then_some((name, weight))
+}
+
+// Split on commas that aren't inside a group or escaped
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth: i32 = 0;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            ',' if depth == 0 => {
+                parts.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn unescape(name: &str) -> String {
+    name.replace("\\(", "(").replace("\\)", ")").replace("\\[", "[").replace("\\]", "]")
+}
+
+fn escape(name: &str) -> String {
+    if KAOMOJI.contains(&name) {
+        return name.to_string();
+    }
+    name.replace('(', "\\(").replace(')', "\\)").replace('[', "\\[").replace(']', "\\]")
+}
+
+fn format_weight(weight: f32) -> String {
+    let formatted = format!("{:.2}", weight);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, weight: Option<f32>) -> Tag {
+        Tag { name: name.to_string(), weight }
+    }
+
+    #[test]
+    fn parse_reads_weights_and_groups() {
+        let parsed = parse("1girl, (smile:1.2), [blurry], ((masterpiece)), pokemon \\(creature\\), (red hair, blue eyes:1.3)");
+        assert_eq!(
+            parsed,
+            [
+                tag("1girl", None),
+                tag("smile", Some(1.2)),
+                tag("blurry", Some(0.91)),
+                tag("masterpiece", Some(1.21)),
+                tag("pokemon (creature)", None),
+                tag("red hair", Some(1.3)),
+                tag("blue eyes", Some(1.3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let format = TagFormat { escape_parentheses: true, ..TagFormat::default() };
+        let caption = "1girl, (smile:1.2), pokemon \\(creature\\), (blurry:0.9), ^_^";
+        assert_eq!(normalize_caption(caption, &format), caption);
+        assert_eq!(serialize(&parse(caption), &format), caption);
+    }
+
+    #[test]
+    fn normalize_respells_and_dedupes() {
+        let format = TagFormat { underscores: UnderscoreStyle::Spaces, ..TagFormat::default() };
+        assert_eq!(normalize_caption("long_hair, Long Hair, ^_^,  blue   eyes", &format), "long hair, ^_^, blue eyes");
+
+        let format = TagFormat { underscores: UnderscoreStyle::Underscores, separator: ",".to_string(), ..TagFormat::default() };
+        assert_eq!(normalize_caption("long hair, o_o", &format), "long_hair,o_o");
+    }
+
+    #[test]
+    fn join_dedupes_across_parts() {
+        let joined = join(&["masterpiece, 1girl", "1girl, solo", "", "Masterpiece"], &TagFormat::default());
+        assert_eq!(joined, "masterpiece, 1girl, solo");
+    }
+
+    #[test]
+    fn tag_keys_ignore_case_and_spacing() {
+        assert_eq!(tag_key("Long_Hair"), tag_key("long  hair"));
+        assert_ne!(tag_key("o_o"), tag_key("o o"));
+    }
+}
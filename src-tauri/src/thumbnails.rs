@@ -0,0 +1,324 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+use base64::{Engine as _, engine::general_purpose};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+use serde::{Serialize, Deserialize};
+
+use crate::error::{AppError, ErrorCode};
+use crate::index;
+
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+const MAX_THUMBNAIL_SIZE: u32 = 2048;
+const JPEG_QUALITY: u8 = 80;
+// Size the cache is pruned back to, dropping the least recently used
+// thumbnails first
+const CACHE_LIMIT_BYTES: u64 = 512 * 1024 * 1024;
+// New thumbnails written between prunes
+const PRUNE_EVERY: usize = 500;
+// Temp files older than this were left behind by a crash
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+// Distinguishes temp files when two workers write the same thumbnail at once
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailFormat {
+    // JPEG for opaque images, WebP when there is transparency to preserve
+    #[default]
+    Auto,
+    Jpeg,
+    // The image crate's WebP encoder is lossless only
+    Webp,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Thumbnail {
+    pub path: String,
+    pub cache_path: Option<String>,
+    // Base64-encoded thumbnail bytes
+    pub data: Option<String>,
+    pub media_type: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    // Whether the thumbnail was served from the disk cache
+    pub cached: bool,
+    pub error: Option<AppError>,
+}
+
+pub struct RenderedThumbnail {
+    pub bytes: Vec<u8>,
+    pub format: ImageFormat,
+    pub cache_path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub cached: bool,
+}
+
+// On-disk thumbnail cache under the app data directory. Files are named by the
+// source's content fingerprint and requested edge length, so edits to an image
+// produce a new entry instead of serving a stale one. Entries for edited or
+// deleted images are left to `prune`, which keeps the cache within budget.
+#[derive(Clone)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    // Thumbnails written since the last prune
+    written: Arc<AtomicUsize>,
+}
+
+impl ThumbnailCache {
+    pub fn new(dir: PathBuf) -> Self {
+        ThumbnailCache { dir, written: Arc::default() }
+    }
+
+    // Delete the least recently used thumbnails until the cache fits in
+    // `CACHE_LIMIT_BYTES`, along with temp files left by a crash. Cache hits
+    // refresh a thumbnail's mtime, so that is what "recently used" goes by.
+    pub fn prune(&self) {
+        self.prune_to(CACHE_LIMIT_BYTES);
+    }
+
+    fn prune_to(&self, limit: u64) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        let mut files = Vec::new();
+        let mut total = 0;
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else { continue };
+            if !metadata.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let modified = metadata.modified().unwrap_or(now);
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                if now.duration_since(modified).is_ok_and(|age| age > STALE_TEMP_AGE) {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            }
+            total += metadata.len();
+            files.push((modified, metadata.len(), path));
+        }
+        if total <= limit {
+            return;
+        }
+
+        files.sort();
+        let mut removed = 0;
+        for (_, len, path) in files {
+            if total <= limit {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+                removed += 1;
+            }
+        }
+        println!("Pruned {} thumbnails from {}", removed, self.dir.display());
+    }
+
+    // Generate (or fetch from cache) thumbnails for many images at once, spread
+    // across one worker per CPU core. Results come back in the order requested.
+    pub fn get_many(&self, paths: &[String], size: u32, format: ThumbnailFormat) -> Vec<Thumbnail> {
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Thumbnail>>> = Mutex::new(paths.iter().map(|_| None).collect());
+        let workers = num_cpus::get().clamp(1, paths.len().max(1));
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else { break };
+
+                    let thumbnail = self.get_one(Path::new(path), size, format).unwrap_or_else(|e| failed(path, e));
+                    results.lock().unwrap_or_else(PoisonError::into_inner)[i] = Some(thumbnail);
+                });
+            }
+        });
+
+        // Every path gets an entry, so callers can line results up with requests
+        let results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+        paths
+            .iter()
+            .zip(results)
+            .map(|(path, thumbnail)| {
+                thumbnail.unwrap_or_else(|| {
+                    failed(path, AppError::new(ErrorCode::Internal, "Thumbnail was not generated").with_path(Path::new(path)))
+                })
+            })
+            .collect()
+    }
+
+    fn get_one(&self, path: &Path, size: u32, format: ThumbnailFormat) -> Result<Thumbnail, AppError> {
+        let rendered = self.render(path, size, format)?;
+        Ok(Thumbnail {
+            path: path.to_string_lossy().to_string(),
+            cache_path: Some(rendered.cache_path.to_string_lossy().to_string()),
+            data: Some(general_purpose::STANDARD.encode(&rendered.bytes)),
+            media_type: Some(crate::formats::mime_type(rendered.format).to_string()),
+            width: rendered.width,
+            height: rendered.height,
+            cached: rendered.cached,
+            error: None,
+        })
+    }
+
+    // Encoded thumbnail bytes for one image, from the cache or freshly generated
+    pub fn render(&self, path: &Path, size: u32, format: ThumbnailFormat) -> Result<RenderedThumbnail, AppError> {
+        if !path.exists() {
+            return Err(AppError::not_found(path));
+        }
+        let size = size.clamp(16, MAX_THUMBNAIL_SIZE);
+        let hash = index::fingerprint(path).map_err(|e| AppError::io("Failed to read", path, e))?;
+
+        // Reuse a cached thumbnail in any format this request accepts
+        let candidates: &[ImageFormat] = match format {
+            ThumbnailFormat::Auto => &[ImageFormat::Jpeg, ImageFormat::WebP],
+            ThumbnailFormat::Jpeg => &[ImageFormat::Jpeg],
+            ThumbnailFormat::Webp => &[ImageFormat::WebP],
+        };
+        for candidate in candidates {
+            let cache_path = self.cache_path(&hash, size, *candidate);
+            if let Ok(bytes) = fs::read(&cache_path) {
+                touch(&cache_path);
+                let (width, height) = image::io::Reader::with_format(Cursor::new(&bytes), *candidate)
+                    .into_dimensions()
+                    .unwrap_or((0, 0));
+                return Ok(RenderedThumbnail { bytes, format: *candidate, cache_path, width, height, cached: true });
+            }
+        }
+
+        let img = image::io::Reader::open(path)
+            .and_then(|r| r.with_guessed_format())
+            .map_err(|e| AppError::io("Failed to open", path, e))?
+            .decode()
+            .map_err(|e| AppError::new(ErrorCode::InvalidImage, format!("Failed to decode image: {}", e)).with_path(path))?;
+
+        let thumb = img.thumbnail(size, size);
+        let (width, height) = thumb.dimensions();
+
+        let output_format = match format {
+            ThumbnailFormat::Jpeg => ImageFormat::Jpeg,
+            ThumbnailFormat::Webp => ImageFormat::WebP,
+            ThumbnailFormat::Auto if thumb.color().has_alpha() => ImageFormat::WebP,
+            ThumbnailFormat::Auto => ImageFormat::Jpeg,
+        };
+        let bytes = encode(&thumb, output_format)?;
+
+        // Write via a temp file so a half-written thumbnail is never served
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            println!("Failed to create thumbnail cache {}: {}", self.dir.display(), e);
+        }
+        let cache_path = self.cache_path(&hash, size, output_format);
+        let temp_path = cache_path.with_extension(format!("{}.tmp", TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        if fs::write(&temp_path, &bytes).and_then(|_| fs::rename(&temp_path, &cache_path)).is_err() {
+            let _ = fs::remove_file(&temp_path);
+            println!("Failed to cache thumbnail for {}", path.display());
+        } else if self.written.fetch_add(1, Ordering::Relaxed) + 1 >= PRUNE_EVERY {
+            self.written.store(0, Ordering::Relaxed);
+            self.prune();
+        }
+
+        Ok(RenderedThumbnail { bytes, format: output_format, cache_path, width, height, cached: false })
+    }
+
+    fn cache_path(&self, hash: &str, size: u32, format: ImageFormat) -> PathBuf {
+        let ext = if format == ImageFormat::WebP { "webp" } else { "jpg" };
+        self.dir.join(format!("{}-{}.{}", hash, size, ext))
+    }
+}
+
+// Mark a cached thumbnail as recently used
+fn touch(path: &Path) {
+    let _ = fs::File::options().append(true).open(path).and_then(|file| file.set_modified(SystemTime::now()));
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::WebP => {
+            let rgba = img.to_rgba8();
+            WebPEncoder::new_lossless(&mut bytes)
+                .encode(&rgba, rgba.width(), rgba.height(), ColorType::Rgba8)
+                .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+        }
+        _ => {
+            let rgb = img.to_rgb8();
+            JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY)
+                .encode(&rgb, rgb.width(), rgb.height(), ColorType::Rgb8)
+                .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+        }
+    }
+    Ok(bytes)
+}
+
+// The entry `get_many` returns for an image it couldn't render
+fn failed(path: &str, error: AppError) -> Thumbnail {
+    Thumbnail {
+        path: path.to_string(),
+        cache_path: None,
+        data: None,
+        media_type: None,
+        width: 0,
+        height: 0,
+        cached: false,
+        error: Some(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_drops_least_recently_used_first() {
+        let dir = std::env::temp_dir().join(format!("tagmeister-thumbnails-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let chunk = 1000;
+        let epoch = SystemTime::UNIX_EPOCH;
+        for (name, age) in [("old-256.jpg", 3), ("used-256.jpg", 2), ("new-256.jpg", 1), ("new-256.jpg.0.tmp", 100)] {
+            let path = dir.join(name);
+            let len = if name.ends_with(".tmp") { 1 } else { chunk };
+            fs::write(&path, vec![0u8; len]).unwrap();
+            let file = fs::File::options().append(true).open(&path).unwrap();
+            file.set_modified(epoch + Duration::from_secs(1_000_000_000 - age * 3600)).unwrap();
+        }
+        touch(&dir.join("used-256.jpg"));
+
+        ThumbnailCache::new(dir.clone()).prune_to(2 * chunk as u64);
+        let mut left: Vec<String> =
+            fs::read_dir(&dir).unwrap().flatten().map(|entry| entry.file_name().to_string_lossy().to_string()).collect();
+        left.sort();
+        assert_eq!(left, ["new-256.jpg", "used-256.jpg"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_many_returns_one_entry_per_path_in_order() {
+        let dir = std::env::temp_dir().join(format!("tagmeister-thumbnails-many-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("a.png");
+        image::RgbImage::new(2, 2).save(&image).unwrap();
+        fs::write(dir.join("broken.png"), b"not an image").unwrap();
+        let paths: Vec<String> = [dir.join("missing.png"), image, dir.join("broken.png")]
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+
+        let thumbnails = ThumbnailCache::new(dir.join("cache")).get_many(&paths, 64, ThumbnailFormat::Auto);
+        assert_eq!(thumbnails.iter().map(|t| t.path.clone()).collect::<Vec<_>>(), paths);
+        assert_eq!(thumbnails[0].error.as_ref().map(|e| e.code), Some(ErrorCode::NotFound));
+        assert!(thumbnails[1].error.is_none() && thumbnails[1].data.is_some());
+        assert!(thumbnails[2].error.is_some() && thumbnails[2].data.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use notify_debouncer_full::notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::captions::{CaptionNaming, NamingPolicy};
+use crate::error::{AppError, ErrorCode};
+use crate::formats::{self, Detection};
+
+// Event emitted to the webview with a batch of `DatasetChange`s
+pub const DATASET_CHANGED_EVENT: &str = "dataset-changed";
+
+// Quiet period before a burst of filesystem events (e.g. a bulk copy) is flushed
+const DEBOUNCE: Duration = Duration::from_millis(750);
+
+// Captions written by the app itself are ignored for this long, so our own
+// saves aren't reported back as external edits
+const OWN_WRITE_GRACE: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    ImageAdded,
+    ImageRemoved,
+    ImageModified,
+    CaptionChanged,
+    CaptionRemoved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatasetChange {
+    pub kind: ChangeKind,
+    pub path: String,
+    // For caption changes, the image the caption belongs to (if it still exists)
+    pub image_path: Option<String>,
+}
+
+// Raw filesystem operation on a single path, before classification
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FsOp {
+    Added,
+    Removed,
+    Modified,
+}
+
+type OwnWrites = Arc<Mutex<HashMap<PathBuf, Instant>>>;
+
+// Watches the currently open dataset root and pushes changes to the UI
+#[derive(Default)]
+pub struct DatasetWatcher {
+    debouncer: Mutex<Option<Debouncer<RecommendedWatcher, RecommendedCache>>>,
+    own_writes: OwnWrites,
+}
+
+impl DatasetWatcher {
+    // Start watching `root`, replacing any previously watched dataset
+    pub fn watch(&self, app: AppHandle, root: &Path, recursive: bool) -> Result<(), AppError> {
+        if !root.is_dir() {
+            return Err(AppError::new(ErrorCode::NotFound, format!("Directory not found: {}", root.display())).with_path(root));
+        }
+
+        let own_writes = self.own_writes.clone();
+        let mut debouncer = new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                let naming = app.state::<NamingPolicy>().get();
+                let changes = classify_events(&events, &own_writes, &naming);
+                if !changes.is_empty() {
+                    if let Err(e) = app.emit(DATASET_CHANGED_EVENT, changes) {
+                        println!("Failed to emit dataset changes: {}", e);
+                    }
+                }
+            }
+            Err(errors) => {
+                for e in errors {
+                    println!("Dataset watcher error: {}", e);
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        debouncer
+            .watch(root, mode)
+            .map_err(|e| AppError::new(ErrorCode::Io, format!("Failed to watch {}: {}", root.display(), e)).with_path(root))?;
+
+        let mut current = self.debouncer.lock().map_err(|_| "Watcher lock poisoned".to_string())?;
+        // Dropping the old debouncer stops its thread
+        *current = Some(debouncer);
+        println!("Watching dataset: {}", root.display());
+        Ok(())
+    }
+
+    pub fn unwatch(&self) {
+        if let Ok(mut current) = self.debouncer.lock() {
+            current.take();
+        }
+    }
+
+    // Record a caption write made by the app so the watcher doesn't echo it
+    pub fn note_own_write(&self, path: &Path) {
+        if let Ok(mut own_writes) = self.own_writes.lock() {
+            let now = Instant::now();
+            own_writes.retain(|_, written| now.duration_since(*written) < OWN_WRITE_GRACE);
+            own_writes.insert(path.to_path_buf(), now);
+        }
+    }
+}
+
+// Turn a debounced batch into per-path dataset changes. Several events for the
+// same path collapse into the net effect (e.g. created then modified = added).
+fn classify_events(events: &[DebouncedEvent], own_writes: &OwnWrites, naming: &CaptionNaming) -> Vec<DatasetChange> {
+    // Net operation per path in first-seen order; None means the events cancelled out
+    let mut order: Vec<PathBuf> = Vec::new();
+    let mut ops: HashMap<PathBuf, Option<FsOp>> = HashMap::new();
+
+    for event in events {
+        let mut record = |path: &PathBuf, op: FsOp| {
+            let merged = match (ops.get(path).copied().flatten(), op) {
+                (None, op) => Some(op),
+                (Some(FsOp::Added), FsOp::Modified) => Some(FsOp::Added),
+                (Some(FsOp::Added), FsOp::Removed) => None,
+                (Some(FsOp::Removed), FsOp::Added) => Some(FsOp::Modified),
+                (Some(_), op) => Some(op),
+            };
+            if ops.insert(path.clone(), merged).is_none() {
+                order.push(path.clone());
+            }
+        };
+
+        match event.kind {
+            EventKind::Create(_) => event.paths.iter().for_each(|p| record(p, FsOp::Added)),
+            EventKind::Remove(_) => event.paths.iter().for_each(|p| record(p, FsOp::Removed)),
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                record(&event.paths[0], FsOp::Removed);
+                record(&event.paths[1], FsOp::Added);
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                event.paths.iter().for_each(|p| record(p, FsOp::Removed))
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                event.paths.iter().for_each(|p| record(p, FsOp::Added))
+            }
+            // Permission and timestamp-only changes don't affect the dataset
+            EventKind::Modify(ModifyKind::Metadata(_)) | EventKind::Access(_) => {}
+            _ => {
+                for path in &event.paths {
+                    let op = if path.exists() { FsOp::Modified } else { FsOp::Removed };
+                    record(path, op);
+                }
+            }
+        }
+    }
+
+    let own_writes = own_writes.lock().map(|w| w.clone()).unwrap_or_default();
+    let now = Instant::now();
+
+    order
+        .into_iter()
+        .filter_map(|path| {
+            let op = ops.get(&path).copied().flatten()?;
+            classify_path(&path, op, &own_writes, now, naming)
+        })
+        .collect()
+}
+
+fn classify_path(
+    path: &Path,
+    op: FsOp,
+    own_writes: &HashMap<PathBuf, Instant>,
+    now: Instant,
+    naming: &CaptionNaming,
+) -> Option<DatasetChange> {
+    // Editor swap files, lock files and other hidden temporaries
+    let name = path.file_name()?.to_string_lossy();
+    if name.starts_with('.') || name.ends_with('~') {
+        return None;
+    }
+    if op != FsOp::Removed && path.is_dir() {
+        return None;
+    }
+
+    if naming.is_caption_path(path) {
+        if let Some(written) = own_writes.get(path) {
+            if now.duration_since(*written) < OWN_WRITE_GRACE {
+                return None;
+            }
+        }
+        let kind = if op == FsOp::Removed { ChangeKind::CaptionRemoved } else { ChangeKind::CaptionChanged };
+        return Some(DatasetChange {
+            kind,
+            path: path.to_string_lossy().to_string(),
+            image_path: naming.image_for_caption(path).map(|p| p.to_string_lossy().to_string()),
+        });
+    }
+
+    let is_image = match op {
+        // The file is gone, so its extension is all we have to go on
+        FsOp::Removed => formats::spec_for_path(path).is_some(),
+        _ => matches!(formats::detect(path), Detection::Image { .. }),
+    };
+    if !is_image {
+        return None;
+    }
+
+    let kind = match op {
+        FsOp::Added => ChangeKind::ImageAdded,
+        FsOp::Removed => ChangeKind::ImageRemoved,
+        FsOp::Modified => ChangeKind::ImageModified,
+    };
+    Some(DatasetChange {
+        kind,
+        path: path.to_string_lossy().to_string(),
+        image_path: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use notify_debouncer_full::notify::event::{CreateKind, DataChange, RemoveKind};
+    use notify_debouncer_full::notify::Event;
+    use super::*;
+
+    fn event(kind: EventKind, paths: &[&Path]) -> DebouncedEvent {
+        let event = paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()));
+        DebouncedEvent::new(event, Instant::now())
+    }
+
+    fn modified(path: &Path) -> DebouncedEvent {
+        event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), &[path])
+    }
+
+    fn kinds(changes: &[DatasetChange]) -> Vec<(ChangeKind, String)> {
+        changes
+            .iter()
+            .map(|change| (change.kind, Path::new(&change.path).file_name().unwrap().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tagmeister-watcher-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::new(2, 2).save(dir.join("a.png")).unwrap();
+        std::fs::write(dir.join("a.txt"), "cat").unwrap();
+        dir
+    }
+
+    #[test]
+    fn own_caption_writes_are_not_reported() {
+        let dir = test_dir("own");
+        let naming = CaptionNaming::default();
+        let watcher = DatasetWatcher::default();
+        let caption = dir.join("a.txt");
+
+        let changes = classify_events(&[modified(&caption)], &watcher.own_writes, &naming);
+        assert_eq!(kinds(&changes), [(ChangeKind::CaptionChanged, "a.txt".to_string())]);
+        assert_eq!(changes[0].image_path, Some(dir.join("a.png").to_string_lossy().to_string()));
+
+        watcher.note_own_write(&caption);
+        assert!(classify_events(&[modified(&caption)], &watcher.own_writes, &naming).is_empty());
+
+        // Once the grace period is over, edits count again
+        let long_ago = Instant::now().checked_sub(OWN_WRITE_GRACE * 2).unwrap();
+        watcher.own_writes.lock().unwrap().insert(caption.clone(), long_ago);
+        assert_eq!(classify_events(&[modified(&caption)], &watcher.own_writes, &naming).len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn events_collapse_to_their_net_effect() {
+        let dir = test_dir("net");
+        let naming = CaptionNaming::default();
+        let own_writes = OwnWrites::default();
+        let image = dir.join("a.png");
+        let gone = dir.join("gone.png");
+        let renamed = dir.join("b.png");
+        std::fs::copy(&image, &renamed).unwrap();
+
+        let events = [
+            event(EventKind::Create(CreateKind::File), &[&image]),
+            modified(&image),
+            event(EventKind::Create(CreateKind::File), &[&gone]),
+            event(EventKind::Remove(RemoveKind::File), &[&gone]),
+            event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[&dir.join("old.png"), &renamed]),
+            modified(&dir.join(".a.txt.swp")),
+            modified(&dir.join("notes.md")),
+        ];
+        let changes = classify_events(&events, &own_writes, &naming);
+        assert_eq!(
+            kinds(&changes),
+            [
+                (ChangeKind::ImageAdded, "a.png".to_string()),
+                (ChangeKind::ImageRemoved, "old.png".to_string()),
+                (ChangeKind::ImageAdded, "b.png".to_string()),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
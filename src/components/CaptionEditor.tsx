@@ -31,13 +31,13 @@ import VisibilityIcon from '@mui/icons-material/Visibility';
 import VisibilityOffIcon from '@mui/icons-material/VisibilityOff';
 import AutoAwesomeIcon from '@mui/icons-material/AutoAwesome';
 import LinkIcon from '@mui/icons-material/Link';
-import { OpenAIService } from '../services/OpenAIService';
-import { AnthropicService } from '../services/AnthropicService';
-import { LMStudioService } from '../services/LMStudioService';
+import HistoryIcon from '@mui/icons-material/History';
+import { generateCaption, cancelCaption, processCaption, promptForStyle, isTagStyle, joinTags, modelIdFor, errorMessage, isAppError, GenerationOptions, Provider } from '../services/CaptionService';
 import Popover from '@mui/material/Popover';
 import Slider from '@mui/material/Slider';
 import Tooltip from '@mui/material/Tooltip';
 import AlertDialog from './AlertDialog';
+import CaptionHistoryDialog from './CaptionHistoryDialog';
 
 // FontSizePopover component
 const FontSizePopover: React.FC<{
@@ -233,6 +233,12 @@ const CaptionEditor: React.FC = () => {
     setShouldInterrupt,
     checkShouldInterrupt,
     shouldInterrupt,
+    activeJobId,
+    activeJobPaused,
+    startBatchJob,
+    pauseBatchJob,
+    resumeBatchJob,
+    cancelBatchJob,
     // LM Studio
     lmStudioBaseUrl,
     setLMStudioBaseUrl,
@@ -251,6 +257,8 @@ const CaptionEditor: React.FC = () => {
   
   const [caption, setCaption] = useState('');
   const [isGenerating, setIsGenerating] = useState(false);
+  // Id of the caption request currently streaming, so Stop can cancel it
+  const activeRequestId = useRef<string | null>(null);
   
   // Update caption when selection changes
   useEffect(() => {
@@ -276,6 +284,28 @@ const CaptionEditor: React.FC = () => {
     }
   };
   
+  // Connection settings for the chosen provider
+  const connectionOptions = (provider: Provider): GenerationOptions => {
+    const { lmStudioBaseUrl, ollamaBaseUrl } = useAppStore.getState();
+    const options: GenerationOptions = {};
+    if (provider === 'anthropic') {
+      if (!anthropicApiKey) {
+        throw new Error('Anthropic API key is required for Claude models');
+      }
+      options.apiKey = anthropicApiKey;
+    } else if (provider === 'openai') {
+      if (!apiKey) {
+        throw new Error('OpenAI API key is required for OpenAI models');
+      }
+      options.apiKey = apiKey;
+    } else if (provider === 'lmstudio') {
+      options.baseUrl = lmStudioBaseUrl;
+    } else if (provider === 'ollama') {
+      options.baseUrl = ollamaBaseUrl;
+    }
+    return options;
+  };
+
   // Helper function to delay execution
   const delay = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
   
@@ -299,112 +329,68 @@ const CaptionEditor: React.FC = () => {
     
     // Determine which service to use based on the selected model
     const provider = getProviderForModel(selectedModel);
-    
-    let rawCaption: string;
-    let processedCaption: string;
+    const options = connectionOptions(provider);
 
-    if (provider === 'anthropic') {
-      // Use Anthropic service
-      if (!anthropicApiKey) {
-        throw new Error('Anthropic API key is required for Claude models');
-      }
-      const anthropicService = new AnthropicService(anthropicApiKey, true);
-      rawCaption = await anthropicService.generateImageCaption(
-        imagePath,
-        selectedModel,
-        selectedPromptStyle,
-        streamHandler
-      );
-      processedCaption = anthropicService.processCaption(rawCaption).trim();
-    } else if (provider === 'lmstudio') {
-      // Use LM Studio service
-      const { lmStudioBaseUrl } = useAppStore.getState();
-      const lmstudioService = new LMStudioService(lmStudioBaseUrl);
-      // Use the same prompt logic as OpenAI
-      let promptText: string;
-      if (selectedPromptStyle === 'SDXL (Booru Tags)') {
-        promptText = "Generate a list of tags for this image in the style of Booru image boards and SDXL prompts. Focus on describing the visual elements, subjects, objects, settings, colors, lighting, composition, artistic style, and other relevant attributes. Format the output as a comma-separated list of tags without numbering or bullet points. Be specific and detailed, but keep each tag concise (1-3 words typically). Include tags for the main subject, background elements, colors, lighting, composition, style, medium, and any notable features. Do not include explanatory text or categorization headers - just provide the raw comma-separated tag list. Make sure to include mostly single-word tags, you can use some double-word tags if needed but mostly single word if possible.";
-      } else {
-        promptText = "Describe this image in one concise paragraph, starting immediately with the primary subject (e.g., 'Watch,' 'Landscape,' 'Person'). Focus on key elements, their relationships, and notable details. Be specific and direct, avoiding any introductory phrases like 'The image shows' or 'I can see.' Prioritize the most important aspects and describe them factually. Identify the main subject quickly and accurately, noting its dominant characteristics such as size, color, shape, or position. For multiple elements, describe their spatial relationships. Include relevant details about composition, color schemes, lighting, and textures. Mention any actions, movements, functions, or unique features of objects, and appearances or behaviors of people or animals. Include any visible text, logos, or recognizable symbols. Describe what you see literally, without interpreting the image's style (e.g., don't use terms like 'stylized,' 'illustration,' or mention artistic techniques). Treat every subject as a real object or scene, not as a representation. Use varied and precise vocabulary to create a vivid description while maintaining a neutral tone. Avoid subjective interpretations unless crucial to understanding the image's content.";
-      }
-      // Remove lmstudio: prefix for model id
-      const modelId = selectedModel.replace(/^lmstudio:/, '');
-      rawCaption = await lmstudioService.generateImageCaption(
-        imagePath,
-        modelId,
-        promptText,
-        streamHandler
-      );
-      // Use OpenAI-style post-processing
-      processedCaption = rawCaption.trim().replace(/\.$/, '');
-    } else if (provider === 'ollama') {
-      // Use Ollama service
-      const { ollamaBaseUrl } = useAppStore.getState();
-      const { OllamaService } = await import('../services/OllamaService');
-      const ollamaService = new OllamaService(ollamaBaseUrl);
-      // Use the same prompt logic as LM Studio
-      let promptText: string;
-      if (selectedPromptStyle === 'SDXL (Booru Tags)') {
-        promptText = "Generate a list of tags for this image in the style of Booru image boards and SDXL prompts. Focus on describing the visual elements, subjects, objects, settings, colors, lighting, composition, artistic style, and other relevant attributes. Format the output as a comma-separated list of tags without numbering or bullet points. Be specific and detailed, but keep each tag concise (1-3 words typically). Include tags for the main subject, background elements, colors, lighting, composition, style, medium, and any notable features. Do not include explanatory text or categorization headers - just provide the raw comma-separated tag list. Make sure to include mostly single-word tags, you can use some double-word tags if needed but mostly single word if possible.";
-      } else {
-        promptText = "Describe this image in one concise paragraph, starting immediately with the primary subject (e.g., 'Watch,' 'Landscape,' 'Person'). Focus on key elements, their relationships, and notable details. Be specific and direct, avoiding any introductory phrases like 'The image shows' or 'I can see.' Prioritize the most important aspects and describe them factually. Identify the main subject quickly and accurately, noting its dominant characteristics such as size, color, shape, or position. For multiple elements, describe their spatial relationships. Include relevant details about composition, color schemes, lighting, and textures. Mention any actions, movements, functions, or unique features of objects, and appearances or behaviors of people or animals. Include any visible text, logos, or recognizable symbols. Describe what you see literally, without interpreting the image's style (e.g., don't use terms like 'stylized,' 'illustration,' or mention artistic techniques). Treat every subject as a real object or scene, not as a representation. Use varied and precise vocabulary to create a vivid description while maintaining a neutral tone. Avoid subjective interpretations unless crucial to understanding the image's content.";
-      }
-      // Remove ollama: prefix and :latest suffix for model id
-      const modelId = selectedModel.replace(/^ollama:/, '').replace(/:latest$/, '');
-      rawCaption = await ollamaService.generateImageCaption(
-        imagePath,
-        modelId,
-        promptText,
-        streamHandler
-      );
-      processedCaption = rawCaption.trim().replace(/\.$/, '');
-    } else {
-      // Use OpenAI service
-      if (!apiKey) {
-        throw new Error('OpenAI API key is required for OpenAI models');
-      }
-      const openAiService = new OpenAIService(apiKey);
-      rawCaption = await openAiService.generateImageCaption(
+    const requestId = `${Date.now()}-${imagePath}`;
+    activeRequestId.current = requestId;
+    let result;
+    try {
+      result = await generateCaption(
         imagePath,
+        provider,
         selectedModel,
-        selectedPromptStyle,
-        streamHandler
+        promptForStyle(selectedPromptStyle),
+        options,
+        streamHandler,
+        requestId
       );
-      processedCaption = openAiService.processCaption(rawCaption).trim();
+    } finally {
+      activeRequestId.current = null;
     }
+    if (result.dictionary && result.dictionary.unknown.length > 0) {
+      console.warn('Generated tags not in the vocabulary:', result.dictionary.unknown.join(', '));
+    }
+    if (result.filtered && result.filtered.stripped.length > 0) {
+      console.log('Filtered from the generated caption:', result.filtered.stripped.map(item => item.text).join(' | '));
+    }
+    let processedCaption = processCaption(provider, result.caption);
 
     // Remove trailing comma if present
     if (processedCaption.endsWith(',')) {
       processedCaption = processedCaption.substring(0, processedCaption.length - 1).trim();
     }
 
-    // Build final caption with prefix and suffix
+    // Build final caption with prefix and suffix. Tag captions are joined as
+    // tag lists so the prefix/suffix tags aren't duplicated
     let finalCaption = '';
-
-    // Handle prefix
-    let prefix = prefixText.trim();
-    if (prefix) {
-      // Remove trailing comma and space if present
-      if (prefix.endsWith(', ') || prefix.endsWith(' ,')) {
-        prefix = prefix.substring(0, prefix.length - 2).trim();
-      } else if (prefix.endsWith(',')) {
-        prefix = prefix.substring(0, prefix.length - 1).trim();
-      }
-      finalCaption = prefix + ', ' + processedCaption;
+    if (isTagStyle(selectedPromptStyle)) {
+      finalCaption = await joinTags([prefixText, processedCaption, suffixText]);
     } else {
-      finalCaption = processedCaption;
-    }
+      // Handle prefix
+      let prefix = prefixText.trim();
+      if (prefix) {
+        // Remove trailing comma and space if present
+        if (prefix.endsWith(', ') || prefix.endsWith(' ,')) {
+          prefix = prefix.substring(0, prefix.length - 2).trim();
+        } else if (prefix.endsWith(',')) {
+          prefix = prefix.substring(0, prefix.length - 1).trim();
+        }
+        finalCaption = prefix + ', ' + processedCaption;
+      } else {
+        finalCaption = processedCaption;
+      }
 
-    // Handle suffix
-    let suffix = suffixText.trim();
-    if (suffix) {
-      // Remove leading comma and space if present
-      if (suffix.startsWith(', ') || suffix.startsWith(' ,')) {
-        suffix = suffix.substring(2).trim();
-      } else if (suffix.startsWith(',')) {
-        suffix = suffix.substring(1).trim();
+      // Handle suffix
+      let suffix = suffixText.trim();
+      if (suffix) {
+        // Remove leading comma and space if present
+        if (suffix.startsWith(', ') || suffix.startsWith(' ,')) {
+          suffix = suffix.substring(2).trim();
+        } else if (suffix.startsWith(',')) {
+          suffix = suffix.substring(1).trim();
+        }
+        finalCaption = finalCaption + ', ' + suffix;
       }
-      finalCaption = finalCaption + ', ' + suffix;
     }
 
     // Update caption in state
@@ -412,7 +398,12 @@ const CaptionEditor: React.FC = () => {
     setCaption(finalCaption);
 
     // Save caption to file
-    await saveCaption(imagePath, finalCaption);
+    await saveCaption(imagePath, finalCaption, {
+      kind: 'model',
+      provider,
+      model: modelIdFor(provider, selectedModel),
+      prompt: promptForStyle(selectedPromptStyle)
+    });
 
     // Refresh caption from store to ensure UI shows latest prefix/suffix
     setCaption(captions[imagePath] || finalCaption);
@@ -429,6 +420,7 @@ const CaptionEditor: React.FC = () => {
   // State for custom confirmation dialog
   const [confirmDialogOpen, setConfirmDialogOpen] = useState(false);
   const [pendingImagesToProcess, setPendingImagesToProcess] = useState<string[] | null>(null);
+  const [historyOpen, setHistoryOpen] = useState(false);
 
   // State for alert dialog
   const [alertDialogOpen, setAlertDialogOpen] = useState(false);
@@ -453,8 +445,30 @@ const CaptionEditor: React.FC = () => {
     setAlertDialogOpen(true);
   };
 
+  // Hand several images to a backend job, which captions and saves them
+  // without needing this window to stay open
+  const startBatchGeneration = async (imagesToProcess: string[]) => {
+    const provider = getProviderForModel(selectedModel);
+    try {
+      await startBatchJob(imagesToProcess, {
+        provider,
+        model: selectedModel,
+        prompt: promptForStyle(selectedPromptStyle),
+        options: connectionOptions(provider),
+        prefix: prefixText,
+        suffix: suffixText
+      });
+    } catch (error) {
+      showAlertDialog(`Error: ${errorMessage(error)}`, { type: 'error', title: 'Caption Generation Error' });
+    }
+  };
+
   // Refactored caption generation logic
   const startCaptionGeneration = async (imagesToProcess: string[]) => {
+    if (imagesToProcess.length > 1) {
+      await startBatchGeneration(imagesToProcess);
+      return;
+    }
     setIsGenerating(true);
     setProcessingState(true, imagesToProcess.length);
     setShouldInterrupt(false);
@@ -479,27 +493,34 @@ const CaptionEditor: React.FC = () => {
         }
       }
     } catch (error) {
+      if (isAppError(error) && error.code === 'cancelled') {
+        console.log('Caption generation cancelled by user');
+        return;
+      }
       console.error('Error in caption generation:', error);
       const provider = getProviderForModel(selectedModel);
-      let errorMessage = 'An error occurred during caption generation.';
-      if (error instanceof Error) {
-        if (error.message.includes('429') || 
-            error.message.toLowerCase().includes('rate limit')) {
-          errorMessage = `${provider === 'anthropic' ? 'Anthropic' : 'OpenAI'} API rate limit exceeded. Please try again later.`;
-        } else if (error.message.includes('401')) {
-          errorMessage = `Invalid API key. Please check your ${provider === 'anthropic' ? 'Anthropic' : 'OpenAI'} API key.`;
-        } else if (error.message.includes('400')) {
-          const match = error.message.match(/400[^:]*: (.+)/);
-          if (match && match[1]) {
-            errorMessage = `Bad request: ${match[1]}`;
-          } else {
-            errorMessage = `Bad request to ${provider === 'anthropic' ? 'Anthropic' : 'OpenAI'} API. Please try again.`;
-          }
-        } else {
-          errorMessage = `Error: ${error.message}`;
+      const providerName = provider === 'anthropic' ? 'Anthropic' : 'OpenAI';
+      let message = `Error: ${errorMessage(error)}`;
+      if (isAppError(error)) {
+        switch (error.code) {
+          case 'rate_limited':
+            message = `${providerName} API rate limit exceeded. Please try again later.`;
+            break;
+          case 'auth_failed':
+          case 'missing_api_key':
+            message = `Invalid API key. Please check your ${providerName} API key.`;
+            break;
+          case 'provider_rejected':
+            message = `Bad request: ${error.message}`;
+            break;
+          case 'not_found':
+          case 'invalid_image':
+          case 'unsupported_format':
+            message = `${error.message}${error.path ? ` (${error.path})` : ''}`;
+            break;
         }
       }
-      showAlertDialog(errorMessage, { type: 'error', title: 'Caption Generation Error' });
+      showAlertDialog(message, { type: 'error', title: 'Caption Generation Error' });
       if (processedImages.length > 0) {
         const remainingImages = Array.from(selectedImages).filter(
           img => !processedImages.includes(img)
@@ -542,7 +563,14 @@ const CaptionEditor: React.FC = () => {
   
   // Handle stopping the caption generation process
   const handleStopProcessing = () => {
+    if (activeJobId) {
+      cancelBatchJob();
+      return;
+    }
     setShouldInterrupt(true);
+    if (activeRequestId.current) {
+      cancelCaption(activeRequestId.current);
+    }
   };
   
   if (!selectedImage) {
@@ -931,11 +959,20 @@ const CaptionEditor: React.FC = () => {
         >
           Auto-Captioner
         </Typography>
-        {/* Font size popover control */}
-        <FontSizePopover
-          fontSize={fontSize}
-          adjustFontSize={adjustFontSize}
-        />
+        <Box sx={{ display: 'flex', alignItems: 'center' }}>
+          <Tooltip title="Caption history">
+            <span>
+              <IconButton size="small" onClick={() => setHistoryOpen(true)} disabled={!selectedImage}>
+                <HistoryIcon fontSize="small" />
+              </IconButton>
+            </span>
+          </Tooltip>
+          {/* Font size popover control */}
+          <FontSizePopover
+            fontSize={fontSize}
+            adjustFontSize={adjustFontSize}
+          />
+        </Box>
       </Box>
       
       <Grid container spacing={2} sx={{ mb: 2 }}>
@@ -1109,6 +1146,7 @@ const CaptionEditor: React.FC = () => {
           onClick={handleGenerateCaption}
           disabled={
             isGenerating ||
+            activeJobId !== null ||
             (
               getProviderForModel(selectedModel) === 'openai'
                 ? !apiKey
@@ -1136,6 +1174,42 @@ const CaptionEditor: React.FC = () => {
           {isGenerating ? 'Generating...' : 'Generate Caption'}
         </Button>
 
+        {/* Batch job progress and controls */}
+        {isProcessing && totalToProcess > 1 && (
+          <Box sx={{ mb: 2 }}>
+            <LinearProgress
+              variant="determinate"
+              value={(processedCount / totalToProcess) * 100}
+              sx={{ mb: 1 }}
+            />
+            <Box sx={{ display: 'flex', alignItems: 'center', gap: 1 }}>
+              <Typography
+                variant="caption"
+                sx={{ flex: 1, fontFamily: '"Karla", sans-serif' }}
+              >
+                {activeJobPaused ? 'Paused' : 'Captioning'} {processedCount} / {totalToProcess}
+              </Typography>
+              {activeJobId && (
+                <Button
+                  size="small"
+                  onClick={activeJobPaused ? resumeBatchJob : pauseBatchJob}
+                  sx={{ fontFamily: '"Karla", sans-serif' }}
+                >
+                  {activeJobPaused ? 'Resume' : 'Pause'}
+                </Button>
+              )}
+              <Button
+                size="small"
+                color="error"
+                onClick={handleStopProcessing}
+                sx={{ fontFamily: '"Karla", sans-serif' }}
+              >
+                Stop
+              </Button>
+            </Box>
+          </Box>
+        )}
+
         {/* Custom confirmation dialog for multi-image captioning */}
         <AlertDialog
           open={confirmDialogOpen}
@@ -1165,6 +1239,11 @@ const CaptionEditor: React.FC = () => {
           onClose={() => setAlertDialogOpen(false)}
           onConfirm={alertDialogOnConfirm}
         />
+        <CaptionHistoryDialog
+          open={historyOpen}
+          imagePath={selectedImage}
+          onClose={() => setHistoryOpen(false)}
+        />
       
         <Typography 
           variant="subtitle2" 
@@ -0,0 +1,146 @@
+import React, { useEffect, useState } from 'react';
+import {
+  Dialog,
+  DialogTitle,
+  DialogContent,
+  DialogActions,
+  Button,
+  List,
+  ListItemButton,
+  ListItemText,
+  Box,
+  Typography
+} from '@mui/material';
+import { useAppStore } from '../context/AppStore';
+import {
+  getCaptionHistory,
+  diffCaptionVersion,
+  errorMessage,
+  CaptionSource,
+  CaptionVersion,
+  DiffSegment
+} from '../services/CaptionService';
+
+type CaptionHistoryDialogProps = {
+  open: boolean;
+  imagePath: string | null;
+  onClose: () => void;
+};
+
+function describeSource(source: CaptionSource): string {
+  switch (source.kind) {
+    case 'manual':
+      return 'Manual edit';
+    case 'model':
+      return `Generated by ${source.model}`;
+    case 'restore':
+      return 'Restored';
+    case 'rollback':
+      return 'Batch rolled back';
+    case 'bulk_edit':
+      return 'Bulk tag edit';
+  }
+}
+
+const CaptionHistoryDialog: React.FC<CaptionHistoryDialogProps> = ({ open, imagePath, onClose }) => {
+  const restoreCaptionVersion = useAppStore(state => state.restoreCaptionVersion);
+  const [versions, setVersions] = useState<CaptionVersion[]>([]);
+  const [selected, setSelected] = useState<CaptionVersion | null>(null);
+  const [diff, setDiff] = useState<DiffSegment[]>([]);
+  const [error, setError] = useState<string | null>(null);
+
+  const refresh = async () => {
+    if (!imagePath) return;
+    try {
+      const history = await getCaptionHistory(imagePath);
+      setVersions(history);
+      setSelected(history[0] ?? null);
+      setError(null);
+    } catch (e) {
+      setError(errorMessage(e));
+    }
+  };
+
+  useEffect(() => {
+    if (open) {
+      refresh();
+    }
+  }, [open, imagePath]);
+
+  useEffect(() => {
+    if (!selected) {
+      setDiff([]);
+      return;
+    }
+    diffCaptionVersion(selected.id)
+      .then(setDiff)
+      .catch(e => setError(errorMessage(e)));
+  }, [selected]);
+
+  const handleRestore = async () => {
+    if (!selected) return;
+    try {
+      await restoreCaptionVersion(selected.id);
+      await refresh();
+    } catch (e) {
+      setError(errorMessage(e));
+    }
+  };
+
+  return (
+    <Dialog open={open} onClose={onClose} maxWidth="md" fullWidth>
+      <DialogTitle>Caption History</DialogTitle>
+      <DialogContent sx={{ display: 'flex', gap: 2, minHeight: 300 }}>
+        <List dense sx={{ width: 240, flexShrink: 0, overflowY: 'auto' }}>
+          {versions.length === 0 && (
+            <Typography variant="body2" color="text.secondary">No saved versions yet</Typography>
+          )}
+          {versions.map(version => (
+            <ListItemButton
+              key={version.id}
+              selected={selected?.id === version.id}
+              onClick={() => setSelected(version)}
+            >
+              <ListItemText
+                primary={describeSource(version.source)}
+                secondary={new Date(version.saved_at).toLocaleString()}
+              />
+            </ListItemButton>
+          ))}
+        </List>
+        <Box sx={{ flex: 1, fontFamily: '"Inconsolata", monospace', whiteSpace: 'pre-wrap', wordBreak: 'break-word' }}>
+          {error && <Typography color="error" sx={{ mb: 1 }}>{error}</Typography>}
+          {selected?.source.kind === 'model' && selected.source.prompt && (
+            <Typography variant="caption" color="text.secondary" component="div" sx={{ mb: 1 }}>
+              Prompt: {selected.source.prompt}
+            </Typography>
+          )}
+          {diff.map((segment, i) => (
+            <Box
+              key={i}
+              component="span"
+              sx={{
+                bgcolor: segment.kind === 'insert'
+                  ? 'rgba(76, 175, 80, 0.3)'
+                  : segment.kind === 'delete'
+                    ? 'rgba(244, 67, 54, 0.3)'
+                    : 'transparent',
+                textDecoration: segment.kind === 'delete' ? 'line-through' : 'none'
+              }}
+            >
+              {segment.text}
+            </Box>
+          ))}
+        </Box>
+      </DialogContent>
+      <DialogActions>
+        <Button onClick={handleRestore} disabled={!selected}>
+          Restore This Version
+        </Button>
+        <Button onClick={onClose}>Close</Button>
+      </DialogActions>
+    </Dialog>
+  );
+};
+
+export default CaptionHistoryDialog;
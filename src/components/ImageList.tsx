@@ -15,8 +15,10 @@ import {
 } from '@mui/material';
 import Tooltip from '@mui/material/Tooltip';
 import FolderOpenIcon from '@mui/icons-material/FolderOpen';
+import AccountTreeIcon from '@mui/icons-material/AccountTree';
 import SelectAllIcon from '@mui/icons-material/SelectAll';
 import { invoke } from '@tauri-apps/api/core';
+import { AppError } from '../services/CaptionService';
 
 const ImageList: React.FC = () => {
   const { 
@@ -30,7 +32,9 @@ const ImageList: React.FC = () => {
     isProcessing,
     processedCount,
     totalToProcess,
-    selectDirectory
+    selectDirectory,
+    includeSubfolders,
+    setIncludeSubfolders
   } = useAppStore();
   
   const listRef = useRef<HTMLUListElement>(null);
@@ -43,43 +47,48 @@ const ImageList: React.FC = () => {
     
     setLoadingThumbnails(true);
     const newImageUrls: Record<string, string> = {};
-    let loadedCount = 0;
+    let cancelled = false;
     
-    // Load thumbnails one by one to avoid overwhelming the system
-    const loadThumbnail = async (index: number) => {
-      if (index >= imageFiles.length) {
+    // Request thumbnails in batches so the list fills in progressively;
+    // the backend resizes and caches them, generating each batch in parallel
+    const BATCH_SIZE = 32;
+    const loadBatch = async (start: number) => {
+      if (cancelled) return;
+      if (start >= imageFiles.length) {
         setLoadingThumbnails(false);
         return;
       }
       
-      const file = imageFiles[index];
+      const batch = imageFiles.slice(start, start + BATCH_SIZE);
       try {
-        // Use Rust function to load the image as base64
-        const base64 = await invoke<string>('read_image_as_base64', { path: file.path });
+        const thumbnails = await invoke<Array<{
+          path: string;
+          data: string | null;
+          media_type: string | null;
+          error: AppError | null;
+        }>>('get_thumbnails', { paths: batch.map(file => file.path), size: 128 });
         
-        // Determine the image type from the file extension
-        const ext = file.path.split('.').pop()?.toLowerCase() || 'png';
-        const mimeType = ext === 'jpg' || ext === 'jpeg' ? 'image/jpeg' : 'image/png';
-        
-        // Create a data URL
-        newImageUrls[file.path] = `data:${mimeType};base64,${base64}`;
-        
-        // Update state every few images to show progress
-        loadedCount++;
-        if (loadedCount % 5 === 0 || index === imageFiles.length - 1) {
+        for (const thumbnail of thumbnails) {
+          if (thumbnail.data && thumbnail.media_type) {
+            newImageUrls[thumbnail.path] = `data:${thumbnail.media_type};base64,${thumbnail.data}`;
+          } else {
+            console.error('Error loading thumbnail:', thumbnail.path, thumbnail.error);
+          }
+        }
+        if (!cancelled) {
           setImageUrls({...newImageUrls});
         }
-        
-        // Load the next thumbnail
-        loadThumbnail(index + 1);
       } catch (err) {
-        console.error('Error loading thumbnail:', err);
-        loadThumbnail(index + 1);
+        console.error('Error loading thumbnails:', err);
       }
+      
+      loadBatch(start + BATCH_SIZE);
     };
     
-    // Start loading thumbnails
-    loadThumbnail(0);
+    loadBatch(0);
+    return () => {
+      cancelled = true;
+    };
   }, [imageFiles]);
   
   // Handle keyboard shortcuts
@@ -294,6 +303,24 @@ const ImageList: React.FC = () => {
         <Box sx={{ display: 'flex', gap: 1 }}>
           {/* Select All button moved to bottom */}
           
+          <Tooltip title={includeSubfolders ? "Showing Subfolders" : "Include Subfolders"}>
+            <IconButton 
+              onClick={() => setIncludeSubfolders(!includeSubfolders)} 
+              size="small"
+              color={includeSubfolders ? 'primary' : 'default'}
+              sx={{
+                borderRadius: '12px',
+                padding: '6px',
+                backgroundColor: theme => theme.palette.mode === 'dark' ? 'rgba(255, 255, 255, 0.08)' : 'rgba(0, 0, 0, 0.04)',
+                '&:hover': {
+                  backgroundColor: theme => theme.palette.mode === 'dark' ? 'rgba(255, 255, 255, 0.12)' : 'rgba(0, 0, 0, 0.08)'
+                }
+              }}
+            >
+              <AccountTreeIcon fontSize="small" />
+            </IconButton>
+          </Tooltip>
+          
           <Tooltip title="Open Directory">
             <IconButton 
               onClick={() => {
@@ -1,7 +1,7 @@
 import React, { useState, useEffect, useRef } from 'react';
 import { useAppStore } from '../context/AppStore';
 import { Box, Paper, Typography, CircularProgress, IconButton, Divider } from '@mui/material';
-import { invoke } from '@tauri-apps/api/core';
+import { convertFileSrc } from '@tauri-apps/api/core';
 import KeyboardArrowUpIcon from '@mui/icons-material/KeyboardArrowUp';
 import KeyboardArrowDownIcon from '@mui/icons-material/KeyboardArrowDown';
 
@@ -27,26 +27,9 @@ const ImageViewer: React.FC = () => {
 
   useEffect(() => {
     if (selectedImage) {
-      setLoading(true);
       setError(null);
-      
-      // Use Rust function to load the image as base64
-      invoke<string>('read_image_as_base64', { path: selectedImage })
-        .then(base64 => {
-          // Determine the image type from the file extension
-          const ext = selectedImage.split('.').pop()?.toLowerCase() || 'png';
-          const mimeType = ext === 'jpg' || ext === 'jpeg' ? 'image/jpeg' : 'image/png';
-          
-          // Create a data URL
-          const dataUrl = `data:${mimeType};base64,${base64}`;
-          setImageSrc(dataUrl);
-          setLoading(false);
-        })
-        .catch(err => {
-          console.error('Error loading image:', err);
-          setError('Failed to load image');
-          setLoading(false);
-        });
+      // Load the original file through the tagimg:// protocol served by the backend
+      setImageSrc(convertFileSrc(selectedImage, 'tagimg'));
     } else {
       setImageSrc(null);
     }
@@ -1,9 +1,42 @@
 import { create } from 'zustand';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
-import { readTextFile, writeTextFile, readDir, exists, create as createFs } from '@tauri-apps/plugin-fs';
+import { writeTextFile, readDir, exists, create as createFs } from '@tauri-apps/plugin-fs';
 import { basename, extname, dirname, join, sep } from '@tauri-apps/api/path';
 import { appDataDir } from '@tauri-apps/api/path';
+import {
+  listModels,
+  listCaptionJobs,
+  startCaptionJob,
+  pauseCaptionJob,
+  resumeCaptionJob,
+  cancelCaptionJob,
+  saveCaptions,
+  restoreCaptionVersion as restoreVersion,
+  rollbackCaptionBatch as rollbackBatch,
+  bulkEditTags,
+  BulkEditReport,
+  TagOperation,
+  CaptionSource,
+  SaveResult,
+  loadCaptions,
+  setCaptionNaming as setBackendCaptionNaming,
+  CaptionNaming,
+  DEFAULT_CAPTION_NAMING,
+  loadTagDictionary,
+  removeTagDictionary as removeBackendTagDictionary,
+  setDictionarySettings as setBackendDictionarySettings,
+  DictionaryKind,
+  DictionarySettings,
+  DEFAULT_DICTIONARY_SETTINGS,
+  JobConfig,
+  JobProgress,
+  JobStatus,
+  Provider,
+  JOB_PROGRESS_EVENT,
+  JOB_STATE_EVENT
+} from '../services/CaptionService';
 
 // Define a type for our file entries
 type FileInfo = {
@@ -15,6 +48,13 @@ interface Caption {
   [key: string]: string;
 }
 
+// Emitted by the backend watcher when the open dataset changes on disk
+type DatasetChange = {
+  kind: 'image_added' | 'image_removed' | 'image_modified' | 'caption_changed' | 'caption_removed';
+  path: string;
+  image_path: string | null;
+};
+
 interface AppState {
   directorySelectionError: string | null;
   // Directory and image selection
@@ -40,6 +80,12 @@ interface AppState {
   fontSize: number;
   leftPanelWidth: number;
   rightPanelWidth: number;
+  captionNaming: CaptionNaming;
+  // List and watch images in subfolders of the dataset too
+  includeSubfolders: boolean;
+  // Tag dictionary files, reloaded into the backend on startup
+  tagDictionaries: Array<{ path: string; kind: DictionaryKind }>;
+  dictionarySettings: DictionarySettings;
 
   // LM Studio integration
   lmStudioBaseUrl: string;
@@ -57,6 +103,10 @@ interface AppState {
   processedCount: number;
   totalToProcess: number;
   shouldInterrupt: boolean;
+  // Batch caption job running in the backend, if any
+  activeJobId: string | null;
+  activeJobProvider: Provider | null;
+  activeJobPaused: boolean;
   
   // Actions
   toggleApiKeyVisibility: () => void;
@@ -65,6 +115,10 @@ interface AppState {
   incrementProcessedCount: () => void;
   setShouldInterrupt: (shouldInterrupt: boolean) => void;
   checkShouldInterrupt: (reset?: boolean) => boolean;
+  startBatchJob: (paths: string[], config: JobConfig) => Promise<void>;
+  pauseBatchJob: () => Promise<void>;
+  resumeBatchJob: () => Promise<void>;
+  cancelBatchJob: () => Promise<void>;
   initialize: () => Promise<void>;
   toggleImageSelection: (path: string) => void;
   clearImageSelection: () => void;
@@ -81,10 +135,27 @@ interface AppState {
   handleImageClick: (path: string, options: { isCtrlPressed?: boolean, isShiftPressed?: boolean }) => void;
   setPromptStyle: (style: string) => void;
   updateCaption: (imagePath: string, caption: string) => void;
-  saveCaption: (imagePath: string, caption: string) => Promise<void>;
+  saveCaption: (imagePath: string, caption: string, source?: CaptionSource) => Promise<void>;
+  restoreCaptionVersion: (versionId: number) => Promise<void>;
+  rollbackCaptionBatch: (batchId: string, force?: boolean) => Promise<SaveResult[]>;
+  applyBulkTagEdit: (operations: TagOperation[], paths?: string[]) => Promise<BulkEditReport>;
   selectDirectory: () => Promise<void>;
   setPanelWidth: (panel: 'left' | 'right', width: number) => void;
+  setCaptionNaming: (naming: CaptionNaming) => Promise<void>;
+  setIncludeSubfolders: (include: boolean) => Promise<void>;
+  addTagDictionary: (path: string, kind: DictionaryKind) => Promise<void>;
+  removeTagDictionary: (path: string) => Promise<void>;
+  setDictionarySettings: (settings: DictionarySettings) => Promise<void>;
 
+  setIncludeSubfolders: async (include) => {
+    set({ includeSubfolders: include });
+    get().saveSettings();
+    if (get().currentDirectory) {
+      await get().loadImagesFromDirectory();
+      await get().watchCurrentDirectory();
+    }
+  },
+  
   // LM Studio actions
   setLMStudioBaseUrl: (url: string) => void;
   checkLMStudioConnection: () => Promise<boolean>;
@@ -99,6 +170,11 @@ interface AppState {
   loadSettings: () => Promise<void>;
   saveSettings: () => Promise<void>;
   loadImagesFromDirectory: () => Promise<void>;
+  watchCurrentDirectory: () => Promise<void>;
+  handleDatasetChanges: (changes: DatasetChange[]) => Promise<void>;
+  reloadCaptions: (imagePaths: string[]) => Promise<void>;
+  handleJobProgress: (progress: JobProgress) => void;
+  handleJobState: (status: JobStatus) => void;
 }
 
 export const useAppStore = create<AppState>((set, get) => ({
@@ -122,6 +198,10 @@ export const useAppStore = create<AppState>((set, get) => ({
   fontSize: 14.0,
   leftPanelWidth: 0.2,
   rightPanelWidth: 0.2,
+  captionNaming: DEFAULT_CAPTION_NAMING,
+  includeSubfolders: false,
+  tagDictionaries: [],
+  dictionarySettings: DEFAULT_DICTIONARY_SETTINGS,
   // LM Studio
   lmStudioBaseUrl: 'http://localhost:1234/v1',
   lmStudioAvailable: false,
@@ -137,6 +217,9 @@ export const useAppStore = create<AppState>((set, get) => ({
   processedCount: 0,
   totalToProcess: 0,
   shouldInterrupt: false,
+  activeJobId: null,
+  activeJobProvider: null,
+  activeJobPaused: false,
   
   // Actions
   toggleApiKeyVisibility: () => set(state => ({ apiKeyVisible: !state.apiKeyVisible })),
@@ -161,11 +244,62 @@ export const useAppStore = create<AppState>((set, get) => ({
     }
     return shouldInterrupt;
   },
+
+  startBatchJob: async (paths, config) => {
+    const status = await startCaptionJob(paths, config);
+    get().handleJobState(status);
+  },
+
+  pauseBatchJob: async () => {
+    const { activeJobId } = get();
+    if (activeJobId) {
+      get().handleJobState(await pauseCaptionJob(activeJobId));
+    }
+  },
+
+  resumeBatchJob: async () => {
+    const { activeJobId, activeJobProvider, apiKey, anthropicApiKey } = get();
+    if (activeJobId) {
+      const key = activeJobProvider === 'openai'
+        ? apiKey
+        : activeJobProvider === 'anthropic' ? anthropicApiKey : undefined;
+      get().handleJobState(await resumeCaptionJob(activeJobId, key || undefined));
+    }
+  },
+
+  cancelBatchJob: async () => {
+    const { activeJobId } = get();
+    if (activeJobId) {
+      await cancelCaptionJob(activeJobId);
+    }
+  },
   
   initialize: async () => {
     await get().loadSettings();
+    await listen<DatasetChange[]>('dataset-changed', event => {
+      get().handleDatasetChanges(event.payload);
+    });
+    await listen<JobProgress>(JOB_PROGRESS_EVENT, event => {
+      get().handleJobProgress(event.payload);
+    });
+    await listen<JobStatus>(JOB_STATE_EVENT, event => {
+      get().handleJobState(event.payload);
+    });
+    // Pick up a batch started before the window reloaded, or one restored
+    // (paused) from the job journal after a restart
+    try {
+      const unfinished = (await listCaptionJobs()).filter(
+        job => job.state === 'running' || job.state === 'paused'
+      );
+      if (unfinished.length > 0) {
+        get().handleJobState(unfinished[unfinished.length - 1]);
+      }
+    } catch (error) {
+      console.error('Error listing caption jobs:', error);
+    }
     if (get().currentDirectory) {
       await get().loadImagesFromDirectory();
+      await get().watchCurrentDirectory();
     }
     set({ isInitialized: true });
   },
@@ -217,6 +351,7 @@ export const useAppStore = create<AppState>((set, get) => ({
     set({ currentDirectory: path });
     console.log('Setting current directory:', path);
     await get().loadImagesFromDirectory();
+    await get().watchCurrentDirectory();
     get().saveSettings();
   },
   
@@ -349,23 +484,38 @@ export const useAppStore = create<AppState>((set, get) => ({
     }));
   },
   
-  saveCaption: async (imagePath, caption) => {
+  saveCaption: async (imagePath, caption, source) => {
     try {
       // Update the caption in the state first
       get().updateCaption(imagePath, caption);
       
       // Use the Rust function to save the caption
-      const result = await invoke<number>('save_captions', { 
-        captions: { [imagePath]: caption } 
-      });
+      const [result] = await saveCaptions({ [imagePath]: caption }, undefined, source);
       
-      console.log(`Caption saved: ${result} files updated`);
+      if (result?.error) {
+        console.error(`Caption not saved (${result.outcome}):`, result.error.message);
+      } else {
+        console.log(`Caption ${result?.outcome}:`, result?.caption_path);
+      }
+
+      // The tag dictionaries may have rewritten what was saved
+      if (result?.dictionary) {
+        if (result.outcome === 'written' || result.outcome === 'unchanged') {
+          get().updateCaption(imagePath, result.dictionary.caption);
+        }
+        if (result.dictionary.unknown.length > 0) {
+          console.warn(`Tags not in the vocabulary for ${imagePath}:`, result.dictionary.unknown.join(', '));
+        }
+      }
     } catch (error) {
       console.error('Error saving caption:', error);
       
       // Fallback to the JavaScript implementation if the Rust function fails
       try {
-        const captionPath = imagePath.replace(/\.(png|jpg|jpeg|gif)$/i, '.txt');
+        const { extension, keep_image_extension } = get().captionNaming;
+        const captionPath = keep_image_extension
+          ? `${imagePath}.${extension}`
+          : imagePath.replace(/\.[^./\\]+$/, `.${extension}`);
         await writeTextFile(captionPath, caption);
         console.log('Caption saved (fallback):', captionPath);
       } catch (fallbackError) {
@@ -374,6 +524,29 @@ export const useAppStore = create<AppState>((set, get) => ({
     }
   },
   
+  restoreCaptionVersion: async (versionId) => {
+    const result = await restoreVersion(versionId);
+    if (result.error) {
+      throw result.error;
+    }
+    await get().reloadCaptions([result.path]);
+  },
+  
+  rollbackCaptionBatch: async (batchId, force = false) => {
+    const results = await rollbackBatch(batchId, force);
+    await get().reloadCaptions(results.filter(result => result.outcome === 'written').map(result => result.path));
+    return results;
+  },
+  
+  // Apply tag operations to the selection, or the whole current dataset
+  applyBulkTagEdit: async (operations, paths) => {
+    const { currentDirectory } = get();
+    const target = paths ? { paths } : { directory: currentDirectory ?? undefined };
+    const report = await bulkEditTags(target, operations, false);
+    await get().reloadCaptions(report.results.filter(result => result.outcome === 'written').map(result => result.path));
+    return report;
+  },
+  
   selectDirectory: async () => {
     try {
       console.log('Opening directory selection dialog...');
@@ -411,6 +584,36 @@ export const useAppStore = create<AppState>((set, get) => ({
     get().saveSettings();
   },
   
+  addTagDictionary: async (path, kind) => {
+    await loadTagDictionary(path, kind);
+    const others = get().tagDictionaries.filter(dictionary => dictionary.path !== path);
+    set({ tagDictionaries: [...others, { path, kind }] });
+    get().saveSettings();
+  },
+
+  removeTagDictionary: async (path) => {
+    await removeBackendTagDictionary(path);
+    set({ tagDictionaries: get().tagDictionaries.filter(dictionary => dictionary.path !== path) });
+    get().saveSettings();
+  },
+
+  setDictionarySettings: async (settings) => {
+    await setBackendDictionarySettings(settings);
+    set({ dictionarySettings: settings });
+    get().saveSettings();
+  },
+
+  // Changing the naming scheme changes which caption belongs to each image,
+  // so the dataset is reloaded
+  setCaptionNaming: async (naming) => {
+    await setBackendCaptionNaming(naming);
+    set({ captionNaming: naming });
+    get().saveSettings();
+    if (get().currentDirectory) {
+      await get().loadImagesFromDirectory();
+    }
+  },
+  
   // LM Studio actions
   setLMStudioBaseUrl: (url: string) => {
     set({ lmStudioBaseUrl: url });
@@ -435,9 +638,7 @@ export const useAppStore = create<AppState>((set, get) => ({
   fetchLMStudioModels: async () => {
     const { lmStudioBaseUrl } = get();
     try {
-      const { LMStudioService } = await import('../services/LMStudioService');
-      const service = new LMStudioService(lmStudioBaseUrl);
-      const models = await service.fetchVisionModels();
+      const models = await listModels('lmstudio', { baseUrl: lmStudioBaseUrl });
       set({ lmStudioModels: models, lmStudioAvailable: models.length > 0 });
     } catch (error) {
       set({ lmStudioModels: [], lmStudioAvailable: false });
@@ -453,11 +654,10 @@ export const useAppStore = create<AppState>((set, get) => ({
   checkOllamaConnection: async () => {
     const { ollamaBaseUrl } = get();
     try {
-      const response = await fetch(`${ollamaBaseUrl}/api/tags`);
-      if (response.ok) {
-        set({ ollamaAvailable: true });
-        return true;
-      }
+      // Goes through the backend so remote Ollama hosts aren't blocked by CORS
+      await invoke<string>('proxy_ollama_request', { endpoint: 'version', requestData: '', baseUrl: ollamaBaseUrl });
+      set({ ollamaAvailable: true });
+      return true;
     } catch (error) {
       // ignore
     }
@@ -468,9 +668,7 @@ export const useAppStore = create<AppState>((set, get) => ({
   fetchOllamaModels: async () => {
     const { ollamaBaseUrl } = get();
     try {
-      const { OllamaService } = await import('../services/OllamaService');
-      const service = new OllamaService(ollamaBaseUrl);
-      const models = await service.fetchModels();
+      const models = await listModels('ollama', { baseUrl: ollamaBaseUrl });
       console.log('Fetched Ollama models:', models);
       // ollamaAvailable should be true if the server is up, even if no models
       set({ ollamaModels: models, ollamaAvailable: true });
@@ -495,6 +693,7 @@ export const useAppStore = create<AppState>((set, get) => ({
         suffixText: '',
         leftPanelWidth: 0.2,
         rightPanelWidth: 0.2,
+        includeSubfolders: false,
         lmStudioBaseUrl: 'http://localhost:1234/v1',
         lmStudioAvailable: false,
         lmStudioModels: [],
@@ -520,10 +719,36 @@ export const useAppStore = create<AppState>((set, get) => ({
               suffixText: settings.suffixText || '',
               leftPanelWidth: settings.leftPanelWidth || 0.2,
               rightPanelWidth: settings.rightPanelWidth || 0.2,
+              includeSubfolders: settings.includeSubfolders === true,
               lmStudioBaseUrl: settings.lmStudioBaseUrl || 'http://localhost:1234/v1',
               lmStudioAvailable: false,
               lmStudioModels: [],
             });
+            
+            if (settings.captionNaming) {
+              try {
+                await setBackendCaptionNaming(settings.captionNaming);
+                set({ captionNaming: settings.captionNaming });
+              } catch (namingError) {
+                console.error('Saved caption naming rejected, using the default:', namingError);
+              }
+            }
+
+            if (settings.dictionarySettings) {
+              await setBackendDictionarySettings(settings.dictionarySettings);
+              set({ dictionarySettings: settings.dictionarySettings });
+            }
+            // Dictionary files that can no longer be read are dropped
+            const dictionaries: Array<{ path: string; kind: DictionaryKind }> = [];
+            for (const dictionary of settings.tagDictionaries || []) {
+              try {
+                await loadTagDictionary(dictionary.path, dictionary.kind);
+                dictionaries.push(dictionary);
+              } catch (dictionaryError) {
+                console.error(`Tag dictionary ${dictionary.path} not loaded:`, dictionaryError);
+              }
+            }
+            set({ tagDictionaries: dictionaries });
           }
         }
       } catch (localStorageError) {
@@ -547,6 +772,10 @@ export const useAppStore = create<AppState>((set, get) => ({
         suffixText,
         leftPanelWidth,
         rightPanelWidth,
+        captionNaming,
+        includeSubfolders,
+        tagDictionaries,
+        dictionarySettings,
         lmStudioBaseUrl
       } = get();
       
@@ -562,6 +791,10 @@ export const useAppStore = create<AppState>((set, get) => ({
         suffixText,
         leftPanelWidth,
         rightPanelWidth,
+        captionNaming,
+        includeSubfolders,
+        tagDictionaries,
+        dictionarySettings,
         lmStudioBaseUrl
       };
       
@@ -580,7 +813,7 @@ export const useAppStore = create<AppState>((set, get) => ({
   },
   
   loadImagesFromDirectory: async () => {
-    const { currentDirectory } = get();
+    const { currentDirectory, includeSubfolders } = get();
     if (!currentDirectory) return;
     
     try {
@@ -594,35 +827,53 @@ export const useAppStore = create<AppState>((set, get) => ({
           name: string;
           is_dir: boolean;
           size: number;
+          is_image: boolean;
+          format: string | null;
+          caption: {
+            path: string;
+            exists: boolean;
+            state: 'uncaptioned' | 'captioned' | 'stale';
+          } | null;
         }>;
         image_count: number;
-      }>('get_directory_contents', { path: currentDirectory });
+        format_issues: Array<{
+          path: string;
+          kind: 'mislabeled' | 'unsupported' | 'unrecognized';
+          extension_format: string | null;
+          detected_format: string | null;
+        }>;
+        caption_collisions: Array<{
+          caption_path: string;
+          image_paths: string[];
+        }>;
+      }>('get_directory_contents', { path: currentDirectory, recursive: includeSubfolders });
       
       console.log('Directory contents:', result);
+      if (result.format_issues.length > 0) {
+        console.warn('Files with format problems:', result.format_issues);
+      }
+      if (result.caption_collisions.length > 0) {
+        console.warn('Images sharing a caption file:', result.caption_collisions);
+      }
       
-      // Filter for image files
+      // Filter for image files (detected by the backend from file headers)
       const imageFiles: FileInfo[] = result.files
-        .filter(file => !file.is_dir)
-        .filter(file => {
-          const ext = file.name.split('.').pop()?.toLowerCase();
-          return ext === 'jpg' || ext === 'jpeg' || ext === 'png';
-        })
+        .filter(file => !file.is_dir && file.is_image)
         .map(file => ({
           path: file.path,
           name: file.name
         }));
-      
-      // Load captions for all images
+      // Load captions for all images that have one
       const newCaptions: Caption = {};
+      const withCaptions = result.files
+        .filter(file => file.is_image && file.caption?.exists)
+        .map(file => file.path);
       
-      for (const file of imageFiles) {
-        const captionPath = file.path.replace(/\.(png|jpg|jpeg|gif)$/i, '.txt');
-        try {
-          const caption = await readTextFile(captionPath);
-          console.log('Loading caption for', file.path, ':', caption);
-          newCaptions[file.path] = caption.trim();
-        } catch (error) {
-          console.log('Caption file not found:', captionPath);
+      for (const loaded of await loadCaptions(withCaptions)) {
+        if (loaded.error) {
+          console.error('Could not read caption for', loaded.path, ':', loaded.error.message);
+        } else if (loaded.text !== null) {
+          newCaptions[loaded.path] = loaded.text.trim();
         }
       }
       
@@ -650,5 +901,93 @@ export const useAppStore = create<AppState>((set, get) => ({
     } catch (error) {
       console.error('Error loading images from directory:', error);
     }
+  },
+
+  watchCurrentDirectory: async () => {
+    const { currentDirectory, includeSubfolders } = get();
+    if (!currentDirectory) return;
+    try {
+      await invoke('watch_dataset', { path: currentDirectory, recursive: includeSubfolders });
+    } catch (error) {
+      console.error('Error watching directory:', error);
+    }
+  },
+
+  handleDatasetChanges: async (changes) => {
+    console.log('Dataset changed on disk:', changes);
+
+    // Images appearing or disappearing means the list itself is out of date
+    if (changes.some(change => change.kind.startsWith('image_'))) {
+      await get().loadImagesFromDirectory();
+      return;
+    }
+
+    const changed = changes
+      .map(change => change.image_path)
+      .filter((path): path is string => path !== null);
+    await get().reloadCaptions(changed);
+  },
+
+  // Re-read captions from disk, dropping those whose file is gone
+  reloadCaptions: async (imagePaths) => {
+    if (imagePaths.length === 0) {
+      return;
+    }
+    try {
+      for (const loaded of await loadCaptions(imagePaths)) {
+        if (loaded.error) {
+          console.error('Error reloading caption:', loaded.caption_path, loaded.error.message);
+        } else if (loaded.text !== null) {
+          get().updateCaption(loaded.path, loaded.text.trim());
+        } else {
+          set(state => {
+            const { [loaded.path]: _removed, ...rest } = state.captions;
+            return { captions: rest };
+          });
+        }
+      }
+    } catch (error) {
+      console.error('Error reloading captions:', error);
+    }
+  },
+
+  handleJobProgress: (progress) => {
+    if (progress.job_id !== get().activeJobId) {
+      return;
+    }
+    const { item } = progress;
+    const updates: Partial<AppState> = {
+      processedCount: progress.completed + progress.failed,
+      totalToProcess: progress.total
+    };
+    if (item.state === 'running') {
+      updates.selectedImage = item.path;
+    } else if (item.state === 'done' && item.caption !== null) {
+      updates.captions = { ...get().captions, [item.path]: item.caption };
+      if (item.dictionary && item.dictionary.unknown.length > 0) {
+        console.warn(`Tags not in the vocabulary for ${item.path}:`, item.dictionary.unknown.join(', '));
+      }
+    }
+    set(updates);
+  },
+
+  handleJobState: (status) => {
+    const { activeJobId } = get();
+    if (activeJobId && status.id !== activeJobId) {
+      return;
+    }
+    if (status.state === 'completed' || status.state === 'cancelled') {
+      console.log(`Caption job ${status.id} ${status.state}: ${status.completed} done, ${status.failed} failed`);
+      set({ activeJobId: null, activeJobProvider: null, activeJobPaused: false, isProcessing: false });
+      return;
+    }
+    set({
+      activeJobId: status.id,
+      activeJobProvider: status.provider,
+      activeJobPaused: status.state === 'paused',
+      isProcessing: true,
+      totalToProcess: status.total,
+      processedCount: status.completed + status.failed
+    });
   }
 }));
@@ -0,0 +1,758 @@
+import { invoke, Channel } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+
+export type Provider = 'openai' | 'anthropic' | 'ollama' | 'lmstudio';
+
+export interface GenerationOptions {
+  apiKey?: string;
+  baseUrl?: string;
+  maxTokens?: number;
+  temperature?: number;
+  systemPrompt?: string;
+  timeoutSecs?: number;
+  // Ollama only
+  keepAlive?: string | number;
+  modelOptions?: Record<string, unknown>;
+}
+
+export interface CaptionResult {
+  caption: string;
+  provider: Provider;
+  model: string;
+  transformations: Array<{ kind: string }>;
+  duration_ms: number;
+  // Set when the tag dictionaries rewrote the caption
+  dictionary: DictionaryReport | null;
+  // Set when the project's caption filters apply to the image
+  filtered: FilterReport | null;
+}
+
+export const PROMPTS: Record<string, string> = {
+  'SDXL (Booru Tags)': "Generate a list of tags for this image in the style of Booru image boards and SDXL prompts. Focus on describing the visual elements, subjects, objects, settings, colors, lighting, composition, artistic style, and other relevant attributes. Format the output as a comma-separated list of tags without numbering or bullet points. Be specific and detailed, but keep each tag concise (1-3 words typically). Include tags for the main subject, background elements, colors, lighting, composition, style, medium, and any notable features. Do not include explanatory text or categorization headers - just provide the raw comma-separated tag list. Make sure to include mostly single-word tags, you can use some double-word tags if needed but mostly single word if possible.",
+  'FLUX (Natural Language)': "Describe this image in one concise paragraph, starting immediately with the primary subject (e.g., 'Watch,' 'Landscape,' 'Person'). Focus on key elements, their relationships, and notable details. Be specific and direct, avoiding any introductory phrases like 'The image shows' or 'I can see.' Prioritize the most important aspects and describe them factually. Identify the main subject quickly and accurately, noting its dominant characteristics such as size, color, shape, or position. For multiple elements, describe their spatial relationships. Include relevant details about composition, color schemes, lighting, and textures. Mention any actions, movements, functions, or unique features of objects, and appearances or behaviors of people or animals. Include any visible text, logos, or recognizable symbols. Describe what you see literally, without interpreting the image's style (e.g., don't use terms like 'stylized,' 'illustration,' or mention artistic techniques). Treat every subject as a real object or scene, not as a representation. Use varied and precise vocabulary to create a vivid description while maintaining a neutral tone. Avoid subjective interpretations unless crucial to understanding the image's content.",
+};
+
+/**
+ * Get the prompt text for a prompt style, defaulting to natural language
+ */
+export function promptForStyle(promptStyle: string): string {
+  return PROMPTS[promptStyle] ?? PROMPTS['FLUX (Natural Language)'];
+}
+
+/**
+ * Whether a prompt style produces comma-separated booru tags
+ */
+export function isTagStyle(promptStyle: string): boolean {
+  return promptStyle === 'SDXL (Booru Tags)';
+}
+
+/**
+ * Strip the provider prefix the model picker adds to local model ids
+ */
+export function modelIdFor(provider: Provider, model: string): string {
+  if (provider === 'lmstudio') {
+    return model.replace(/^lmstudio:/, '');
+  }
+  if (provider === 'ollama') {
+    return model.replace(/^ollama:/, '').replace(/:latest$/, '');
+  }
+  return model;
+}
+
+// The backend expects snake_case option names
+function toBackendOptions(options: GenerationOptions) {
+  return {
+    api_key: options.apiKey,
+    base_url: options.baseUrl,
+    max_tokens: options.maxTokens,
+    temperature: options.temperature,
+    system_prompt: options.systemPrompt,
+    timeout_secs: options.timeoutSecs,
+    keep_alive: options.keepAlive,
+    model_options: options.modelOptions,
+  };
+}
+
+export type ErrorCode =
+  | 'not_found'
+  | 'permission_denied'
+  | 'io'
+  | 'invalid_argument'
+  | 'unsupported'
+  | 'conflict'
+  | 'unsupported_format'
+  | 'invalid_image'
+  | 'image_too_small'
+  | 'image_too_large'
+  | 'missing_api_key'
+  | 'auth_failed'
+  | 'rate_limited'
+  | 'provider_rejected'
+  | 'provider_unavailable'
+  | 'timeout'
+  | 'provider_error'
+  | 'cancelled'
+  | 'internal';
+
+/**
+ * Error returned by backend commands
+ */
+export interface AppError {
+  code: ErrorCode;
+  message: string;
+  path: string | null;
+  provider: {
+    provider: Provider;
+    status: number | null;
+    retryable: boolean;
+    retry_after_ms: number | null;
+  } | null;
+}
+
+export function isAppError(error: unknown): error is AppError {
+  return typeof error === 'object' && error !== null && 'code' in error && 'message' in error;
+}
+
+export type SaveOutcome = 'written' | 'unchanged' | 'skipped_missing' | 'failed';
+
+export interface SaveResult {
+  path: string;
+  caption_path: string | null;
+  outcome: SaveOutcome;
+  error: AppError | null;
+  // History entry recorded for the save
+  version: number | null;
+  // What the tag dictionaries changed before the caption was written
+  dictionary: DictionaryReport | null;
+}
+
+// Who or what produced a caption, recorded in the caption history
+export type CaptionSource =
+  | { kind: 'manual' }
+  | { kind: 'model'; provider: Provider; model: string; prompt: string | null }
+  | { kind: 'restore'; version: number }
+  | { kind: 'rollback'; batch_id: string }
+  | { kind: 'bulk_edit'; operations: TagOperation[] };
+
+export interface CaptionVersion {
+  id: number;
+  caption_path: string;
+  image_path: string;
+  // null when the caption file didn't exist before / after the save
+  previous: string | null;
+  text: string | null;
+  source: CaptionSource;
+  batch_id: string | null;
+  saved_at: number;
+}
+
+export interface CaptionBatch {
+  batch_id: string;
+  source: CaptionSource;
+  caption_count: number;
+  started_at: number;
+  finished_at: number;
+}
+
+export interface DiffSegment {
+  kind: 'equal' | 'insert' | 'delete';
+  text: string;
+}
+
+export interface SaveOptions {
+  // Write the caption even if the image is missing right now
+  create_missing?: boolean;
+  newline?: 'keep' | 'lf' | 'crlf';
+  // 'preserve' keeps a BOM only if the file being replaced had one
+  bom?: 'preserve' | 'add' | 'strip';
+  trim_trailing_whitespace?: boolean;
+  sync?: 'none' | 'file' | 'file_and_directory';
+}
+
+export interface LoadedCaption {
+  path: string;
+  caption_path: string | null;
+  exists: boolean;
+  text: string | null;
+  encoding: 'utf8' | 'utf8_bom' | 'utf16_le' | 'utf16_be' | 'latin1' | null;
+  error: AppError | null;
+}
+
+/**
+ * Read the captions for the given images in one call
+ */
+export async function loadCaptions(paths: string[]): Promise<LoadedCaption[]> {
+  return invoke<LoadedCaption[]>('load_captions', { paths });
+}
+
+// How caption files are named after their images
+export interface CaptionNaming {
+  // Extension without the dot, e.g. 'txt', 'caption' or 'tags'
+  extension: string;
+  // Name captions `<name>.<image ext>.<ext>` instead of `<name>.<ext>`
+  keep_image_extension: boolean;
+}
+
+export const DEFAULT_CAPTION_NAMING: CaptionNaming = { extension: 'txt', keep_image_extension: false };
+
+export async function getCaptionNaming(): Promise<CaptionNaming> {
+  return invoke<CaptionNaming>('get_caption_naming');
+}
+
+export async function setCaptionNaming(policy: CaptionNaming): Promise<void> {
+  return invoke('set_caption_naming', { policy });
+}
+
+/**
+ * Save captions keyed by image path, returning what happened to each
+ */
+export async function saveCaptions(
+  captions: Record<string, string>,
+  options?: SaveOptions,
+  source?: CaptionSource,
+  batchId?: string
+): Promise<SaveResult[]> {
+  return invoke<SaveResult[]>('save_captions', { captions, options, source, batchId });
+}
+
+/**
+ * Every recorded save of an image's caption, newest first
+ */
+export async function getCaptionHistory(imagePath: string): Promise<CaptionVersion[]> {
+  return invoke<CaptionVersion[]>('get_caption_history', { imagePath });
+}
+
+/**
+ * What a save changed, or how the caption has changed since it
+ */
+export async function diffCaptionVersion(versionId: number, againstCurrent = false): Promise<DiffSegment[]> {
+  return invoke<DiffSegment[]>('diff_caption_version', { versionId, againstCurrent });
+}
+
+/**
+ * Bring the caption back to the text the given version saved
+ */
+export async function restoreCaptionVersion(versionId: number): Promise<SaveResult> {
+  return invoke<SaveResult>('restore_caption_version', { versionId });
+}
+
+export async function listCaptionBatches(limit?: number): Promise<CaptionBatch[]> {
+  return invoke<CaptionBatch[]>('list_caption_batches', { limit });
+}
+
+/**
+ * Undo every caption a batch job or bulk edit wrote
+ */
+export async function rollbackCaptionBatch(batchId: string, force = false): Promise<SaveResult[]> {
+  return invoke<SaveResult[]>('rollback_caption_batch', { batchId, force });
+}
+
+/**
+ * Human-readable message for anything a command or the UI may throw
+ */
+export function errorMessage(error: unknown): string {
+  if (isAppError(error) || error instanceof Error) {
+    return error.message;
+  }
+  return String(error);
+}
+
+/**
+ * Generate a caption for an image with any provider via the Rust backend.
+ * With `onChunk`, text is streamed as it is generated; pass a `requestId` to
+ * be able to stop the request with `cancelCaption`.
+ */
+export async function generateCaption(
+  imagePath: string,
+  provider: Provider,
+  model: string,
+  prompt: string,
+  options: GenerationOptions = {},
+  onChunk?: (chunk: string) => void,
+  requestId?: string
+): Promise<CaptionResult> {
+  let onDelta: Channel<{ text: string }> | undefined;
+  if (onChunk) {
+    onDelta = new Channel<{ text: string }>();
+    onDelta.onmessage = (delta) => onChunk(delta.text);
+  }
+
+  const result = await invoke<CaptionResult>('generate_caption', {
+    path: imagePath,
+    provider,
+    model: modelIdFor(provider, model),
+    prompt,
+    options: toBackendOptions(options),
+    requestId,
+    onDelta,
+  });
+  if (result.transformations.length > 0) {
+    console.log(`Preprocessed ${imagePath}:`, result.transformations.map(t => t.kind).join(', '));
+  }
+  return result;
+}
+
+/**
+ * Stop an in-flight caption request started with a `requestId`
+ */
+export async function cancelCaption(requestId: string): Promise<boolean> {
+  return invoke<boolean>('cancel_caption', { requestId });
+}
+
+/**
+ * List the models a provider offers
+ */
+export async function listModels(
+  provider: Provider,
+  options: GenerationOptions = {}
+): Promise<Array<{ id: string; name: string }>> {
+  return invoke<Array<{ id: string; name: string }>>('list_models', {
+    provider,
+    options: toBackendOptions(options),
+  });
+}
+
+export interface OllamaModelDetails {
+  name: string;
+  family: string | null;
+  parameter_size: string | null;
+  quantization: string | null;
+  capabilities: string[];
+  vision: boolean;
+  context_length: number | null;
+  num_ctx: number | null;
+}
+
+export interface OllamaPullProgress {
+  model: string;
+  status: string;
+  digest: string | null;
+  total: number | null;
+  completed: number | null;
+  done: boolean;
+}
+
+/**
+ * Look up an installed Ollama model, including whether it can see images
+ */
+export async function showOllamaModel(model: string, baseUrl?: string): Promise<OllamaModelDetails> {
+  return invoke<OllamaModelDetails>('ollama_show_model', { model: modelIdFor('ollama', model), baseUrl });
+}
+
+/**
+ * Download a model into Ollama. Progress arrives through `onProgress` until
+ * the pull finishes or is stopped with `cancelOllamaPull`.
+ */
+export async function pullOllamaModel(
+  model: string,
+  baseUrl?: string,
+  onProgress?: (progress: OllamaPullProgress) => void
+): Promise<void> {
+  const unlisten = onProgress
+    ? await listen<OllamaPullProgress>('ollama-pull-progress', (event) => {
+        if (event.payload.model === model) {
+          onProgress(event.payload);
+        }
+      })
+    : undefined;
+  try {
+    await invoke('ollama_pull_model', { model, baseUrl });
+  } finally {
+    unlisten?.();
+  }
+}
+
+export async function cancelOllamaPull(model: string): Promise<boolean> {
+  return invoke<boolean>('cancel_ollama_pull', { model });
+}
+
+export type JobState = 'running' | 'paused' | 'completed' | 'cancelled';
+export type JobItemState = 'pending' | 'running' | 'done' | 'failed' | 'cancelled';
+
+export interface JobConfig {
+  provider: Provider;
+  model: string;
+  prompt: string;
+  options?: GenerationOptions;
+  prefix?: string;
+  suffix?: string;
+  // Write captions to disk as they are generated (default true)
+  save?: boolean;
+  save_options?: SaveOptions;
+}
+
+export interface JobItem {
+  path: string;
+  state: JobItemState;
+  caption: string | null;
+  error: string | null;
+  duration_ms: number | null;
+  dictionary: DictionaryReport | null;
+  filtered: FilterReport | null;
+}
+
+export interface JobStatus {
+  id: string;
+  provider: Provider;
+  model: string;
+  state: JobState;
+  total: number;
+  completed: number;
+  failed: number;
+  created_at: number;
+  finished_at: number | null;
+  items: JobItem[] | null;
+}
+
+export interface JobProgress {
+  job_id: string;
+  index: number;
+  item: JobItem;
+  completed: number;
+  failed: number;
+  total: number;
+}
+
+export const JOB_PROGRESS_EVENT = 'caption-job-progress';
+export const JOB_STATE_EVENT = 'caption-job-state';
+
+/**
+ * Caption a batch of images in the backend. The job keeps running if the
+ * window reloads; follow it through the job progress and state events.
+ */
+export async function startCaptionJob(paths: string[], config: JobConfig): Promise<JobStatus> {
+  return invoke<JobStatus>('start_caption_job', {
+    paths,
+    config: {
+      provider: config.provider,
+      model: modelIdFor(config.provider, config.model),
+      prompt: config.prompt,
+      options: toBackendOptions(config.options ?? {}),
+      prefix: config.prefix,
+      suffix: config.suffix,
+      save: config.save,
+    },
+  });
+}
+
+export async function pauseCaptionJob(jobId: string): Promise<JobStatus> {
+  return invoke<JobStatus>('pause_caption_job', { jobId });
+}
+
+/**
+ * Resume a paused job. Jobs restored after a restart need the provider's API
+ * key again, since keys are not saved with the job.
+ */
+export async function resumeCaptionJob(jobId: string, apiKey?: string): Promise<JobStatus> {
+  return invoke<JobStatus>('resume_caption_job', { jobId, apiKey });
+}
+
+export async function cancelCaptionJob(jobId: string): Promise<JobStatus> {
+  return invoke<JobStatus>('cancel_caption_job', { jobId });
+}
+
+export async function getCaptionJob(jobId: string): Promise<JobStatus> {
+  return invoke<JobStatus>('get_caption_job', { jobId });
+}
+
+export async function listCaptionJobs(): Promise<JobStatus[]> {
+  return invoke<JobStatus[]>('list_caption_jobs');
+}
+
+export interface ProviderLimits {
+  requests_per_minute: number | null;
+  tokens_per_minute: number | null;
+  max_retries: number;
+  base_delay_ms: number;
+  max_delay_ms: number;
+}
+
+/**
+ * Rate limits and retry policy the backend applies to a provider
+ */
+export async function getProviderLimits(provider: Provider): Promise<ProviderLimits> {
+  return invoke<ProviderLimits>('get_provider_limits', { provider });
+}
+
+export async function setProviderLimits(provider: Provider, limits: ProviderLimits): Promise<void> {
+  await invoke('set_provider_limits', { provider, limits });
+}
+
+/**
+ * Clean up a raw caption. Cloud models answer in sentences, which are joined
+ * with commas; local models only lose a trailing period.
+ */
+export function processCaption(provider: Provider, caption: string): string {
+  let processedCaption = caption.trim();
+
+  if (provider === 'anthropic' || provider === 'openai') {
+    // Replace periods with commas, except for the last one
+    const components = processedCaption.split('.');
+    processedCaption = components.map((component, index) => {
+      const trimmed = component.trim();
+      return index === components.length - 1 ? trimmed : `${trimmed},`;
+    }).join(' ');
+  } else {
+    processedCaption = processedCaption.replace(/\.$/, '');
+  }
+
+  return processedCaption.trim();
+}
+
+export interface Tag {
+  name: string;
+  weight: number | null;
+}
+
+// How tags are normalised and written back out; omitted fields use the
+// backend defaults (keep underscores, no escaping, dedupe, ", " separator)
+export interface TagFormat {
+  underscores?: 'keep' | 'spaces' | 'underscores';
+  escape_parentheses?: boolean;
+  dedupe?: boolean;
+  separator?: string;
+}
+
+export async function parseTags(caption: string): Promise<Tag[]> {
+  return invoke<Tag[]>('parse_tags', { caption });
+}
+
+export async function normalizeTags(caption: string, format?: TagFormat): Promise<string> {
+  return invoke<string>('normalize_tags', { caption, format });
+}
+
+export async function serializeTags(tags: Tag[], format?: TagFormat): Promise<string> {
+  return invoke<string>('serialize_tags', { tags, format });
+}
+
+/**
+ * Join several tag captions (e.g. prefix, tags, suffix) into one deduplicated list
+ */
+export async function joinTags(parts: string[], format?: TagFormat): Promise<string> {
+  return invoke<string>('join_tags', { parts, format });
+}
+
+// Where a tag is inserted or moved to
+export type TagPosition =
+  | { at: 'start' }
+  | { at: 'end' }
+  | { at: 'before'; tag: string }
+  | { at: 'after'; tag: string };
+
+export type TagOperation =
+  | { op: 'replace'; find: string; replace: string }
+  | { op: 'remove'; tags: string[] }
+  | { op: 'add'; tags: string[]; position?: TagPosition }
+  | { op: 'move'; tag: string; position: TagPosition };
+
+// A dataset folder, or a selection of images
+export interface BulkTarget {
+  directory?: string;
+  recursive?: boolean;
+  paths?: string[];
+}
+
+export interface BulkEditReport {
+  dry_run: boolean;
+  batch_id: string | null;
+  scanned: number;
+  changes: Array<{ path: string; caption_path: string | null; before: string; after: string }>;
+  results: SaveResult[];
+  errors: AppError[];
+}
+
+/**
+ * Apply tag operations across a dataset; with dryRun only preview the changes
+ */
+export async function bulkEditTags(
+  target: BulkTarget,
+  operations: TagOperation[],
+  dryRun: boolean,
+  format?: TagFormat,
+  options?: SaveOptions
+): Promise<BulkEditReport> {
+  return invoke<BulkEditReport>('bulk_edit_tags', { target, operations, format, options, dryRun });
+}
+
+/**
+ * Images whose captions have all of the given tags
+ */
+export async function findTags(target: BulkTarget, tags: string[]): Promise<string[]> {
+  return invoke<string[]>('find_tags', { target, tags });
+}
+
+export interface TagFrequency {
+  tag: string;
+  count: number;
+  share: number;
+}
+
+export interface LengthDistribution {
+  min: number;
+  max: number;
+  mean: number;
+  median: number;
+  p95: number;
+  buckets: Array<{ from: number; to: number; count: number }>;
+}
+
+export interface TagStats {
+  root: string;
+  caption_count: number;
+  uncaptioned_count: number;
+  tags: TagFrequency[];
+  folders: Array<{ folder: string; caption_count: number; tags: TagFrequency[] }>;
+  pairs: Array<{ a: string; b: string; count: number }>;
+  singletons: string[];
+  word_lengths: LengthDistribution;
+  token_lengths: LengthDistribution;
+  over_token_limit: number;
+  captions: Array<{ path: string; words: number; tags: number; clip_tokens: number }>;
+  errors: AppError[];
+}
+
+export type StatsExportFormat = 'json' | 'csv';
+export type StatsExportSection = 'tags' | 'pairs' | 'captions';
+
+/**
+ * Tag frequencies, co-occurrence and caption lengths for a dataset folder
+ */
+export async function getTagStats(directory: string, recursive = true, maxPairs?: number): Promise<TagStats> {
+  return invoke<TagStats>('get_tag_stats', { directory, recursive, maxPairs });
+}
+
+/**
+ * Save statistics as JSON, or one section of them as CSV
+ */
+export async function exportTagStats(
+  stats: TagStats,
+  format: StatsExportFormat,
+  path: string,
+  section?: StatsExportSection
+): Promise<void> {
+  return invoke<void>('export_tag_stats', { stats, format, section, path });
+}
+
+export type DictionaryKind = 'aliases' | 'implications' | 'vocabulary';
+export type ImplicationMode = 'add' | 'prune' | 'ignore';
+
+// A loaded Danbooru-style tag dictionary CSV
+export interface DictionarySource {
+  path: string;
+  kind: DictionaryKind;
+  entries: number;
+  skipped: number;
+}
+
+export interface DictionarySettings {
+  apply_on_generate: boolean;
+  apply_on_save: boolean;
+  implications: ImplicationMode;
+  format?: TagFormat;
+}
+
+export const DEFAULT_DICTIONARY_SETTINGS: DictionarySettings = {
+  apply_on_generate: true,
+  apply_on_save: true,
+  implications: 'add'
+};
+
+// What the tag dictionaries did to one caption
+export interface DictionaryReport {
+  caption: string;
+  aliased: Array<{ from: string; to: string }>;
+  // Tags added (or pruned) because of implications
+  implied: string[];
+  // Tags missing from the loaded vocabulary files
+  unknown: string[];
+}
+
+export async function getTagDictionaries(): Promise<DictionarySource[]> {
+  return invoke<DictionarySource[]>('get_tag_dictionaries');
+}
+
+/**
+ * Load (or reload) an alias, implication or vocabulary CSV
+ */
+export async function loadTagDictionary(path: string, kind: DictionaryKind): Promise<DictionarySource[]> {
+  return invoke<DictionarySource[]>('load_tag_dictionary', { path, kind });
+}
+
+export async function removeTagDictionary(path: string): Promise<DictionarySource[]> {
+  return invoke<DictionarySource[]>('remove_tag_dictionary', { path });
+}
+
+export async function getDictionarySettings(): Promise<DictionarySettings> {
+  return invoke<DictionarySettings>('get_dictionary_settings');
+}
+
+export async function setDictionarySettings(settings: DictionarySettings): Promise<void> {
+  return invoke('set_dictionary_settings', { settings });
+}
+
+/**
+ * Preview what the loaded dictionaries would do to a caption
+ */
+export async function checkTags(caption: string): Promise<DictionaryReport> {
+  return invoke<DictionaryReport>('check_tags', { caption });
+}
+
+// A tag name, or with `regex` a case-insensitive regular expression
+export interface FilterRule {
+  pattern: string;
+  regex?: boolean;
+}
+
+// Per-project rules applied to every generated caption
+export interface CaptionFilters {
+  enabled: boolean;
+  blacklist: FilterRule[];
+  // When not empty, only matching tags are kept
+  whitelist: FilterRule[];
+  // Text cut out of the caption, e.g. "the image shows"
+  phrases: FilterRule[];
+}
+
+export interface StrippedItem {
+  text: string;
+  reason: 'blacklisted' | 'not_whitelisted' | 'phrase';
+  rule: string | null;
+}
+
+export interface FilterReport {
+  caption: string;
+  stripped: StrippedItem[];
+}
+
+export interface FilterLogEntry {
+  image_path: string;
+  project: string;
+  stripped: StrippedItem[];
+  at: number;
+}
+
+export async function getCaptionFilters(project: string): Promise<CaptionFilters> {
+  return invoke<CaptionFilters>('get_caption_filters', { project });
+}
+
+export async function setCaptionFilters(project: string, filters: CaptionFilters): Promise<void> {
+  return invoke('set_caption_filters', { project, filters });
+}
+
+/**
+ * Run filters over a caption without saving them
+ */
+export async function testCaptionFilters(filters: CaptionFilters, caption: string): Promise<FilterReport> {
+  return invoke<FilterReport>('test_caption_filters', { filters, caption });
+}
+
+/**
+ * What the filters stripped from recent captions, newest first
+ */
+export async function getFilterLog(limit?: number): Promise<FilterLogEntry[]> {
+  return invoke<FilterLogEntry[]>('get_filter_log', { limit });
+}
+
+export async function clearFilterLog(): Promise<void> {
+  return invoke('clear_filter_log');
+}